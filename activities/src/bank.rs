@@ -0,0 +1,537 @@
+//! Bank accounts.
+//!
+//! Balances aren't stored anywhere: every change to an account is recorded as an event in its
+//! [`AccountHistory`], which is the only record of the account. Its balance, statements and
+//! double-entry [`Ledger`] are all derived from the events, so they can't disagree, and the
+//! ledger shows where every change came from, so money can't appear from nowhere. Withdrawals
+//! are checked against the account's [`WithdrawalPolicy`].
+
+pub mod history;
+pub mod ledger;
+pub mod money;
+pub mod policy;
+pub mod registry;
+pub mod schedule;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use history::{AccountHistory, EventKind, HistoryError, Statement};
+use ledger::{Ledger, LedgerError, EXTERNAL, FEES, INTEREST};
+use money::{Currency, Money, MoneyError};
+use policy::WithdrawalPolicy;
+
+/// The account name used by [`BankAccount::new`].
+pub const DEFAULT_ACCOUNT: &str = "customer";
+
+/// Errors that may occur while adjusting an account. Most are rejected withdrawals.
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq)]
+pub enum AccountError {
+    #[error(transparent)]
+    Money(#[from] MoneyError),
+
+    #[error(transparent)]
+    Ledger(LedgerError),
+
+    #[error("the account is frozen")]
+    Frozen,
+
+    #[error("a change at {at} can't be made before the latest one, at {latest}")]
+    OutOfOrder {
+        at: DateTime<Utc>,
+        latest: DateTime<Utc>,
+    },
+
+    #[error("insufficient funds: the balance is {balance} but {requested} was requested")]
+    InsufficientFunds { balance: Money, requested: Money },
+
+    #[error(
+        "overdraft limit of {limit} exceeded: the balance is {balance} and {requested} was \
+         requested, plus a fee of {fee}"
+    )]
+    OverdraftLimit {
+        balance: Money,
+        limit: Money,
+        fee: Money,
+        requested: Money,
+    },
+
+    #[error(
+        "daily withdrawal limit of {limit} exceeded: {withdrawn_today} was already withdrawn \
+         today and {requested} was requested"
+    )]
+    DailyLimit {
+        limit: Money,
+        withdrawn_today: Money,
+        requested: Money,
+    },
+}
+
+impl From<LedgerError> for AccountError {
+    fn from(e: LedgerError) -> Self {
+        match e {
+            LedgerError::Money(e) => AccountError::Money(e),
+            e => AccountError::Ledger(e),
+        }
+    }
+}
+
+impl From<HistoryError> for AccountError {
+    fn from(e: HistoryError) -> Self {
+        match e {
+            HistoryError::Money(e) => AccountError::Money(e),
+            HistoryError::OutOfOrder { at, latest } => AccountError::OutOfOrder { at, latest },
+        }
+    }
+}
+
+/// Something with a balance that can be adjusted.
+pub trait Account {
+    /// Add `amount` to the balance. A negative amount is taken from it.
+    fn adjust(&mut self, amount: Money) -> Result<(), AccountError>;
+}
+
+/// Deposits and withdrawals for every [`Account`].
+pub trait AccountExt {
+    /// Take `amount` from the account.
+    fn withdraw(&mut self, amount: Money) -> Result<(), AccountError>;
+
+    /// Add `amount` to the account.
+    fn deposit(&mut self, amount: Money) -> Result<(), AccountError>;
+}
+
+impl<A: Account + ?Sized> AccountExt for A {
+    fn withdraw(&mut self, amount: Money) -> Result<(), AccountError> {
+        self.adjust(amount.checked_neg()?)
+    }
+
+    fn deposit(&mut self, amount: Money) -> Result<(), AccountError> {
+        self.adjust(amount)
+    }
+}
+
+/// A single account, recorded as a history of events.
+#[derive(Debug, Clone)]
+pub struct BankAccount {
+    name: String,
+    history: AccountHistory,
+    policy: WithdrawalPolicy,
+}
+
+impl BankAccount {
+    /// Open an account in the currency of `initial_balance`, starting with that balance.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `initial_balance` is [`i64::MIN`] minor units, which can't be posted.
+    pub fn new(initial_balance: Money) -> Self {
+        Self::named(DEFAULT_ACCOUNT, initial_balance)
+    }
+
+    /// Open an account called `name` in the currency of `initial_balance`, starting with that
+    /// balance.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `initial_balance` is [`i64::MIN`] minor units, which can't be posted.
+    pub fn named<N: Into<String>>(name: N, initial_balance: Money) -> Self {
+        Self::open_at(name, initial_balance, Utc::now())
+    }
+
+    /// Open an account called `name` at `now`, starting with `initial_balance`.
+    ///
+    /// The opening balance is posted as it is, so it can be negative: the withdrawal policy
+    /// only applies to later withdrawals.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `initial_balance` is [`i64::MIN`] minor units, which can't be posted.
+    pub fn open_at<N: Into<String>>(name: N, initial_balance: Money, now: DateTime<Utc>) -> Self {
+        assert!(
+            initial_balance.checked_neg().is_ok(),
+            "opening balance out of range"
+        );
+        let mut account = Self {
+            name: name.into(),
+            history: AccountHistory::new(initial_balance.currency()),
+            policy: WithdrawalPolicy::default(),
+        };
+        if !initial_balance.is_zero() {
+            let balance = initial_balance;
+            account
+                .history
+                .append(EventKind::Opened { balance }, now)
+                .expect("an empty history accepts any amount in its currency");
+        }
+        account
+    }
+
+    /// Use `policy` to check withdrawals. If it freezes or unfreezes the account, that's
+    /// recorded as of the latest change to the account, or now if there hasn't been one.
+    pub fn with_policy(mut self, policy: WithdrawalPolicy) -> Self {
+        let at = self
+            .history
+            .events()
+            .last()
+            .map_or_else(Utc::now, |event| event.at);
+        self.set_policy_at(policy, at)
+            .expect("events without amounts can always be appended at the latest time");
+        self
+    }
+
+    pub fn policy(&self) -> &WithdrawalPolicy {
+        &self.policy
+    }
+
+    /// Use `policy` to check withdrawals from now on.
+    pub fn set_policy(&mut self, policy: WithdrawalPolicy) -> Result<(), AccountError> {
+        self.set_policy_at(policy, Utc::now())
+    }
+
+    /// Use `policy` to check withdrawals from `now` on. If it freezes or unfreezes the account,
+    /// that's recorded in the history.
+    pub fn set_policy_at(
+        &mut self,
+        policy: WithdrawalPolicy,
+        now: DateTime<Utc>,
+    ) -> Result<(), AccountError> {
+        self.set_frozen_at(policy.frozen, now)?;
+        self.policy = policy;
+        Ok(())
+    }
+
+    /// Stop any further withdrawals until the account is unfrozen. Deposits are still allowed.
+    pub fn freeze(&mut self) -> Result<(), AccountError> {
+        self.freeze_at(Utc::now())
+    }
+
+    pub fn freeze_at(&mut self, now: DateTime<Utc>) -> Result<(), AccountError> {
+        self.set_frozen_at(true, now)
+    }
+
+    pub fn unfreeze(&mut self) -> Result<(), AccountError> {
+        self.unfreeze_at(Utc::now())
+    }
+
+    pub fn unfreeze_at(&mut self, now: DateTime<Utc>) -> Result<(), AccountError> {
+        self.set_frozen_at(false, now)
+    }
+
+    /// Freeze or unfreeze the account, recording the change if there is one. This is the only
+    /// place the policy's `frozen` flag changes, so it always matches the history.
+    fn set_frozen_at(&mut self, frozen: bool, now: DateTime<Utc>) -> Result<(), AccountError> {
+        if frozen != self.history.state().frozen {
+            let kind = if frozen {
+                EventKind::Frozen
+            } else {
+                EventKind::Unfrozen
+            };
+            self.history.append(kind, now)?;
+        }
+        self.policy.frozen = frozen;
+        Ok(())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.history.currency()
+    }
+
+    /// The current balance, rebuilt from the account's history.
+    pub fn balance(&self) -> Money {
+        self.history.state().balance
+    }
+
+    /// Every transaction posted to the account, derived from its history. Each change moves
+    /// money between the account and [`EXTERNAL`], [`FEES`], [`INTEREST`] or another account.
+    pub fn ledger(&self) -> Ledger {
+        let mut ledger = Ledger::new(self.currency());
+        for event in self.history.events() {
+            let Some((from, to, amount)) = self.transfer(&event.kind) else {
+                continue;
+            };
+            ledger
+                .transfer_at(from, to, amount, event.kind.to_string(), event.at)
+                .expect("events were checked when they were appended");
+        }
+        ledger
+    }
+
+    /// The accounts `kind` moves money from and to, and how much.
+    fn transfer<'a>(&'a self, kind: &'a EventKind) -> Option<(&'a str, &'a str, Money)> {
+        let name = self.name.as_str();
+        Some(match kind {
+            EventKind::Opened { balance } => (EXTERNAL, name, *balance),
+            EventKind::Deposited { amount } => (EXTERNAL, name, *amount),
+            EventKind::Withdrawn { amount } => (name, EXTERNAL, *amount),
+            EventKind::FeeCharged { amount } => (name, FEES, *amount),
+            EventKind::InterestPaid { amount } => (INTEREST, name, *amount),
+            EventKind::TransferredIn { from, amount } => (from.as_str(), name, *amount),
+            EventKind::TransferredOut { to, amount } => (name, to.as_str(), *amount),
+            EventKind::Frozen | EventKind::Unfrozen => return None,
+        })
+    }
+
+    /// Every change made to the account.
+    pub fn history(&self) -> &AccountHistory {
+        &self.history
+    }
+
+    /// The account's statement for a calendar month, or `None` if the month is invalid.
+    pub fn statement(&self, year: i32, month: u32) -> Option<Statement> {
+        self.history.statement(&self.name, year, month)
+    }
+
+    /// The total withdrawn or transferred out on `date`, not counting fees.
+    pub fn withdrawn_on(&self, date: NaiveDate) -> Money {
+        let minor = self
+            .history
+            .events()
+            .iter()
+            .filter(|event| event.at.date_naive() == date)
+            .filter_map(|event| match &event.kind {
+                EventKind::Withdrawn { amount } | EventKind::TransferredOut { amount, .. } => {
+                    Some(amount.minor())
+                }
+                _ => None,
+            })
+            .sum();
+        Money::from_minor(minor, self.currency())
+    }
+
+    /// Add `amount` to the balance at `now`. Withdrawals are checked against the account's
+    /// policy, and any fee it charges is recorded along with the withdrawal.
+    pub fn adjust_at(&mut self, amount: Money, now: DateTime<Utc>) -> Result<(), AccountError> {
+        if amount.is_positive() {
+            self.receive_at(EXTERNAL, amount, now)
+        } else if amount.is_negative() {
+            self.pay_at(EXTERNAL, amount.checked_neg()?, now)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Pay `amount` into the account `to` if the policy allows it, charging any fee. The
+    /// payment and the fee are recorded together, or not at all.
+    pub(crate) fn pay_at(
+        &mut self,
+        to: &str,
+        amount: Money,
+        now: DateTime<Utc>,
+    ) -> Result<(), AccountError> {
+        let withdrawn_today = self.withdrawn_on(now.date_naive());
+        let fee = self.policy.check(self.balance(), withdrawn_today, amount)?;
+        let kind = match to {
+            EXTERNAL => EventKind::Withdrawn { amount },
+            to => EventKind::TransferredOut {
+                to: to.to_owned(),
+                amount,
+            },
+        };
+        let fee = fee.map(|amount| EventKind::FeeCharged { amount });
+        self.history
+            .append_all([kind].into_iter().chain(fee), now)?;
+        Ok(())
+    }
+
+    /// Receive `amount` from the account `from`.
+    pub(crate) fn receive_at(
+        &mut self,
+        from: &str,
+        amount: Money,
+        now: DateTime<Utc>,
+    ) -> Result<(), AccountError> {
+        self.history.append(Self::received(from, amount), now)?;
+        Ok(())
+    }
+
+    /// Check that [`receive_at`](Self::receive_at) would succeed, without receiving anything.
+    pub(crate) fn check_receive_at(
+        &self,
+        from: &str,
+        amount: Money,
+        now: DateTime<Utc>,
+    ) -> Result<(), AccountError> {
+        self.history.preview([Self::received(from, amount)], now)?;
+        Ok(())
+    }
+
+    fn received(from: &str, amount: Money) -> EventKind {
+        match from {
+            EXTERNAL => EventKind::Deposited { amount },
+            INTEREST => EventKind::InterestPaid { amount },
+            from => EventKind::TransferredIn {
+                from: from.to_owned(),
+                amount,
+            },
+        }
+    }
+
+    /// Take `amount` from the account at `now`.
+    pub fn withdraw_at(&mut self, amount: Money, now: DateTime<Utc>) -> Result<(), AccountError> {
+        self.adjust_at(amount.checked_neg()?, now)
+    }
+
+    /// Add `amount` to the account at `now`.
+    pub fn deposit_at(&mut self, amount: Money, now: DateTime<Utc>) -> Result<(), AccountError> {
+        self.adjust_at(amount, now)
+    }
+}
+
+impl Account for BankAccount {
+    fn adjust(&mut self, amount: Money) -> Result<(), AccountError> {
+        self.adjust_at(amount, Utc::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::money::usd;
+
+    #[test]
+    fn adjustments_are_posted_to_the_ledger() {
+        let mut account = BankAccount::new(usd("100"));
+        account.adjust(usd("50")).unwrap();
+        account.withdraw(usd("30")).unwrap();
+        account.deposit(usd("20")).unwrap();
+
+        assert_eq!(account.balance(), usd("140"));
+        assert_eq!(account.ledger().balance(account.name()), usd("140"));
+        assert_eq!(account.ledger().transactions().len(), 4);
+        assert_eq!(account.history().events().len(), 4);
+        assert_eq!(account.ledger().balance(ledger::EXTERNAL), usd("-140"));
+        assert!(account.ledger().trial_balance().is_balanced());
+    }
+
+    #[test]
+    fn rejects_other_currencies() {
+        let mut account = BankAccount::new(usd("100"));
+        let euros = Money::from_major(10, Currency::EUR).unwrap();
+        assert_eq!(
+            account.deposit(euros),
+            Err(AccountError::Money(MoneyError::CurrencyMismatch(
+                Currency::USD,
+                Currency::EUR
+            )))
+        );
+        assert_eq!(account.balance(), usd("100"));
+    }
+
+    #[test]
+    fn accounts_can_open_overdrawn() {
+        let mut account = BankAccount::new(usd("-20"));
+        assert_eq!(account.balance(), usd("-20"));
+        assert_eq!(account.ledger().balance(account.name()), usd("-20"));
+        assert_eq!(
+            account.withdraw(usd("1")),
+            Err(AccountError::InsufficientFunds {
+                balance: usd("-20"),
+                requested: usd("1")
+            })
+        );
+        account.deposit(usd("25")).unwrap();
+        assert_eq!(account.balance(), usd("5"));
+        assert!(BankAccount::new(usd("0")).history().events().is_empty());
+    }
+
+    #[test]
+    fn policies_record_freezing_in_the_history() {
+        let monday = "2024-03-04T10:00:00Z".parse().unwrap();
+        let tuesday = "2024-03-05T10:00:00Z".parse().unwrap();
+        let frozen = WithdrawalPolicy {
+            frozen: true,
+            ..WithdrawalPolicy::default()
+        };
+        let mut account = BankAccount::open_at("alice", usd("100"), monday).with_policy(frozen);
+        assert!(account.history().state().frozen);
+        assert_eq!(account.history().events()[1].at, monday);
+        assert_eq!(
+            account.withdraw_at(usd("1"), monday),
+            Err(AccountError::Frozen)
+        );
+
+        account
+            .set_policy_at(WithdrawalPolicy::default(), tuesday)
+            .unwrap();
+        assert!(!account.policy().frozen);
+        account.withdraw_at(usd("1"), tuesday).unwrap();
+        assert!(matches!(
+            account.freeze_at(monday),
+            Err(AccountError::OutOfOrder { .. })
+        ));
+        assert!(!account.policy().frozen);
+
+        let kinds: Vec<_> = account
+            .history()
+            .events()
+            .iter()
+            .map(|event| event.kind.to_string())
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "account opened",
+                "account frozen",
+                "account unfrozen",
+                "withdrawal"
+            ]
+        );
+    }
+
+    #[test]
+    fn withdrawals_follow_the_policy() {
+        let policy = WithdrawalPolicy::default()
+            .overdraft(usd("50"), usd("5"))
+            .daily_limit(usd("200"));
+        let monday = "2024-03-04T10:00:00Z".parse().unwrap();
+        let tuesday = "2024-03-05T10:00:00Z".parse().unwrap();
+        let mut account = BankAccount::open_at("alice", usd("100"), monday).with_policy(policy);
+
+        account.withdraw_at(usd("120"), monday).unwrap();
+        assert_eq!(account.balance(), usd("-25"));
+        assert_eq!(account.ledger().balance(FEES), usd("5"));
+        assert_eq!(account.withdrawn_on(monday.date_naive()), usd("120"));
+
+        assert!(matches!(
+            account.withdraw_at(usd("21"), monday),
+            Err(AccountError::OverdraftLimit { .. })
+        ));
+        account.deposit_at(usd("500"), monday).unwrap();
+        assert!(matches!(
+            account.withdraw_at(usd("81"), monday),
+            Err(AccountError::DailyLimit { .. })
+        ));
+        account.withdraw_at(usd("81"), tuesday).unwrap();
+
+        account.freeze_at(tuesday).unwrap();
+        assert_eq!(
+            account.withdraw_at(usd("1"), tuesday),
+            Err(AccountError::Frozen)
+        );
+        account.deposit_at(usd("1"), tuesday).unwrap();
+        assert_eq!(account.balance(), usd("395"));
+        assert!(account.ledger().trial_balance().is_balanced());
+
+        let statement = account.statement(2024, 3).unwrap();
+        assert_eq!(statement.opening, usd("0"));
+        assert_eq!(statement.closing, usd("395"));
+        let descriptions: Vec<_> = statement
+            .lines
+            .iter()
+            .map(|line| line.description.as_str())
+            .collect();
+        assert_eq!(
+            descriptions,
+            [
+                "account opened",
+                "withdrawal",
+                "overdraft fee",
+                "deposit",
+                "withdrawal",
+                "deposit"
+            ]
+        );
+    }
+}
@@ -0,0 +1,550 @@
+//! Event-sourced account history and monthly statements.
+//!
+//! Every change to an account is stored as an [`AccountEvent`]. The account's state is never
+//! stored directly: it's rebuilt by folding the events, starting from the latest
+//! [`AccountState`] snapshot so only a bounded number of events needs replaying. Events must be
+//! appended in time order, and an event dated before the latest one is rejected.
+
+use super::money::{Currency, Money, MoneyError};
+use crate::util::csv::csv_field;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How many events are appended between snapshots by default.
+pub const DEFAULT_SNAPSHOT_INTERVAL: usize = 100;
+
+const CHECKED: &str = "events were checked when they were appended";
+
+/// Errors that may occur while appending to a history.
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq)]
+pub enum HistoryError {
+    #[error(transparent)]
+    Money(#[from] MoneyError),
+
+    #[error("an event at {at} can't be appended after the latest one, at {latest}")]
+    OutOfOrder {
+        at: DateTime<Utc>,
+        latest: DateTime<Utc>,
+    },
+}
+
+/// A change to an account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EventKind {
+    /// The account was opened with `balance`, which may be negative.
+    Opened {
+        balance: Money,
+    },
+    Deposited {
+        amount: Money,
+    },
+    Withdrawn {
+        amount: Money,
+    },
+    FeeCharged {
+        amount: Money,
+    },
+    InterestPaid {
+        amount: Money,
+    },
+    TransferredIn {
+        from: String,
+        amount: Money,
+    },
+    TransferredOut {
+        to: String,
+        amount: Money,
+    },
+    Frozen,
+    Unfrozen,
+}
+
+impl EventKind {
+    /// The change to the balance, if any.
+    pub fn change(&self) -> Result<Option<Money>, MoneyError> {
+        Ok(match self {
+            EventKind::Opened { balance } => Some(*balance),
+            EventKind::Deposited { amount }
+            | EventKind::InterestPaid { amount }
+            | EventKind::TransferredIn { amount, .. } => Some(*amount),
+            EventKind::Withdrawn { amount }
+            | EventKind::FeeCharged { amount }
+            | EventKind::TransferredOut { amount, .. } => Some(amount.checked_neg()?),
+            EventKind::Frozen | EventKind::Unfrozen => None,
+        })
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventKind::Opened { .. } => write!(f, "account opened"),
+            EventKind::Deposited { .. } => write!(f, "deposit"),
+            EventKind::Withdrawn { .. } => write!(f, "withdrawal"),
+            EventKind::FeeCharged { .. } => write!(f, "overdraft fee"),
+            EventKind::InterestPaid { .. } => write!(f, "interest"),
+            EventKind::TransferredIn { from, .. } => write!(f, "transfer from {from}"),
+            EventKind::TransferredOut { to, .. } => write!(f, "transfer to {to}"),
+            EventKind::Frozen => write!(f, "account frozen"),
+            EventKind::Unfrozen => write!(f, "account unfrozen"),
+        }
+    }
+}
+
+/// A change to an account along with when it happened.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountEvent {
+    /// Position in the history, starting from 1.
+    pub seq: u64,
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+/// The state of an account after some number of events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountState {
+    /// The number of events folded into this state.
+    pub version: u64,
+    pub balance: Money,
+    pub frozen: bool,
+}
+
+impl AccountState {
+    /// The state of a new account, before any events.
+    pub fn new(currency: Currency) -> Self {
+        Self {
+            version: 0,
+            balance: Money::zero(currency),
+            frozen: false,
+        }
+    }
+
+    /// The state after `event`.
+    pub fn apply(&self, event: &AccountEvent) -> Result<Self, MoneyError> {
+        let mut state = *self;
+        state.version += 1;
+        match event.kind {
+            EventKind::Frozen => state.frozen = true,
+            EventKind::Unfrozen => state.frozen = false,
+            _ => (),
+        }
+        if let Some(change) = event.kind.change()? {
+            state.balance = state.balance.checked_add(change)?;
+        }
+        Ok(state)
+    }
+}
+
+/// Every event of an account, with snapshots of its state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountHistory {
+    currency: Currency,
+    events: Vec<AccountEvent>,
+    /// Snapshots in version order, starting with the empty state.
+    snapshots: Vec<AccountState>,
+    snapshot_interval: usize,
+}
+
+impl AccountHistory {
+    /// Create an empty history for an account in `currency`.
+    pub fn new(currency: Currency) -> Self {
+        Self {
+            currency,
+            events: Vec::new(),
+            snapshots: vec![AccountState::new(currency)],
+            snapshot_interval: DEFAULT_SNAPSHOT_INTERVAL,
+        }
+    }
+
+    /// Take a snapshot every `interval` events.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is 0.
+    pub fn with_snapshot_interval(mut self, interval: usize) -> Self {
+        assert!(interval > 0, "the snapshot interval must be positive");
+        self.snapshot_interval = interval;
+        self
+    }
+
+    /// Rebuild a history from its events, taking fresh snapshots along the way.
+    pub fn replay<I>(currency: Currency, events: I) -> Result<Self, HistoryError>
+    where
+        I: IntoIterator<Item = AccountEvent>,
+    {
+        let mut history = Self::new(currency);
+        for event in events {
+            history.append(event.kind, event.at)?;
+        }
+        Ok(history)
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    /// Every event, oldest first.
+    pub fn events(&self) -> &[AccountEvent] {
+        &self.events
+    }
+
+    pub fn snapshots(&self) -> &[AccountState] {
+        &self.snapshots
+    }
+
+    /// Record a change made at `at`, returning the new state. Changes which would leave the
+    /// account in an invalid state, such as an amount in another currency, are rejected, as are
+    /// changes made before the latest one.
+    pub fn append(
+        &mut self,
+        kind: EventKind,
+        at: DateTime<Utc>,
+    ) -> Result<AccountState, HistoryError> {
+        self.append_all([kind], at)
+    }
+
+    /// Record several changes made together at `at`, returning the new state. Either every
+    /// change is recorded or, if one is rejected, none are.
+    pub fn append_all<I>(
+        &mut self,
+        kinds: I,
+        at: DateTime<Utc>,
+    ) -> Result<AccountState, HistoryError>
+    where
+        I: IntoIterator<Item = EventKind>,
+    {
+        let events = self.prepare(kinds, at)?;
+        let state = events
+            .last()
+            .map_or_else(|| self.state(), |(_, state)| *state);
+        for (event, state) in events {
+            self.events.push(event);
+            if self.events.len().is_multiple_of(self.snapshot_interval) {
+                self.snapshots.push(state);
+            }
+        }
+        Ok(state)
+    }
+
+    /// The state the account would be in if `kinds` were appended at `at`, without appending
+    /// them.
+    pub fn preview<I>(&self, kinds: I, at: DateTime<Utc>) -> Result<AccountState, HistoryError>
+    where
+        I: IntoIterator<Item = EventKind>,
+    {
+        let events = self.prepare(kinds, at)?;
+        Ok(events
+            .last()
+            .map_or_else(|| self.state(), |(_, state)| *state))
+    }
+
+    /// Check `kinds` as if they were appended at `at`, returning each event with the state after
+    /// it.
+    fn prepare<I>(
+        &self,
+        kinds: I,
+        at: DateTime<Utc>,
+    ) -> Result<Vec<(AccountEvent, AccountState)>, HistoryError>
+    where
+        I: IntoIterator<Item = EventKind>,
+    {
+        if let Some(latest) = self.events.last().map(|event| event.at) {
+            if at < latest {
+                return Err(HistoryError::OutOfOrder { at, latest });
+            }
+        }
+        let mut state = self.state();
+        let mut events = Vec::new();
+        for kind in kinds {
+            let event = AccountEvent {
+                seq: state.version + 1,
+                at,
+                kind,
+            };
+            state = state.apply(&event)?;
+            events.push((event, state));
+        }
+        Ok(events)
+    }
+
+    /// The current state.
+    pub fn state(&self) -> AccountState {
+        self.state_after(self.events.len())
+    }
+
+    /// The state before anything happened at or after `time`.
+    pub fn state_before(&self, time: DateTime<Utc>) -> AccountState {
+        let version = self.events.partition_point(|event| event.at < time);
+        self.state_after(version)
+    }
+
+    /// The state after the first `version` events, folded from the closest snapshot.
+    fn state_after(&self, version: usize) -> AccountState {
+        let index = self
+            .snapshots
+            .partition_point(|snapshot| snapshot.version as usize <= version);
+        let snapshot = self.snapshots[index - 1];
+        self.events[snapshot.version as usize..version]
+            .iter()
+            .try_fold(snapshot, |state, event| state.apply(event))
+            .expect(CHECKED)
+    }
+
+    /// The statement of account `name` for a calendar month (UTC).
+    pub fn statement(&self, name: &str, year: i32, month: u32) -> Option<Statement> {
+        let start = NaiveDate::from_ymd_opt(year, month, 1)?;
+        let end = start.checked_add_months(chrono::Months::new(1))?;
+        let midnight = |date: NaiveDate| date.and_time(NaiveTime::MIN).and_utc();
+        let (start, end) = (midnight(start), midnight(end));
+
+        let opening = self.state_before(start);
+        let mut balance = opening.balance;
+        let mut lines = Vec::new();
+        for event in self
+            .events
+            .iter()
+            .filter(|event| start <= event.at && event.at < end)
+        {
+            let Some(amount) = event.kind.change().expect(CHECKED) else {
+                continue;
+            };
+            balance = balance.checked_add(amount).expect(CHECKED);
+            lines.push(StatementLine {
+                date: event.at.date_naive(),
+                description: event.kind.to_string(),
+                amount,
+                balance,
+            });
+        }
+
+        Some(Statement {
+            account: name.to_owned(),
+            year,
+            month,
+            opening: opening.balance,
+            lines,
+            closing: balance,
+        })
+    }
+}
+
+/// One transaction on a statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatementLine {
+    pub date: NaiveDate,
+    pub description: String,
+    pub amount: Money,
+    /// The balance after this transaction.
+    pub balance: Money,
+}
+
+/// An account's transactions over a calendar month.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub account: String,
+    pub year: i32,
+    pub month: u32,
+    pub opening: Money,
+    pub lines: Vec<StatementLine>,
+    pub closing: Money,
+}
+
+impl Statement {
+    /// Encode the statement as CSV with a `date,description,amount,balance` header. The
+    /// opening and closing balances are the first and last records.
+    pub fn to_csv(&self) -> String {
+        let first = NaiveDate::from_ymd_opt(self.year, self.month, 1).expect("valid month");
+        let last = first
+            .checked_add_months(chrono::Months::new(1))
+            .and_then(|next| next.pred_opt())
+            .expect("valid month");
+        let record = |date: NaiveDate, description: &str, amount: &str, balance: Money| {
+            format!(
+                "{date},{},{amount},{}\n",
+                csv_field(description),
+                balance.to_decimal()
+            )
+        };
+
+        let mut csv = String::from("date,description,amount,balance\n");
+        csv.push_str(&record(first, "opening balance", "", self.opening));
+        for line in &self.lines {
+            let amount = line.amount.to_decimal();
+            csv.push_str(&record(line.date, &line.description, &amount, line.balance));
+        }
+        csv.push_str(&record(last, "closing balance", "", self.closing));
+        csv
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let month = u8::try_from(self.month)
+            .ok()
+            .and_then(|month| chrono::Month::try_from(month).ok())
+            .map_or("?", |month| month.name());
+        writeln!(
+            f,
+            "Statement for {}, {month} {} ({})",
+            self.account,
+            self.year,
+            self.opening.currency()
+        )?;
+
+        let mut row = |date: &str, description: &str, amount: &str, balance: &str| {
+            writeln!(
+                f,
+                "{date:<10}  {description:<28} {amount:>12} {balance:>12}"
+            )
+        };
+        row("date", "", "amount", "balance")?;
+        row("", "opening balance", "", &self.opening.to_string())?;
+        for line in &self.lines {
+            row(
+                &line.date.to_string(),
+                &line.description,
+                &line.amount.to_string(),
+                &line.balance.to_string(),
+            )?;
+        }
+        row("", "closing balance", "", &self.closing.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::money::usd;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn state_is_folded_from_snapshots() {
+        let mut history = AccountHistory::new(Currency::USD).with_snapshot_interval(10);
+        for day in 1..=25 {
+            let time = at(&format!("2024-01-{day:02}T12:00:00Z"));
+            history
+                .append(EventKind::Deposited { amount: usd("10") }, time)
+                .unwrap();
+        }
+        history
+            .append(EventKind::Frozen, at("2024-01-26T00:00:00Z"))
+            .unwrap();
+
+        assert_eq!(history.snapshots().len(), 3);
+        let state = history.state();
+        assert_eq!(state.version, 26);
+        assert_eq!(state.balance, usd("250"));
+        assert!(state.frozen);
+        assert_eq!(
+            history.state_before(at("2024-01-16T00:00:00Z")).balance,
+            usd("150")
+        );
+
+        let replayed = AccountHistory::replay(Currency::USD, history.events().to_vec()).unwrap();
+        assert_eq!(replayed.state(), state);
+
+        let euros = Money::from_major(1, Currency::EUR).unwrap();
+        let deposit = EventKind::Deposited { amount: euros };
+        assert!(history.append(deposit, at("2024-01-27T00:00:00Z")).is_err());
+        assert_eq!(history.events().len(), 26);
+    }
+
+    #[test]
+    fn rejects_events_out_of_time_order() {
+        let mut history = AccountHistory::new(Currency::USD);
+        let deposit = || EventKind::Deposited { amount: usd("10") };
+        history
+            .append(deposit(), at("2024-01-02T00:00:00Z"))
+            .unwrap();
+        history
+            .append(deposit(), at("2024-01-02T00:00:00Z"))
+            .unwrap();
+
+        assert_eq!(
+            history.append(deposit(), at("2024-01-01T00:00:00Z")),
+            Err(HistoryError::OutOfOrder {
+                at: at("2024-01-01T00:00:00Z"),
+                latest: at("2024-01-02T00:00:00Z"),
+            })
+        );
+        let euros = EventKind::Deposited {
+            amount: Money::from_major(1, Currency::EUR).unwrap(),
+        };
+        let later = at("2024-01-03T00:00:00Z");
+        assert!(history.preview([deposit()], later).is_ok());
+        assert!(history.append_all([deposit(), euros], later).is_err());
+        assert_eq!(history.events().len(), 2);
+        assert_eq!(history.state().balance, usd("20"));
+    }
+
+    #[test]
+    fn events_serialize_as_tagged_json() {
+        let event = AccountEvent {
+            seq: 1,
+            at: at("2024-03-01T00:00:00Z"),
+            kind: EventKind::TransferredOut {
+                to: "bob".into(),
+                amount: usd("12.50"),
+            },
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.contains(r#""kind":"transferred_out""#), "{json}");
+        assert_eq!(serde_json::from_str::<AccountEvent>(&json).unwrap(), event);
+    }
+
+    #[test]
+    fn monthly_statements() {
+        let mut history = AccountHistory::new(Currency::USD);
+        let events = [
+            (
+                "2024-02-20T09:00:00Z",
+                EventKind::Deposited { amount: usd("100") },
+            ),
+            (
+                "2024-03-01T00:00:00Z",
+                EventKind::TransferredOut {
+                    to: "landlord".into(),
+                    amount: usd("80"),
+                },
+            ),
+            ("2024-03-05T10:00:00Z", EventKind::Frozen),
+            (
+                "2024-03-31T23:59:59Z",
+                EventKind::InterestPaid {
+                    amount: usd("0.05"),
+                },
+            ),
+            (
+                "2024-04-01T00:00:00Z",
+                EventKind::Withdrawn { amount: usd("5") },
+            ),
+        ];
+        for (time, kind) in events {
+            history.append(kind, at(time)).unwrap();
+        }
+
+        let statement = history.statement("alice", 2024, 3).unwrap();
+        assert_eq!(statement.opening, usd("100"));
+        assert_eq!(statement.closing, usd("20.05"));
+        assert_eq!(statement.lines.len(), 2);
+
+        assert_eq!(
+            statement.to_csv(),
+            "date,description,amount,balance\n\
+             2024-03-01,opening balance,,100.00\n\
+             2024-03-01,transfer to landlord,-80.00,20.00\n\
+             2024-03-31,interest,0.05,20.05\n\
+             2024-03-31,closing balance,,20.05\n"
+        );
+        let text = statement.to_string();
+        assert!(text.starts_with("Statement for alice, March 2024 (USD)"));
+        assert!(text.contains("-$80.00"));
+        assert!(history.statement("alice", 2024, 13).is_none());
+    }
+}
@@ -0,0 +1,353 @@
+//! A double-entry ledger.
+//!
+//! Every transaction moves money between named accounts: the accounts it's taken from are
+//! credited and the accounts it's paid into are debited by the same total. Money entering or
+//! leaving the bank is taken from or paid into [`EXTERNAL`], so the balances of all accounts
+//! always sum to zero. Every ledger keeps its books in a single currency.
+
+use super::money::{Currency, Money, MoneyError};
+use super::AccountError;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The account representing the world outside the bank.
+pub const EXTERNAL: &str = "external";
+
+/// The account collecting fees charged by the bank.
+pub const FEES: &str = "fees";
+
+/// The account interest is paid from.
+pub const INTEREST: &str = "interest";
+
+/// Errors that may occur while posting a transaction.
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq)]
+pub enum LedgerError {
+    #[error("a transaction needs at least two postings")]
+    TooFewPostings,
+
+    #[error("debits and credits differ by {0}")]
+    Unbalanced(Money),
+
+    #[error(transparent)]
+    Money(#[from] MoneyError),
+}
+
+/// One side of a transaction. Debits are positive and credits are negative.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Posting {
+    pub account: String,
+    pub amount: Money,
+}
+
+impl Posting {
+    /// Pay `amount` into `account`.
+    pub fn debit<A: Into<String>>(account: A, amount: Money) -> Self {
+        Self {
+            account: account.into(),
+            amount,
+        }
+    }
+
+    /// Take `amount` from `account`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `amount` is [`i64::MIN`] minor units, which can't be negated.
+    pub fn credit<A: Into<String>>(account: A, amount: Money) -> Self {
+        Self {
+            account: account.into(),
+            amount: amount.checked_neg().expect("credit amount out of range"),
+        }
+    }
+}
+
+/// A balanced set of postings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    /// Position in the ledger, starting from 1.
+    pub id: u64,
+    pub at: DateTime<Utc>,
+    pub description: String,
+    pub postings: Vec<Posting>,
+}
+
+/// An append-only list of balanced transactions. Account balances are derived from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ledger {
+    currency: Currency,
+    transactions: Vec<Transaction>,
+}
+
+impl Ledger {
+    /// Create an empty ledger which keeps its books in `currency`.
+    pub fn new(currency: Currency) -> Self {
+        Self {
+            currency,
+            transactions: Vec::new(),
+        }
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    /// Post a transaction, returning its id. Its debits and credits must cancel out.
+    pub fn post<D: Into<String>>(
+        &mut self,
+        description: D,
+        postings: Vec<Posting>,
+    ) -> Result<u64, LedgerError> {
+        self.post_at(description, postings, Utc::now())
+    }
+
+    /// Post a transaction made at `at`, returning its id.
+    pub fn post_at<D: Into<String>>(
+        &mut self,
+        description: D,
+        postings: Vec<Posting>,
+        at: DateTime<Utc>,
+    ) -> Result<u64, LedgerError> {
+        if postings.len() < 2 {
+            return Err(LedgerError::TooFewPostings);
+        }
+        let mut difference = Money::zero(self.currency);
+        for posting in &postings {
+            difference = difference.checked_add(posting.amount)?;
+        }
+        if !difference.is_zero() {
+            return Err(LedgerError::Unbalanced(difference));
+        }
+
+        let id = self.transactions.len() as u64 + 1;
+        self.transactions.push(Transaction {
+            id,
+            at,
+            description: description.into(),
+            postings,
+        });
+        Ok(id)
+    }
+
+    /// Move `amount` from one account to another, returning the transaction id.
+    pub fn transfer<D: Into<String>>(
+        &mut self,
+        from: &str,
+        to: &str,
+        amount: Money,
+        description: D,
+    ) -> Result<u64, LedgerError> {
+        self.transfer_at(from, to, amount, description, Utc::now())
+    }
+
+    /// Move `amount` from one account to another at `at`, returning the transaction id.
+    pub fn transfer_at<D: Into<String>>(
+        &mut self,
+        from: &str,
+        to: &str,
+        amount: Money,
+        description: D,
+        at: DateTime<Utc>,
+    ) -> Result<u64, LedgerError> {
+        let postings = vec![Posting::credit(from, amount), Posting::debit(to, amount)];
+        self.post_at(description, postings, at)
+    }
+
+    /// Every transaction, oldest first.
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    /// The balance of an account.
+    pub fn balance(&self, account: &str) -> Money {
+        let minor = self
+            .postings()
+            .filter(|posting| posting.account == account)
+            .map(|posting| posting.amount.minor())
+            .sum();
+        Money::from_minor(minor, self.currency)
+    }
+
+    /// The balance of every account which has been posted to.
+    pub fn trial_balance(&self) -> TrialBalance {
+        let mut balances = BTreeMap::new();
+        for posting in self.postings() {
+            *balances.entry(posting.account.clone()).or_default() += posting.amount.minor() as i128;
+        }
+        TrialBalance {
+            currency: self.currency,
+            balances,
+        }
+    }
+
+    /// An [`Account`](super::Account) which posts its adjustments to this ledger.
+    pub fn account<A: Into<String>>(&mut self, name: A) -> LedgerAccount<'_> {
+        LedgerAccount {
+            ledger: self,
+            name: name.into(),
+        }
+    }
+
+    fn postings(&self) -> impl Iterator<Item = &Posting> {
+        self.transactions
+            .iter()
+            .flat_map(|transaction| &transaction.postings)
+    }
+}
+
+/// A named account within a [`Ledger`]. Deposits come from, and withdrawals go to,
+/// [`EXTERNAL`].
+#[derive(Debug)]
+pub struct LedgerAccount<'a> {
+    ledger: &'a mut Ledger,
+    name: String,
+}
+
+impl LedgerAccount<'_> {
+    pub fn balance(&self) -> Money {
+        self.ledger.balance(&self.name)
+    }
+}
+
+impl super::Account for LedgerAccount<'_> {
+    fn adjust(&mut self, amount: Money) -> Result<(), AccountError> {
+        let result = if amount.is_negative() {
+            let amount = amount.checked_neg()?;
+            self.ledger
+                .transfer(&self.name, EXTERNAL, amount, "withdrawal")
+        } else if amount.is_positive() {
+            self.ledger
+                .transfer(EXTERNAL, &self.name, amount, "deposit")
+        } else {
+            return Ok(());
+        };
+        result?;
+        Ok(())
+    }
+}
+
+/// The balance of every account in a ledger, used to check the books.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrialBalance {
+    pub currency: Currency,
+    /// The balance of each account in minor units, which may not fit in a [`Money`].
+    pub balances: BTreeMap<String, i128>,
+}
+
+impl TrialBalance {
+    /// The total of every debit balance, in minor units.
+    pub fn debits(&self) -> i128 {
+        self.balances.values().filter(|b| **b > 0).sum()
+    }
+
+    /// The total of every credit balance, in minor units.
+    pub fn credits(&self) -> i128 {
+        -self.balances.values().filter(|b| **b < 0).sum::<i128>()
+    }
+
+    /// Returns `true` if debits and credits cancel out, which is the case for any ledger whose
+    /// transactions were all balanced.
+    pub fn is_balanced(&self) -> bool {
+        self.debits() == self.credits()
+    }
+}
+
+impl fmt::Display for TrialBalance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = |minor: i128| match i64::try_from(minor) {
+            Ok(minor) => Money::from_minor(minor, self.currency).to_string(),
+            Err(_) => format!("{minor} minor units"),
+        };
+        writeln!(f, "{:<16} {:>12} {:>12}", "account", "debit", "credit")?;
+        for (account, &balance) in &self.balances {
+            let (debit, credit) = match balance {
+                b if b > 0 => (format(b), String::new()),
+                b if b < 0 => (String::new(), format(-b)),
+                _ => (String::new(), String::new()),
+            };
+            writeln!(f, "{account:<16} {debit:>12} {credit:>12}")?;
+        }
+        writeln!(
+            f,
+            "{:<16} {:>12} {:>12}",
+            "total",
+            format(self.debits()),
+            format(self.credits())
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::money::usd;
+    use crate::bank::AccountExt;
+
+    #[test]
+    fn rejects_unbalanced_transactions() {
+        let mut ledger = Ledger::new(Currency::USD);
+        assert_eq!(
+            ledger.post(
+                "free money",
+                vec![
+                    Posting::debit("alice", usd("5.00")),
+                    Posting::credit(EXTERNAL, usd("4.00"))
+                ]
+            ),
+            Err(LedgerError::Unbalanced(usd("1.00")))
+        );
+        assert_eq!(
+            ledger.post("nothing", vec![Posting::debit("alice", usd("0"))]),
+            Err(LedgerError::TooFewPostings)
+        );
+        let euros = Money::from_major(5, Currency::EUR).unwrap();
+        assert!(matches!(
+            ledger.transfer(EXTERNAL, "alice", euros, "wrong currency"),
+            Err(LedgerError::Money(MoneyError::CurrencyMismatch(..)))
+        ));
+        assert!(ledger.transactions().is_empty());
+        assert!(ledger.balance("alice").is_zero());
+    }
+
+    #[test]
+    fn balances_are_derived_from_postings() {
+        let mut ledger = Ledger::new(Currency::USD);
+        ledger.account("alice").deposit(usd("100")).unwrap();
+        ledger.account("alice").withdraw(usd("30.25")).unwrap();
+        ledger
+            .transfer("alice", "bob", usd("20.00"), "rent")
+            .unwrap();
+        ledger
+            .post(
+                "split bill",
+                vec![
+                    Posting::credit("bob", usd("9.00")),
+                    Posting::debit("alice", usd("4.50")),
+                    Posting::debit("carol", usd("4.50")),
+                ],
+            )
+            .unwrap();
+
+        assert_eq!(ledger.balance("alice"), usd("54.25"));
+        assert_eq!(ledger.balance("bob"), usd("11.00"));
+        assert_eq!(ledger.balance("carol"), usd("4.50"));
+        assert_eq!(ledger.balance(EXTERNAL), usd("-69.75"));
+        assert_eq!(ledger.transactions().len(), 4);
+    }
+
+    #[test]
+    fn trial_balance_sums_to_zero() {
+        let mut ledger = Ledger::new(Currency::USD);
+        ledger.account("alice").deposit(usd("12.50")).unwrap();
+        ledger.account("bob").deposit(usd("7.50")).unwrap();
+        ledger
+            .transfer("bob", "alice", usd("2.50"), "lunch")
+            .unwrap();
+
+        let trial = ledger.trial_balance();
+        assert!(trial.is_balanced());
+        assert_eq!(trial.debits(), 2000);
+        assert_eq!(trial.balances.values().sum::<i128>(), 0);
+        assert!(trial.to_string().contains("$20.00"));
+    }
+}
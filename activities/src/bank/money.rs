@@ -0,0 +1,519 @@
+//! Amounts of money.
+//!
+//! [`Money`] stores a whole number of minor units (cents for dollars) along with an ISO 4217
+//! [`Currency`], so adding and subtracting amounts is exact. Anything which can't be exact, such
+//! as applying an interest rate or converting between currencies, takes an explicit
+//! [`RoundingMode`]. Combining amounts in different currencies is an error.
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Errors that may occur while working with money.
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq)]
+pub enum MoneyError {
+    #[error("{0:?} isn't an ISO currency code")]
+    InvalidCurrency(String),
+
+    #[error("can't combine {0} with {1}")]
+    CurrencyMismatch(Currency, Currency),
+
+    #[error("{0:?} isn't a valid amount of {1}")]
+    InvalidAmount(String, Currency),
+
+    #[error("amount out of range")]
+    Overflow,
+
+    #[error("can't divide by zero")]
+    DivideByZero,
+
+    #[error("no exchange rate from {from} to {to}")]
+    NoRate { from: Currency, to: Currency },
+}
+
+/// An ISO 4217 currency code, such as `USD`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub const USD: Currency = Currency(*b"USD");
+    pub const EUR: Currency = Currency(*b"EUR");
+    pub const GBP: Currency = Currency(*b"GBP");
+    pub const JPY: Currency = Currency(*b"JPY");
+
+    /// Parse a three letter currency code.
+    pub fn new(code: &str) -> Result<Self, MoneyError> {
+        match code.as_bytes() {
+            &[a, b, c] if code.bytes().all(|b| b.is_ascii_uppercase()) => Ok(Self([a, b, c])),
+            _ => Err(MoneyError::InvalidCurrency(code.to_owned())),
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("currency codes are ASCII")
+    }
+
+    /// The number of decimal places used by the currency's minor unit.
+    pub fn exponent(self) -> u32 {
+        match &self.0 {
+            b"JPY" | b"KRW" | b"ISK" => 0,
+            b"BHD" | b"JOD" | b"KWD" | b"OMR" | b"TND" => 3,
+            _ => 2,
+        }
+    }
+
+    fn symbol(self) -> Option<&'static str> {
+        match &self.0 {
+            b"USD" => Some("$"),
+            b"EUR" => Some("€"),
+            b"GBP" => Some("£"),
+            b"JPY" => Some("¥"),
+            _ => None,
+        }
+    }
+
+    fn scale(self) -> i64 {
+        10_i64.pow(self.exponent())
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl fmt::Debug for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl FromStr for Currency {
+    type Err = MoneyError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        Self::new(code)
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = MoneyError;
+
+    fn try_from(code: String) -> Result<Self, Self::Error> {
+        Self::new(&code)
+    }
+}
+
+impl From<Currency> for String {
+    fn from(currency: Currency) -> Self {
+        currency.code().to_owned()
+    }
+}
+
+/// How to round a result which falls between two minor units.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// Drop the remainder.
+    TowardZero,
+    /// Round any remainder up in magnitude.
+    AwayFromZero,
+    /// Round toward negative infinity.
+    Floor,
+    /// Round toward positive infinity.
+    Ceiling,
+    /// Round to the nearest unit, with halves rounded away from zero.
+    HalfUp,
+    /// Round to the nearest unit, with halves rounded to the even neighbor. Unlike
+    /// [`HalfUp`](RoundingMode::HalfUp), this doesn't drift upward over many roundings.
+    #[default]
+    HalfEven,
+}
+
+impl RoundingMode {
+    /// Divide `n` by `d`, which must be positive.
+    fn divide(self, n: i128, d: i128) -> i128 {
+        let (quotient, remainder) = (n / d, n % d);
+        if remainder == 0 {
+            return quotient;
+        }
+        let away = quotient + n.signum();
+        let half = (2 * remainder.abs()).cmp(&d);
+        match self {
+            RoundingMode::TowardZero => quotient,
+            RoundingMode::AwayFromZero => away,
+            RoundingMode::Floor => quotient.min(away),
+            RoundingMode::Ceiling => quotient.max(away),
+            RoundingMode::HalfUp if half == Ordering::Less => quotient,
+            RoundingMode::HalfUp => away,
+            RoundingMode::HalfEven => match half {
+                Ordering::Less => quotient,
+                Ordering::Equal if quotient % 2 == 0 => quotient,
+                _ => away,
+            },
+        }
+    }
+}
+
+/// An amount of money in a single currency.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Money {
+    minor: i64,
+    currency: Currency,
+}
+
+impl Money {
+    /// An amount in minor units, such as cents.
+    pub const fn from_minor(minor: i64, currency: Currency) -> Self {
+        Self { minor, currency }
+    }
+
+    /// An amount in major units, such as dollars.
+    pub fn from_major(major: i64, currency: Currency) -> Result<Self, MoneyError> {
+        let minor = major
+            .checked_mul(currency.scale())
+            .ok_or(MoneyError::Overflow)?;
+        Ok(Self::from_minor(minor, currency))
+    }
+
+    pub const fn zero(currency: Currency) -> Self {
+        Self::from_minor(0, currency)
+    }
+
+    /// Parse a decimal amount such as `-12.5`. It can't be more precise than the currency's
+    /// minor unit.
+    pub fn parse(amount: &str, currency: Currency) -> Result<Self, MoneyError> {
+        let invalid = || MoneyError::InvalidAmount(amount.to_owned(), currency);
+        let (digits, places) = parse_decimal(amount).ok_or_else(invalid)?;
+        let exponent = currency.exponent();
+        if places > exponent {
+            return Err(invalid());
+        }
+        let minor = digits
+            .checked_mul(10_i64.pow(exponent - places))
+            .ok_or(MoneyError::Overflow)?;
+        Ok(Self::from_minor(minor, currency))
+    }
+
+    /// The amount in minor units.
+    pub fn minor(&self) -> i64 {
+        self.minor
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.minor == 0
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.minor > 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.minor < 0
+    }
+
+    fn same_currency(&self, other: &Money) -> Result<(), MoneyError> {
+        if self.currency == other.currency {
+            Ok(())
+        } else {
+            Err(MoneyError::CurrencyMismatch(self.currency, other.currency))
+        }
+    }
+
+    fn with_minor(&self, minor: Option<i64>) -> Result<Money, MoneyError> {
+        let minor = minor.ok_or(MoneyError::Overflow)?;
+        Ok(Self::from_minor(minor, self.currency))
+    }
+
+    /// The amount as a plain decimal number without the currency, such as `-12.50`.
+    pub fn to_decimal(&self) -> String {
+        let sign = if self.minor < 0 { "-" } else { "" };
+        let scale = self.currency.scale().unsigned_abs();
+        let (major, minor) = (
+            self.minor.unsigned_abs() / scale,
+            self.minor.unsigned_abs() % scale,
+        );
+        match self.currency.exponent() {
+            0 => format!("{sign}{major}"),
+            places => format!("{sign}{major}.{minor:0places$}", places = places as usize),
+        }
+    }
+
+    pub fn checked_add(&self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        self.with_minor(self.minor.checked_add(other.minor))
+    }
+
+    pub fn checked_sub(&self, other: Money) -> Result<Money, MoneyError> {
+        self.same_currency(&other)?;
+        self.with_minor(self.minor.checked_sub(other.minor))
+    }
+
+    pub fn checked_neg(&self) -> Result<Money, MoneyError> {
+        self.with_minor(self.minor.checked_neg())
+    }
+
+    pub fn checked_mul(&self, factor: i64) -> Result<Money, MoneyError> {
+        self.with_minor(self.minor.checked_mul(factor))
+    }
+
+    /// Multiply by `numerator / denominator`, rounding the result to a whole minor unit.
+    ///
+    /// Interest is applied this way: a day's interest at 4.5% a year is
+    /// `balance.mul_ratio(450, 10_000 * 365, mode)`.
+    pub fn mul_ratio(
+        &self,
+        numerator: i64,
+        denominator: i64,
+        mode: RoundingMode,
+    ) -> Result<Money, MoneyError> {
+        if denominator == 0 {
+            return Err(MoneyError::DivideByZero);
+        }
+        let n = self.minor as i128 * numerator as i128 * denominator.signum() as i128;
+        let minor = mode.divide(n, (denominator as i128).abs());
+        self.with_minor(i64::try_from(minor).ok())
+    }
+
+    /// Split into `parts` shares which add up to exactly this amount.
+    ///
+    /// Shares differ by at most one minor unit. Leftover units go to the first shares, so
+    /// splitting $10.00 three ways gives $3.34, $3.33 and $3.33.
+    pub fn split(&self, parts: usize) -> Result<Vec<Money>, MoneyError> {
+        self.allocate(&vec![1; parts])
+    }
+
+    /// Split in proportion to `weights`, with the shares adding up to exactly this amount.
+    ///
+    /// Each share is rounded toward zero and the leftover units go to the shares with the
+    /// largest remainders, earlier shares first.
+    pub fn allocate(&self, weights: &[u32]) -> Result<Vec<Money>, MoneyError> {
+        let total: i128 = weights.iter().map(|&w| w as i128).sum();
+        if total == 0 {
+            return Err(MoneyError::DivideByZero);
+        }
+        let amount = self.minor as i128;
+        let mut shares: Vec<i128> = weights
+            .iter()
+            .map(|&w| amount * w as i128 / total)
+            .collect();
+        let mut remainders: Vec<(usize, i128)> = weights
+            .iter()
+            .enumerate()
+            .map(|(i, &w)| (i, (amount * w as i128 % total).abs()))
+            .collect();
+        remainders.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let leftover = amount - shares.iter().sum::<i128>();
+        for &(i, _) in remainders.iter().take(leftover.unsigned_abs() as usize) {
+            shares[i] += leftover.signum();
+        }
+        Ok(shares
+            .into_iter()
+            .map(|minor| Self::from_minor(minor as i64, self.currency))
+            .collect())
+    }
+}
+
+impl PartialOrd for Money {
+    /// Amounts in different currencies can't be compared.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (self.currency == other.currency).then(|| self.minor.cmp(&other.minor))
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let decimal = self.to_decimal();
+        let (sign, number) = match decimal.strip_prefix('-') {
+            Some(number) => ("-", number),
+            None => ("", decimal.as_str()),
+        };
+        match self.currency.symbol() {
+            Some(symbol) => write!(f, "{sign}{symbol}{number}"),
+            None => write!(f, "{sign}{number} {}", self.currency),
+        }
+    }
+}
+
+impl fmt::Debug for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Money({self})")
+    }
+}
+
+/// Parse a decimal number into its digits and the number of decimal places.
+fn parse_decimal(number: &str) -> Option<(i64, u32)> {
+    let (negative, number) = match number.trim().strip_prefix('-') {
+        Some(number) => (true, number),
+        None => (false, number.trim()),
+    };
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    let all_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if whole.is_empty() || !all_digits(whole) || !all_digits(fraction) {
+        return None;
+    }
+    let digits: i64 = format!("{whole}{fraction}").parse().ok()?;
+    let digits = if negative { -digits } else { digits };
+    Some((digits, fraction.len() as u32))
+}
+
+/// Exchange rates between pairs of currencies.
+#[derive(Debug, Clone, Default)]
+pub struct ExchangeRates {
+    /// The rate from one currency to another as a fraction.
+    rates: HashMap<(Currency, Currency), (i64, i64)>,
+}
+
+impl ExchangeRates {
+    /// Set how many units of `to` one unit of `from` buys, as a decimal such as `1.0845`. The
+    /// inverse rate is set too.
+    pub fn set(&mut self, from: Currency, to: Currency, rate: &str) -> Result<(), MoneyError> {
+        let invalid = || MoneyError::InvalidAmount(rate.to_owned(), to);
+        let (digits, places) = parse_decimal(rate).ok_or_else(invalid)?;
+        if digits <= 0 {
+            return Err(invalid());
+        }
+        let denominator = 10_i64.checked_pow(places).ok_or_else(invalid)?;
+        self.rates.insert((from, to), (digits, denominator));
+        self.rates.insert((to, from), (denominator, digits));
+        Ok(())
+    }
+
+    /// Convert `money` into another currency.
+    pub fn convert(
+        &self,
+        money: Money,
+        to: Currency,
+        mode: RoundingMode,
+    ) -> Result<Money, MoneyError> {
+        let from = money.currency();
+        if from == to {
+            return Ok(money);
+        }
+        let &(numerator, denominator) = self
+            .rates
+            .get(&(from, to))
+            .ok_or(MoneyError::NoRate { from, to })?;
+        let numerator = numerator
+            .checked_mul(to.scale())
+            .ok_or(MoneyError::Overflow)?;
+        let denominator = denominator
+            .checked_mul(from.scale())
+            .ok_or(MoneyError::Overflow)?;
+        let converted = money.mul_ratio(numerator, denominator, mode)?;
+        Ok(Money::from_minor(converted.minor(), to))
+    }
+}
+
+/// Parse `amount` as US dollars, for tests.
+#[cfg(test)]
+pub(crate) fn usd(amount: &str) -> Money {
+    Money::parse(amount, Currency::USD).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_displays_amounts() {
+        assert_eq!(usd("12.5").minor(), 1250);
+        assert_eq!(usd("-0.07").to_string(), "-$0.07");
+        assert_eq!(
+            Money::from_major(500, Currency::JPY).unwrap().to_string(),
+            "¥500"
+        );
+        let chf = Currency::new("CHF").unwrap();
+        assert_eq!(Money::from_minor(123456, chf).to_string(), "1234.56 CHF");
+
+        assert!(Money::parse("1.005", Currency::USD).is_err());
+        assert!(Money::parse("1.5", Currency::JPY).is_err());
+        assert!(Money::parse("12,50", Currency::USD).is_err());
+        assert!(Currency::new("usd").is_err());
+    }
+
+    #[test]
+    fn arithmetic_is_exact_and_checked() {
+        let mut total = Money::zero(Currency::USD);
+        for _ in 0..10 {
+            total = total.checked_add(usd("0.10")).unwrap();
+        }
+        assert_eq!(total, usd("1.00"));
+
+        let euros = Money::from_major(1, Currency::EUR).unwrap();
+        assert_eq!(
+            total.checked_add(euros),
+            Err(MoneyError::CurrencyMismatch(Currency::USD, Currency::EUR))
+        );
+        assert_eq!(total.partial_cmp(&euros), None);
+        assert_eq!(
+            Money::from_minor(i64::MAX, Currency::USD).checked_add(usd("0.01")),
+            Err(MoneyError::Overflow)
+        );
+    }
+
+    #[test]
+    fn rounding_modes() {
+        let cases = [
+            (RoundingMode::TowardZero, [2, 2, -2, -2]),
+            (RoundingMode::AwayFromZero, [3, 3, -3, -3]),
+            (RoundingMode::Floor, [2, 2, -3, -3]),
+            (RoundingMode::Ceiling, [3, 3, -2, -2]),
+            (RoundingMode::HalfUp, [3, 3, -3, -3]),
+            (RoundingMode::HalfEven, [2, 3, -2, -3]),
+        ];
+        for (mode, expected) in cases {
+            // 2.5, 2.75, -2.5 and -2.75 cents
+            let rounded: Vec<_> = [(5, 2), (11, 4), (-5, 2), (-11, 4)]
+                .iter()
+                .map(|&(n, d)| usd("0.01").mul_ratio(n, d, mode).unwrap().minor())
+                .collect();
+            assert_eq!(rounded, expected, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn splits_without_losing_cents() {
+        let shares = usd("10.00").split(3).unwrap();
+        assert_eq!(shares, vec![usd("3.34"), usd("3.33"), usd("3.33")]);
+
+        let shares = usd("-1.00").allocate(&[1, 1, 1]).unwrap();
+        assert_eq!(shares, vec![usd("-0.34"), usd("-0.33"), usd("-0.33")]);
+
+        let shares = usd("100.00").allocate(&[70, 20, 10]).unwrap();
+        assert_eq!(shares, vec![usd("70.00"), usd("20.00"), usd("10.00")]);
+        assert_eq!(usd("1.00").split(0), Err(MoneyError::DivideByZero));
+    }
+
+    #[test]
+    fn converts_with_exchange_rates() {
+        let mut rates = ExchangeRates::default();
+        rates.set(Currency::EUR, Currency::USD, "1.0845").unwrap();
+        rates.set(Currency::USD, Currency::JPY, "151.5").unwrap();
+
+        let euros = Money::from_major(100, Currency::EUR).unwrap();
+        let dollars = rates
+            .convert(euros, Currency::USD, RoundingMode::HalfEven)
+            .unwrap();
+        assert_eq!(dollars, usd("108.45"));
+        let yen = rates
+            .convert(usd("10.01"), Currency::JPY, RoundingMode::HalfEven)
+            .unwrap();
+        assert_eq!(yen.to_string(), "¥1517");
+        assert_eq!(
+            rates.convert(euros, Currency::JPY, RoundingMode::HalfEven),
+            Err(MoneyError::NoRate {
+                from: Currency::EUR,
+                to: Currency::JPY
+            })
+        );
+    }
+}
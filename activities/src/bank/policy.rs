@@ -0,0 +1,143 @@
+//! Rules deciding which withdrawals an account allows.
+
+use super::money::Money;
+use super::AccountError;
+
+/// How far an account may go below zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overdraft {
+    /// The balance can't go below zero.
+    #[default]
+    None,
+    /// The balance may go down to `-limit`. Every withdrawal which leaves the account
+    /// overdrawn is charged `fee`, which also has to fit within the limit.
+    Limit { limit: Money, fee: Money },
+}
+
+/// The withdrawal rules of an account. By default there's no overdraft and no daily limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WithdrawalPolicy {
+    pub overdraft: Overdraft,
+    /// The most which may be withdrawn per calendar day (UTC), not counting fees.
+    pub daily_limit: Option<Money>,
+    /// Frozen accounts can't be withdrawn from at all.
+    pub frozen: bool,
+}
+
+impl WithdrawalPolicy {
+    /// Allow the balance to go down to `-limit`, charging `fee` per overdrawn withdrawal.
+    pub fn overdraft(mut self, limit: Money, fee: Money) -> Self {
+        self.overdraft = Overdraft::Limit { limit, fee };
+        self
+    }
+
+    /// Allow at most `limit` to be withdrawn per day.
+    pub fn daily_limit(mut self, limit: Money) -> Self {
+        self.daily_limit = Some(limit);
+        self
+    }
+
+    /// Check a withdrawal of `amount` from an account with `balance`, which has already had
+    /// `withdrawn_today` withdrawn. Returns the fee to charge, if any.
+    pub fn check(
+        &self,
+        balance: Money,
+        withdrawn_today: Money,
+        amount: Money,
+    ) -> Result<Option<Money>, AccountError> {
+        if self.frozen {
+            return Err(AccountError::Frozen);
+        }
+
+        if let Some(limit) = self.daily_limit {
+            let total = withdrawn_today.checked_add(amount)?;
+            if limit.checked_sub(total)?.is_negative() {
+                return Err(AccountError::DailyLimit {
+                    limit,
+                    withdrawn_today,
+                    requested: amount,
+                });
+            }
+        }
+
+        let remaining = balance.checked_sub(amount)?;
+        if !remaining.is_negative() {
+            return Ok(None);
+        }
+        match self.overdraft {
+            Overdraft::None => Err(AccountError::InsufficientFunds {
+                balance,
+                requested: amount,
+            }),
+            Overdraft::Limit { limit, fee } => {
+                if remaining
+                    .checked_sub(fee)?
+                    .checked_add(limit)?
+                    .is_negative()
+                {
+                    return Err(AccountError::OverdraftLimit {
+                        balance,
+                        limit,
+                        fee,
+                        requested: amount,
+                    });
+                }
+                Ok((!fee.is_zero()).then_some(fee))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::money::usd;
+
+    #[test]
+    fn overdraft_allows_withdrawals_down_to_the_limit_including_fees() {
+        let policy = WithdrawalPolicy::default().overdraft(usd("100"), usd("5"));
+        let zero = usd("0");
+
+        assert_eq!(policy.check(usd("50"), zero, usd("50")), Ok(None));
+        assert_eq!(
+            policy.check(usd("50"), zero, usd("145")),
+            Ok(Some(usd("5")))
+        );
+        assert_eq!(
+            policy.check(usd("50"), zero, usd("146")),
+            Err(AccountError::OverdraftLimit {
+                balance: usd("50"),
+                limit: usd("100"),
+                fee: usd("5"),
+                requested: usd("146"),
+            })
+        );
+        assert_eq!(
+            WithdrawalPolicy::default().check(usd("50"), zero, usd("51")),
+            Err(AccountError::InsufficientFunds {
+                balance: usd("50"),
+                requested: usd("51"),
+            })
+        );
+    }
+
+    #[test]
+    fn daily_limits_and_frozen_accounts() {
+        let mut policy = WithdrawalPolicy::default().daily_limit(usd("300"));
+        assert_eq!(policy.check(usd("1000"), usd("200"), usd("100")), Ok(None));
+        assert_eq!(
+            policy.check(usd("1000"), usd("200"), usd("101")),
+            Err(AccountError::DailyLimit {
+                limit: usd("300"),
+                withdrawn_today: usd("200"),
+                requested: usd("101"),
+            })
+        );
+
+        policy.frozen = true;
+        assert_eq!(
+            policy.check(usd("1000"), usd("0"), usd("1")),
+            Err(AccountError::Frozen)
+        );
+    }
+}
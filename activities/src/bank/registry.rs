@@ -0,0 +1,293 @@
+//! Accounts shared between threads.
+//!
+//! [`Bank`] keeps every account behind its own lock, so transfers between different accounts
+//! run in parallel. A transfer locks both of its accounts in name order, which means two
+//! transfers in opposite directions can't each hold one lock while waiting for the other.
+
+use super::money::{Currency, Money, MoneyError};
+use super::{AccountError, BankAccount};
+use chrono::{DateTime, Utc};
+use parking_lot::{Mutex, RwLock};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Errors that may occur while working with a bank's accounts.
+#[derive(Debug, thiserror::Error, Clone, PartialEq, Eq)]
+pub enum BankError {
+    #[error("no account named {0}")]
+    UnknownAccount(String),
+
+    #[error("an account named {0} already exists")]
+    DuplicateAccount(String),
+
+    #[error("transfer amount must be positive, got {0}")]
+    InvalidAmount(Money),
+
+    #[error("can't transfer from {0} to itself")]
+    SameAccount(String),
+
+    #[error("{account}: {source}")]
+    Account {
+        account: String,
+        #[source]
+        source: AccountError,
+    },
+}
+
+type SharedAccount = Arc<Mutex<BankAccount>>;
+
+/// A registry of accounts which can be used from many threads at once.
+#[derive(Debug, Default)]
+pub struct Bank {
+    accounts: RwLock<BTreeMap<String, SharedAccount>>,
+}
+
+impl Bank {
+    /// Add an account, which is known by its name.
+    pub fn open(&self, account: BankAccount) -> Result<(), BankError> {
+        let mut accounts = self.accounts.write();
+        let name = account.name().to_owned();
+        if accounts.contains_key(&name) {
+            return Err(BankError::DuplicateAccount(name));
+        }
+        accounts.insert(name, Arc::new(Mutex::new(account)));
+        Ok(())
+    }
+
+    /// The names of every account, sorted.
+    pub fn names(&self) -> Vec<String> {
+        self.accounts.read().keys().cloned().collect()
+    }
+
+    fn get(&self, name: &str) -> Result<SharedAccount, BankError> {
+        self.accounts
+            .read()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| BankError::UnknownAccount(name.to_owned()))
+    }
+
+    /// Run `f` with exclusive access to an account, for example to deposit into it.
+    pub fn with_account<R, F>(&self, name: &str, f: F) -> Result<R, BankError>
+    where
+        F: FnOnce(&mut BankAccount) -> R,
+    {
+        let account = self.get(name)?;
+        let mut account = account.lock();
+        Ok(f(&mut account))
+    }
+
+    pub fn balance(&self, name: &str) -> Result<Money, BankError> {
+        self.with_account(name, |account| account.balance())
+    }
+
+    /// Move `amount` from one account to another. Either both accounts change or neither does.
+    pub fn transfer(&self, from: &str, to: &str, amount: Money) -> Result<(), BankError> {
+        self.transfer_at(from, to, amount, Utc::now())
+    }
+
+    /// Move `amount` from one account to another at `now`.
+    pub fn transfer_at(
+        &self,
+        from: &str,
+        to: &str,
+        amount: Money,
+        now: DateTime<Utc>,
+    ) -> Result<(), BankError> {
+        if !amount.is_positive() {
+            return Err(BankError::InvalidAmount(amount));
+        }
+        if from == to {
+            return Err(BankError::SameAccount(from.to_owned()));
+        }
+        let (payer, payee) = (self.get(from)?, self.get(to)?);
+
+        let (mut payer, mut payee) = if from < to {
+            let payer = payer.lock();
+            (payer, payee.lock())
+        } else {
+            let payee = payee.lock();
+            (payer.lock(), payee)
+        };
+
+        let rejected = |account: &str, source: AccountError| BankError::Account {
+            account: account.to_owned(),
+            source,
+        };
+        payee
+            .check_receive_at(from, amount, now)
+            .map_err(|e| rejected(to, e))?;
+        payer
+            .pay_at(to, amount, now)
+            .map_err(|e| rejected(from, e))?;
+        payee
+            .receive_at(from, amount, now)
+            .expect("the payee was checked");
+        Ok(())
+    }
+
+    /// The total balance of every account in `currency`.
+    ///
+    /// Every account is locked at once, in the same order as transfers, so the total never
+    /// includes a transfer which is only half done.
+    ///
+    /// Transfers between accounts never change the total, but fees don't stay in the bank's
+    /// accounts: an overdraft fee is paid to the ledger's [`FEES`](super::ledger::FEES)
+    /// account, so it lowers the total by the amount charged.
+    pub fn total(&self, currency: Currency) -> Result<Money, MoneyError> {
+        let accounts: Vec<_> = self.accounts.read().values().cloned().collect();
+        let locked: Vec<_> = accounts.iter().map(|account| account.lock()).collect();
+        locked
+            .iter()
+            .map(|account| account.balance())
+            .filter(|balance| balance.currency() == currency)
+            .try_fold(Money::zero(currency), |total, balance| {
+                total.checked_add(balance)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::ledger::FEES;
+    use crate::bank::money::usd;
+    use crate::bank::policy::WithdrawalPolicy;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn transfers_move_money_or_change_nothing() {
+        let bank = Bank::default();
+        bank.open(BankAccount::named("alice", usd("100"))).unwrap();
+        bank.open(BankAccount::named("bob", usd("10"))).unwrap();
+        assert_eq!(
+            bank.open(BankAccount::named("bob", usd("0"))),
+            Err(BankError::DuplicateAccount("bob".into()))
+        );
+
+        bank.transfer("alice", "bob", usd("60")).unwrap();
+        assert_eq!(bank.balance("alice").unwrap(), usd("40"));
+        assert_eq!(bank.balance("bob").unwrap(), usd("70"));
+
+        assert!(matches!(
+            bank.transfer("alice", "bob", usd("41")),
+            Err(BankError::Account {
+                source: AccountError::InsufficientFunds { .. },
+                ..
+            })
+        ));
+        assert_eq!(
+            bank.transfer("alice", "carol", usd("1")),
+            Err(BankError::UnknownAccount("carol".into()))
+        );
+        assert!(bank.transfer("alice", "alice", usd("1")).is_err());
+        assert!(bank.transfer("alice", "bob", usd("0")).is_err());
+
+        let euros = Money::from_major(1, Currency::EUR).unwrap();
+        assert!(bank.transfer("alice", "bob", euros).is_err());
+        assert_eq!(bank.total(Currency::USD), Ok(usd("110")));
+
+        let last = bank
+            .with_account("bob", |bob| bob.ledger().transactions().last().cloned())
+            .unwrap()
+            .unwrap();
+        assert_eq!(last.description, "transfer from alice");
+    }
+
+    #[test]
+    fn transfers_cant_predate_either_account() {
+        let bank = Bank::default();
+        let monday = "2024-03-04T10:00:00Z".parse().unwrap();
+        let tuesday = "2024-03-05T10:00:00Z".parse().unwrap();
+        bank.open(BankAccount::open_at("alice", usd("100"), monday))
+            .unwrap();
+        bank.open(BankAccount::open_at("bob", usd("100"), tuesday))
+            .unwrap();
+
+        assert!(matches!(
+            bank.transfer_at("alice", "bob", usd("10"), monday),
+            Err(BankError::Account { account, source: AccountError::OutOfOrder { .. } })
+                if account == "bob"
+        ));
+        assert_eq!(bank.balance("alice"), Ok(usd("100")));
+        bank.transfer_at("alice", "bob", usd("10"), tuesday)
+            .unwrap();
+        assert_eq!(bank.total(Currency::USD), Ok(usd("200")));
+    }
+
+    #[test]
+    fn concurrent_transfers_conserve_money() {
+        const ACCOUNTS: usize = 8;
+        let bank = Bank::default();
+        let names: Vec<String> = (0..ACCOUNTS).map(|i| format!("account-{i}")).collect();
+        for name in &names {
+            // without a fee, so money only moves between accounts and the total never changes
+            let policy = WithdrawalPolicy::default().overdraft(usd("50"), usd("0"));
+            let account = BankAccount::named(name.as_str(), usd("1000")).with_policy(policy);
+            bank.open(account).unwrap();
+        }
+
+        let succeeded = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for t in 0..8_u64 {
+                let (bank, names, succeeded) = (&bank, &names, &succeeded);
+                s.spawn(move || {
+                    let mut state = t + 1;
+                    let mut next = || {
+                        state ^= state << 13;
+                        state ^= state >> 7;
+                        state ^= state << 17;
+                        state as usize
+                    };
+                    for _ in 0..2000 {
+                        let from = &names[next() % ACCOUNTS];
+                        let to = &names[next() % ACCOUNTS];
+                        let amount = Money::from_minor((next() % 20_000) as i64 + 1, Currency::USD);
+                        if bank.transfer(from, to, amount).is_ok() {
+                            succeeded.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+            // totals taken mid-flight must already balance
+            s.spawn(|| {
+                for _ in 0..100 {
+                    assert_eq!(bank.total(Currency::USD), Ok(usd("8000")));
+                }
+            });
+        });
+
+        assert_eq!(bank.total(Currency::USD), Ok(usd("8000")));
+        // an eighth of the attempts are from an account to itself, and a few would overdraw
+        let succeeded = succeeded.into_inner();
+        assert!(
+            succeeded > 10_000,
+            "only {succeeded} of 16000 transfers succeeded"
+        );
+        for name in &names {
+            bank.with_account(name, |account| {
+                assert!(account.balance() >= usd("-50"));
+                assert!(account.ledger().trial_balance().is_balanced());
+            })
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn fees_leave_the_total() {
+        let bank = Bank::default();
+        let policy = WithdrawalPolicy::default().overdraft(usd("50"), usd("5"));
+        bank.open(BankAccount::named("alice", usd("10")).with_policy(policy))
+            .unwrap();
+        bank.open(BankAccount::named("bob", usd("10"))).unwrap();
+
+        bank.transfer("alice", "bob", usd("30")).unwrap();
+        assert_eq!(bank.balance("alice"), Ok(usd("-25")));
+        assert_eq!(bank.total(Currency::USD), Ok(usd("15")));
+        let fees = bank
+            .with_account("alice", |alice| alice.ledger().balance(FEES))
+            .unwrap();
+        assert_eq!(fees, usd("5"));
+    }
+}
@@ -0,0 +1,543 @@
+//! Standing orders and interest, run against a simulated clock.
+//!
+//! A [`Scheduler`] owns a [`Bank`] and steps through the calendar one day at a time. Each day it
+//! runs the standing orders due that day, then accrues a day's interest on every account which
+//! earns it. Accrued interest is paid on the last day of each month. Nothing depends on the
+//! real time, so a year of activity always plays out the same way.
+
+use super::ledger::INTEREST;
+use super::money::{Money, MoneyError, RoundingMode};
+use super::registry::{Bank, BankError};
+use super::AccountError;
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Utc, Weekday};
+use std::collections::BTreeMap;
+
+/// Interest is accrued in this fraction of a minor unit: basis points per day, where a year is
+/// taken to be 365 days.
+const ACCRUAL_SCALE: i64 = 10_000 * 365;
+
+/// When a standing order runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recurrence {
+    Daily,
+    Weekly(Weekday),
+    /// On this day of every month. Days past the end of a month fall on its last day, so
+    /// `Monthly(31)` runs on the last day of every month.
+    Monthly(u32),
+}
+
+impl Recurrence {
+    /// Returns `true` if an order with this recurrence runs on `date`.
+    pub fn occurs_on(&self, date: NaiveDate) -> bool {
+        match *self {
+            Recurrence::Daily => true,
+            Recurrence::Weekly(weekday) => date.weekday() == weekday,
+            Recurrence::Monthly(day) => date.day() == day.clamp(1, days_in_month(date)),
+        }
+    }
+}
+
+fn days_in_month(date: NaiveDate) -> u32 {
+    let (year, month) = match date.month() {
+        12 => (date.year() + 1, 1),
+        month => (date.year(), month + 1),
+    };
+    let first_of_next = NaiveDate::from_ymd_opt(year, month, 1).expect("valid date");
+    first_of_next.pred_opt().expect("valid date").day()
+}
+
+fn is_last_day_of_month(date: NaiveDate) -> bool {
+    date.day() == days_in_month(date)
+}
+
+/// What a standing order does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Deposit {
+        account: String,
+        amount: Money,
+    },
+    Withdraw {
+        account: String,
+        amount: Money,
+    },
+    Transfer {
+        from: String,
+        to: String,
+        amount: Money,
+    },
+}
+
+/// A transaction which repeats on a schedule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StandingOrder {
+    pub description: String,
+    pub recurrence: Recurrence,
+    pub action: Action,
+    /// The first day the order may run.
+    pub starts: Option<NaiveDate>,
+    /// The last day the order may run.
+    pub ends: Option<NaiveDate>,
+}
+
+impl StandingOrder {
+    /// An order which runs on every day matching `recurrence`.
+    pub fn new<D: Into<String>>(description: D, recurrence: Recurrence, action: Action) -> Self {
+        Self {
+            description: description.into(),
+            recurrence,
+            action,
+            starts: None,
+            ends: None,
+        }
+    }
+
+    /// Don't run before `date`.
+    pub fn starting(mut self, date: NaiveDate) -> Self {
+        self.starts = Some(date);
+        self
+    }
+
+    /// Don't run after `date`.
+    pub fn until(mut self, date: NaiveDate) -> Self {
+        self.ends = Some(date);
+        self
+    }
+
+    /// Returns `true` if the order runs on `date`.
+    pub fn is_due(&self, date: NaiveDate) -> bool {
+        self.starts.is_none_or(|starts| date >= starts)
+            && self.ends.is_none_or(|ends| date <= ends)
+            && self.recurrence.occurs_on(date)
+    }
+}
+
+/// The interest earned by an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interest {
+    /// The annual percentage rate in basis points, so 4.5% is 450.
+    pub apr_basis_points: i64,
+    /// How the month's accrued interest is rounded to a minor unit when it's paid.
+    pub rounding: RoundingMode,
+}
+
+impl Interest {
+    /// Interest at `apr_basis_points` a year, rounded half to even.
+    pub fn apr(apr_basis_points: i64) -> Self {
+        Self {
+            apr_basis_points,
+            rounding: RoundingMode::default(),
+        }
+    }
+}
+
+/// Something the scheduler did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Activity {
+    /// A standing order ran, possibly unsuccessfully.
+    Order {
+        date: NaiveDate,
+        description: String,
+        result: Result<(), BankError>,
+    },
+    /// A month's interest was paid into an account.
+    Interest {
+        date: NaiveDate,
+        account: String,
+        amount: Money,
+    },
+    /// Interest couldn't be accrued or paid. Whatever had accrued is kept and paid later, unless
+    /// the account no longer exists, in which case it stops earning interest.
+    InterestFailed {
+        date: NaiveDate,
+        account: String,
+        error: BankError,
+    },
+}
+
+/// Runs standing orders and pays interest on a simulated calendar.
+#[derive(Debug)]
+pub struct Scheduler {
+    bank: Bank,
+    next_day: NaiveDate,
+    orders: Vec<StandingOrder>,
+    interest: BTreeMap<String, Interest>,
+    /// Interest accrued this month, in units of 1/ACCRUAL_SCALE of a minor unit.
+    accrued: BTreeMap<String, i64>,
+}
+
+impl Scheduler {
+    /// Create a scheduler whose first simulated day is `start`.
+    pub fn new(bank: Bank, start: NaiveDate) -> Self {
+        Self {
+            bank,
+            next_day: start,
+            orders: Vec::new(),
+            interest: BTreeMap::new(),
+            accrued: BTreeMap::new(),
+        }
+    }
+
+    pub fn bank(&self) -> &Bank {
+        &self.bank
+    }
+
+    pub fn into_bank(self) -> Bank {
+        self.bank
+    }
+
+    /// The first day which hasn't been simulated yet.
+    pub fn next_day(&self) -> NaiveDate {
+        self.next_day
+    }
+
+    pub fn schedule(&mut self, order: StandingOrder) {
+        self.orders.push(order);
+    }
+
+    /// Make `account` earn interest, replacing any rate it had. Interest is only earned on a
+    /// positive balance.
+    pub fn set_interest<A: Into<String>>(&mut self, account: A, interest: Interest) {
+        self.interest.insert(account.into(), interest);
+    }
+
+    /// Simulate `days` days.
+    pub fn advance(&mut self, days: u64) -> Vec<Activity> {
+        let end = self
+            .next_day
+            .checked_add_days(chrono::Days::new(days))
+            .expect("date out of range");
+        self.run_until(end)
+    }
+
+    /// Simulate every day before `end`.
+    pub fn run_until(&mut self, end: NaiveDate) -> Vec<Activity> {
+        let mut activity = Vec::new();
+        while self.next_day < end {
+            let date = self.next_day;
+            self.run_orders(date, &mut activity);
+            self.accrue_interest(date, &mut activity);
+            self.next_day = date.succ_opt().expect("date out of range");
+        }
+        activity
+    }
+
+    fn run_orders(&self, date: NaiveDate, activity: &mut Vec<Activity>) {
+        let at = date.and_time(NaiveTime::MIN).and_utc();
+        for order in self.orders.iter().filter(|order| order.is_due(date)) {
+            activity.push(Activity::Order {
+                date,
+                description: order.description.clone(),
+                result: self.execute(&order.action, at),
+            });
+        }
+    }
+
+    fn execute(&self, action: &Action, at: DateTime<Utc>) -> Result<(), BankError> {
+        let rejected = |account: &str, source| BankError::Account {
+            account: account.to_owned(),
+            source,
+        };
+        match action {
+            Action::Deposit { account, amount } => self
+                .bank
+                .with_account(account, |a| a.deposit_at(*amount, at))?
+                .map_err(|e| rejected(account, e)),
+            Action::Withdraw { account, amount } => self
+                .bank
+                .with_account(account, |a| a.withdraw_at(*amount, at))?
+                .map_err(|e| rejected(account, e)),
+            Action::Transfer { from, to, amount } => self.bank.transfer_at(from, to, *amount, at),
+        }
+    }
+
+    /// Accrue a day's interest on every account which earns it, paying it on the last day of
+    /// the month.
+    fn accrue_interest(&mut self, date: NaiveDate, activity: &mut Vec<Activity>) {
+        let at = date
+            .and_time(NaiveTime::from_hms_opt(23, 59, 59).expect("valid time"))
+            .and_utc();
+        let mut closed = Vec::new();
+        for (name, interest) in &self.interest {
+            let rejected = |source: AccountError| BankError::Account {
+                account: name.clone(),
+                source,
+            };
+            let accrued = self.bank.with_account(name, |account| {
+                let accrued = self.accrued.get(name).copied().unwrap_or(0);
+                let day = account
+                    .balance()
+                    .minor()
+                    .max(0)
+                    .checked_mul(interest.apr_basis_points);
+                day.and_then(|day| accrued.checked_add(day))
+                    .ok_or(MoneyError::Overflow)
+            });
+            let paid = accrued
+                .and_then(|accrued| accrued.map_err(|e| rejected(e.into())))
+                .and_then(|accrued| {
+                    // kept until it's paid, so a payment which fails is retried next month
+                    self.accrued.insert(name.clone(), accrued);
+                    if !is_last_day_of_month(date) {
+                        return Ok(None);
+                    }
+                    self.bank
+                        .with_account(name, |account| {
+                            let amount = Money::from_minor(1, account.currency()).mul_ratio(
+                                accrued,
+                                ACCRUAL_SCALE,
+                                interest.rounding,
+                            )?;
+                            if amount.is_positive() {
+                                account.receive_at(INTEREST, amount, at)?;
+                            }
+                            Ok(Some(amount))
+                        })?
+                        .map_err(rejected)
+                });
+
+            match paid {
+                Ok(None) => (),
+                Ok(Some(amount)) => {
+                    self.accrued.insert(name.clone(), 0);
+                    if amount.is_positive() {
+                        activity.push(Activity::Interest {
+                            date,
+                            account: name.clone(),
+                            amount,
+                        });
+                    }
+                }
+                Err(error) => {
+                    if let BankError::UnknownAccount(_) = error {
+                        closed.push(name.clone());
+                    }
+                    activity.push(Activity::InterestFailed {
+                        date,
+                        account: name.clone(),
+                        error,
+                    });
+                }
+            }
+        }
+        for name in closed {
+            self.interest.remove(&name);
+            self.accrued.remove(&name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::money::{usd, Currency};
+    use crate::bank::BankAccount;
+
+    fn date(date: &str) -> NaiveDate {
+        date.parse().unwrap()
+    }
+
+    fn start() -> DateTime<Utc> {
+        "2024-01-01T00:00:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn recurrences() {
+        let leap_day = date("2024-02-29");
+        assert!(Recurrence::Monthly(31).occurs_on(leap_day));
+        assert!(Recurrence::Monthly(29).occurs_on(leap_day));
+        assert!(!Recurrence::Monthly(28).occurs_on(leap_day));
+        assert!(Recurrence::Weekly(Weekday::Thu).occurs_on(leap_day));
+        assert!(Recurrence::Monthly(31).occurs_on(date("2024-04-30")));
+
+        let order = StandingOrder::new(
+            "gym",
+            Recurrence::Daily,
+            Action::Withdraw {
+                account: "alice".into(),
+                amount: usd("1"),
+            },
+        )
+        .starting(date("2024-03-01"))
+        .until(date("2024-03-31"));
+        assert!(!order.is_due(leap_day));
+        assert!(order.is_due(date("2024-03-31")));
+        assert!(!order.is_due(date("2024-04-01")));
+    }
+
+    #[test]
+    fn interest_accrues_daily_and_is_paid_monthly() {
+        let bank = Bank::default();
+        bank.open(BankAccount::open_at("saver", usd("10000"), start()))
+            .unwrap();
+        let mut scheduler = Scheduler::new(bank, date("2024-01-01"));
+        scheduler.set_interest("saver", Interest::apr(365));
+
+        // $1.00 a day in January, then 1003100 * 365 / 3650000 = 100.31 cents a day
+        let activity = scheduler.run_until(date("2024-03-01"));
+        let paid: Vec<_> = activity
+            .iter()
+            .map(|a| match a {
+                Activity::Interest { date, amount, .. } => (date.to_string(), *amount),
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            paid,
+            vec![
+                ("2024-01-31".to_owned(), usd("31.00")),
+                ("2024-02-29".to_owned(), usd("29.09")),
+            ]
+        );
+        assert_eq!(scheduler.bank().balance("saver"), Ok(usd("10060.09")));
+        assert_eq!(scheduler.next_day(), date("2024-03-01"));
+    }
+
+    #[test]
+    fn reports_interest_which_cant_be_paid() {
+        let bank = Bank::default();
+        let huge = Money::from_minor(i64::MAX / 2, Currency::USD);
+        bank.open(BankAccount::open_at("whale", huge, start()))
+            .unwrap();
+        let mut scheduler = Scheduler::new(bank, date("2024-01-01"));
+        scheduler.set_interest("whale", Interest::apr(365));
+        scheduler.set_interest("ghost", Interest::apr(365));
+
+        let activity = scheduler.advance(2);
+        let failures: Vec<_> = activity
+            .iter()
+            .map(|a| match a {
+                Activity::InterestFailed { account, error, .. } => (account.as_str(), error),
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect();
+        let overflow = BankError::Account {
+            account: "whale".into(),
+            source: MoneyError::Overflow.into(),
+        };
+        let unknown = BankError::UnknownAccount("ghost".into());
+        // the missing account stops earning interest, the other keeps trying
+        assert_eq!(
+            failures,
+            [
+                ("ghost", &unknown),
+                ("whale", &overflow),
+                ("whale", &overflow)
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_interest_which_cant_be_paid_yet() {
+        let bank = Bank::default();
+        let opened = "2024-02-10T00:00:00Z".parse().unwrap();
+        bank.open(BankAccount::open_at("saver", usd("10000"), opened))
+            .unwrap();
+        let mut scheduler = Scheduler::new(bank, date("2024-01-01"));
+        scheduler.set_interest("saver", Interest::apr(365));
+
+        // January's interest would be paid before the account's history starts
+        let activity = scheduler.run_until(date("2024-03-01"));
+        assert!(matches!(
+            &activity[0],
+            Activity::InterestFailed {
+                error: BankError::Account {
+                    source: AccountError::OutOfOrder { .. },
+                    ..
+                },
+                ..
+            }
+        ));
+        assert_eq!(
+            activity[1],
+            Activity::Interest {
+                date: date("2024-02-29"),
+                account: "saver".into(),
+                amount: usd("60.00"),
+            }
+        );
+        assert_eq!(activity.len(), 2);
+    }
+
+    fn simulate_year() -> (Vec<Activity>, Bank) {
+        let bank = Bank::default();
+        bank.open(BankAccount::open_at("alice", usd("1000"), start()))
+            .unwrap();
+        bank.open(BankAccount::open_at("landlord", usd("0"), start()))
+            .unwrap();
+        let mut scheduler = Scheduler::new(bank, date("2024-01-01"));
+        let salary = Action::Deposit {
+            account: "alice".into(),
+            amount: usd("3000"),
+        };
+        let rent = Action::Transfer {
+            from: "alice".into(),
+            to: "landlord".into(),
+            amount: usd("1200"),
+        };
+        let groceries = Action::Withdraw {
+            account: "alice".into(),
+            amount: usd("150.25"),
+        };
+        scheduler.schedule(StandingOrder::new(
+            "salary",
+            Recurrence::Monthly(31),
+            salary,
+        ));
+        scheduler.schedule(StandingOrder::new("rent", Recurrence::Monthly(1), rent));
+        scheduler.schedule(StandingOrder::new(
+            "groceries",
+            Recurrence::Weekly(Weekday::Sat),
+            groceries,
+        ));
+        scheduler.set_interest("alice", Interest::apr(450));
+
+        let activity = scheduler.advance(366);
+        (activity, scheduler.into_bank())
+    }
+
+    #[test]
+    fn simulates_a_year_deterministically() {
+        let (activity, bank) = simulate_year();
+        let (again, _) = simulate_year();
+        assert_eq!(activity, again);
+
+        let count = |description: &str| {
+            activity
+                .iter()
+                .filter(|a| match a {
+                    Activity::Order {
+                        description: d,
+                        result,
+                        ..
+                    } => d == description && result.is_ok(),
+                    _ => false,
+                })
+                .count()
+        };
+        assert_eq!(count("salary"), 12);
+        assert_eq!(count("groceries"), 52);
+        // rent on January 1st bounces because the first salary hasn't arrived
+        assert_eq!(count("rent"), 11);
+        assert_eq!(bank.balance("landlord"), Ok(usd("13200")));
+
+        let interest = activity
+            .iter()
+            .filter_map(|a| match a {
+                Activity::Interest { amount, .. } => Some(*amount),
+                _ => None,
+            })
+            .try_fold(usd("0"), |total, amount| total.checked_add(amount))
+            .unwrap();
+        assert!(interest.is_positive());
+        let december = bank
+            .with_account("alice", |alice| alice.statement(2024, 12).unwrap())
+            .unwrap();
+        // rent, four Saturdays of groceries, salary and interest
+        assert_eq!(december.lines.len(), 7);
+        assert_eq!(Ok(december.closing), bank.balance("alice"));
+        // 1000 + 12 * 3000 - 11 * 1200 - 52 * 150.25 + interest
+        let expected = usd("15987.00").checked_add(interest).unwrap();
+        assert_eq!(bank.balance("alice"), Ok(expected));
+    }
+}
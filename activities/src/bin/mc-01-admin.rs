@@ -0,0 +1,104 @@
+// Inspects and manages message queues saved to disk.
+//
+// Usage:
+//   cargo run --bin mc-01-admin -- <command> <args...>
+//
+//   list <queue>              print every unexpired message
+//   peek <queue>              print the message that would be dequeued next
+//   count <queue>             print the number of unexpired messages
+//   purge <queue> [--expired] remove all messages, or only expired ones
+//   export <queue> <file>     write the queue to a JSON, CSV or binary file
+//   import <file> <queue>     replace the queue with the contents of a file
+//   requeue <dead> <live>     move the messages in a dead file onto the end of a live queue
+//
+// The format of each file is taken from its extension: `.csv`, `.json` or `.mqb` (binary). Prefix
+// a path with `csv:`, `json:` or `binary:` to pick the format explicitly. Any other file is
+// treated as one saved by `FileStore`, like `.mc-01-queue`.
+
+use color_eyre::eyre::{eyre, WrapErr};
+use mylib::message_queue::admin::{self, Purge};
+use mylib::message_queue::formats::{convert, Format};
+use mylib::message_queue::protocol::escape;
+use mylib::message_queue::{FileStore, Message, MessageQueueStorage};
+
+const USAGE: &str = "usage: mc-01-admin <list|peek|count|purge|export|import|requeue> <args...>";
+
+/// Open a queue file. The format comes from a `csv:`, `json:` or `binary:` prefix, then from the
+/// file extension, and otherwise defaults to `FileStore`.
+fn store(arg: &str) -> Box<dyn MessageQueueStorage> {
+    if let Some((format, path)) = Format::split_prefix(arg) {
+        return format.store(path);
+    }
+    match Format::from_path(arg) {
+        Some(format) => format.store(arg),
+        None => Box::new(FileStore::new(arg)),
+    }
+}
+
+fn print_message(message: &Message) {
+    print!("{}\t{}", message.id, message.priority);
+    if let Some(not_before) = message.not_before {
+        print!("\tnot before {}", not_before.to_rfc3339());
+    }
+    if let Some(expires_at) = message.expires_at {
+        print!("\texpires {}", expires_at.to_rfc3339());
+    }
+    println!("\t{}", escape(&message.content));
+}
+
+fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["list", queue] => {
+            let loaded = store(queue)
+                .load()
+                .wrap_err_with(|| format!("failed to load {queue}"))?;
+            loaded.iter().for_each(print_message);
+        }
+        ["peek", queue] => {
+            let loaded = store(queue)
+                .load()
+                .wrap_err_with(|| format!("failed to load {queue}"))?;
+            match loaded.peek() {
+                Some(message) => print_message(message),
+                None => println!("no messages are ready"),
+            }
+        }
+        ["count", queue] => {
+            let loaded = store(queue)
+                .load()
+                .wrap_err_with(|| format!("failed to load {queue}"))?;
+            println!("{}", loaded.iter().count());
+        }
+        ["purge", queue, rest @ ..] => {
+            let which = match rest {
+                [] => Purge::All,
+                ["--expired"] => Purge::Expired,
+                _ => return Err(eyre!("usage: mc-01-admin purge <queue> [--expired]")),
+            };
+            let removed = admin::purge(store(queue).as_ref(), which)
+                .wrap_err_with(|| format!("failed to purge {queue}"))?;
+            println!("removed {removed} messages from {queue}");
+        }
+        ["export", queue, file] => {
+            let count = convert(store(queue).as_ref(), store(file).as_ref())
+                .wrap_err_with(|| format!("failed to export {queue} to {file}"))?;
+            println!("exported {count} messages to {file}");
+        }
+        ["import", file, queue] => {
+            let count = convert(store(file).as_ref(), store(queue).as_ref())
+                .wrap_err_with(|| format!("failed to import {file} into {queue}"))?;
+            println!("imported {count} messages into {queue}");
+        }
+        ["requeue", dead, live] => {
+            let moved = admin::requeue(store(dead).as_ref(), store(live).as_ref())
+                .wrap_err_with(|| format!("failed to requeue {dead} into {live}"))?;
+            println!("moved {moved} messages from {dead} to {live}");
+        }
+        _ => return Err(eyre!(USAGE)),
+    }
+    Ok(())
+}
@@ -0,0 +1,37 @@
+// Converts a saved message queue from one storage format to another.
+//
+// Usage:
+//   cargo run --bin mc-01-convert -- <from> <to>
+//
+// The format of each file is taken from its extension: `.csv`, `.json` or `.mqb` (binary). Prefix
+// a path with `csv:`, `json:` or `binary:` to pick the format explicitly, for example
+// `json:.mc-01-queue` to read a file saved by `FileStore`.
+
+use color_eyre::eyre::{eyre, WrapErr};
+use mylib::message_queue::formats::{convert, Format};
+use mylib::message_queue::MessageQueueStorage;
+
+/// Parse a `[format:]path` argument into a store.
+fn store(arg: &str) -> color_eyre::Result<Box<dyn MessageQueueStorage>> {
+    if let Some((format, path)) = Format::split_prefix(arg) {
+        return Ok(format.store(path));
+    }
+    let format = Format::from_path(arg).ok_or_else(|| {
+        eyre!("can't tell the format of {arg}; prefix it with csv:, json: or binary:")
+    })?;
+    Ok(format.store(arg))
+}
+
+fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [from, to] = args.as_slice() else {
+        return Err(eyre!("usage: mc-01-convert <from> <to>"));
+    };
+
+    let count = convert(store(from)?.as_ref(), store(to)?.as_ref())
+        .wrap_err_with(|| format!("failed to convert {from} to {to}"))?;
+    println!("converted {count} messages from {from} to {to}");
+    Ok(())
+}
@@ -0,0 +1,43 @@
+// Serves a message queue to other processes on this host.
+//
+// Usage:
+//   cargo run --bin mc-01-server -- [address] [capacity]
+//
+//   address   a loopback address; defaults to 127.0.0.1:7878
+//   capacity  optional maximum number of queued messages (at least 1); enqueues are rejected
+//             when full
+//
+// Try it out with netcat:
+//   $ nc 127.0.0.1 7878
+//   ENQUEUE hello
+//   OK 0
+//   DEQUEUE
+//   MESSAGE 0 hello
+
+use color_eyre::eyre::WrapErr;
+use mylib::message_queue::server::Server;
+use mylib::message_queue::shared::{Backpressure, SharedQueue};
+use std::num::NonZeroUsize;
+
+const DEFAULT_ADDR: &str = "127.0.0.1:7878";
+
+fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+
+    let mut args = std::env::args().skip(1);
+    let addr = args.next().unwrap_or_else(|| DEFAULT_ADDR.to_owned());
+    let queue = match args.next() {
+        Some(capacity) => {
+            let capacity: NonZeroUsize = capacity
+                .parse()
+                .wrap_err_with(|| format!("invalid capacity: {capacity}"))?;
+            SharedQueue::bounded(capacity.get(), Backpressure::Reject)
+        }
+        None => SharedQueue::unbounded(),
+    };
+
+    let server = Server::bind(&addr, queue).wrap_err_with(|| format!("failed to bind {addr}"))?;
+    println!("listening on {}", server.local_addr()?);
+    server.run()?;
+    Ok(())
+}
@@ -32,7 +32,9 @@
 //     - use the `serde_json` crate to perform the serialize and deserialize operation
 
 use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
+use std::path::PathBuf;
 
 /// A message in the queue.
 ///
@@ -77,6 +79,8 @@ enum FileStoreError {
     #[error("IO error")]
     IO(#[from] std::io::Error),
     // add more variants if needed
+    #[error("malformed queue file")]
+    Format(#[from] serde_json::Error),
 }
 
 /// Allows conversion of error type using question mark operator.
@@ -149,6 +153,76 @@ impl MessageQueue {
 * - implementation blocks
 ********************************************/
 
+/// Saves and loads a whole [`MessageQueue`].
+trait MessageQueueStorage {
+    fn save(&self, queue: &MessageQueue) -> Result<(), MessageQueueStorageError>;
+    fn load(&self) -> Result<MessageQueue, MessageQueueStorageError>;
+}
+
+/// Stores a queue as JSON in a single file.
+struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn write(&self, queue: &MessageQueue) -> Result<(), FileStoreError> {
+        let stored = StoredQueue {
+            next_id: queue.next_id,
+            messages: queue.iter().cloned().map(StoredMessage::from).collect(),
+        };
+        std::fs::write(&self.path, serde_json::to_vec(&stored)?)?;
+        Ok(())
+    }
+
+    fn read(&self) -> Result<MessageQueue, FileStoreError> {
+        let stored: StoredQueue = serde_json::from_slice(&std::fs::read(&self.path)?)?;
+        Ok(MessageQueue {
+            messages: stored
+                .messages
+                .into_iter()
+                .map(|message| Message::new(message.id, message.content))
+                .collect(),
+            next_id: stored.next_id,
+        })
+    }
+}
+
+impl MessageQueueStorage for FileStore {
+    fn save(&self, queue: &MessageQueue) -> Result<(), MessageQueueStorageError> {
+        Ok(self.write(queue)?)
+    }
+
+    fn load(&self) -> Result<MessageQueue, MessageQueueStorageError> {
+        Ok(self.read()?)
+    }
+}
+
+/// The file format, kept apart from the queue so the queue doesn't need to change.
+#[derive(Serialize, Deserialize)]
+struct StoredQueue {
+    next_id: u32,
+    messages: Vec<StoredMessage>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredMessage {
+    id: u32,
+    content: String,
+}
+
+impl From<Message> for StoredMessage {
+    fn from(message: Message) -> Self {
+        Self {
+            id: message.id,
+            content: message.content,
+        }
+    }
+}
+
 /// *****************************************************************
 /// use `cargo test --bin mc-01` to check your work.
 /// *****************************************************************
@@ -161,9 +235,13 @@ fn main() -> color_eyre::Result<()> {
     let mut queue = MessageQueue::default();
     queue.enqueue("first message");
     queue.enqueue("second message");
-    Ok(())
 
     // save/load here
+    let storage = FileStore::new(".mc-01-save");
+    storage.save(&queue)?;
+    let loaded = storage.load()?;
+    println!("loaded {} messages", loaded.iter().count());
+    Ok(())
 }
 
 #[cfg(test)]
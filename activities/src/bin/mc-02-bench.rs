@@ -0,0 +1,91 @@
+// Compares the throughput of `ShardedInventory` with a single shard, which puts every item behind
+// one global lock, against the default number of shards. Both sides run the same code with the
+// same borrowed keys, so the difference comes from lock contention.
+//
+// Usage:
+//   cargo run --release --bin mc-02-bench -- [threads] [operations per thread]
+//
+// Each thread updates random items, and every tenth operation moves stock between two items
+// atomically.
+
+use color_eyre::eyre::WrapErr;
+use mylib::inventory::sharded::ShardedInventory;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ITEMS: usize = 1000;
+
+/// A small deterministic random number generator, so each run does the same work.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+}
+
+/// Run `op` on `threads` threads, `ops` times each, returning the elapsed time.
+fn run<F>(threads: usize, ops: usize, op: F) -> Duration
+where
+    F: Fn(&mut XorShift, &str, &str, bool) + Sync,
+{
+    let names: Vec<String> = (0..ITEMS).map(|i| format!("item-{i}")).collect();
+    let start = Instant::now();
+    thread::scope(|s| {
+        for t in 0..threads {
+            let (names, op) = (&names, &op);
+            s.spawn(move || {
+                let mut rng = XorShift(t as u64 + 1);
+                for i in 0..ops {
+                    let a = &names[rng.next() % ITEMS];
+                    let b = &names[rng.next() % ITEMS];
+                    op(&mut rng, a, b, i % 10 == 0);
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn report(name: &str, threads: usize, ops: usize, elapsed: Duration) {
+    let per_sec = (threads * ops) as f64 / elapsed.as_secs_f64();
+    println!("{name:<12} {elapsed:>10.2?} {per_sec:>14.0} ops/s");
+}
+
+fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+
+    let mut args = std::env::args().skip(1);
+    let threads = match args.next() {
+        Some(threads) => threads
+            .parse()
+            .wrap_err_with(|| format!("invalid thread count: {threads}"))?,
+        None => thread::available_parallelism().map_or(4, |n| n.get()),
+    };
+    let ops = match args.next() {
+        Some(ops) => ops
+            .parse()
+            .wrap_err_with(|| format!("invalid operation count: {ops}"))?,
+        None => 200_000,
+    };
+    println!("{threads} threads, {ops} operations each, {ITEMS} items");
+
+    for (name, inventory) in [
+        ("global lock", ShardedInventory::new(1)),
+        ("sharded", ShardedInventory::default()),
+    ] {
+        let elapsed = run(threads, ops, |rng, a, b, transfer| {
+            if transfer {
+                inventory.update_many(&[(a, -1), (b, 1)]);
+            } else {
+                inventory.update(a, (rng.next() % 3) as i32 - 1);
+            }
+        });
+        report(name, threads, ops, elapsed);
+    }
+
+    Ok(())
+}
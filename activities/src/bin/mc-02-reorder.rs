@@ -0,0 +1,69 @@
+// Prints a reorder report forecast from a month of simulated sales, then uses the reorder points
+// as low stock alert thresholds.
+//
+// Usage:
+//   cargo run --bin mc-02-reorder -- [lead time days] [safety factor]
+
+use chrono::{Duration, Utc};
+use color_eyre::eyre::eyre;
+use mylib::inventory::alert::{InventoryAlerter, StdoutSink};
+use mylib::inventory::forecast::{ConsumptionTracker, DemandModel, ReorderPolicy};
+use mylib::inventory::{BasicInventory, InventoryManager};
+
+/// Item, starting stock and units sold per day.
+const ITEMS: [(&str, i32, [i32; 7]); 4] = [
+    ("apple", 400, [10, 12, 9, 14, 11, 20, 18]),
+    ("tomato", 200, [3, 4, 2, 5, 3, 6, 4]),
+    ("cilantro", 120, [1, 6, 0, 7, 2, 8, 1]),
+    ("pear", 900, [5, 5, 5, 5, 5, 5, 5]),
+];
+
+fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+
+    let mut args = std::env::args().skip(1);
+    let mut policy = ReorderPolicy::default();
+    if let Some(lead_time) = args.next() {
+        let days = lead_time
+            .parse()
+            .ok()
+            .filter(|days: &f64| days.is_finite() && *days >= 0.0)
+            .ok_or_else(|| eyre!("invalid lead time: {lead_time}"))?;
+        policy = policy.lead_time_days(days);
+    }
+    if let Some(factor) = args.next() {
+        let factor = factor
+            .parse()
+            .ok()
+            .filter(|factor: &f64| factor.is_finite() && *factor >= 0.0)
+            .ok_or_else(|| eyre!("invalid safety factor: {factor}"))?;
+        policy = policy.safety_factor(factor);
+    }
+
+    let now = Utc::now();
+    let mut tracker = ConsumptionTracker::new(BasicInventory::default());
+    for (item, stock, _) in ITEMS {
+        tracker.update_quantity_at(item, stock, now - Duration::days(31));
+    }
+    for day in (0..30).rev() {
+        for (item, _, sales) in ITEMS {
+            let sold = sales[day as usize % sales.len()];
+            tracker.update_quantity_at(item, -sold, now - Duration::days(day));
+        }
+    }
+
+    let model = DemandModel::exponential_smoothing(0.3, 28);
+    let report = tracker.report(model, policy, now);
+    print!("{report}");
+
+    println!();
+    println!("alerts using forecast reorder points:");
+    let thresholds: Vec<_> = tracker.reorder_points(model, policy, now).collect();
+    let mut inventory = InventoryAlerter::new(tracker).with_sink(StdoutSink);
+    for (item, reorder_point) in thresholds {
+        inventory.set_alert_threshold(item.as_str(), reorder_point);
+        // a change of zero checks the item against its new threshold
+        inventory.update_quantity(item, 0);
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+// Compares a stock take with the system's quantities and optionally applies the corrections.
+//
+// Usage:
+//   cargo run --bin mc-02-stocktake -- <system.csv> <counted.csv> [--apply <journal>]
+//
+// Both files have an `item,quantity` header. With `--apply`, the system file is rewritten with
+// the counted quantities and the adjustments are appended to the journal file, one JSON entry per
+// line. The journal is created if it doesn't exist yet.
+
+use color_eyre::eyre::{eyre, WrapErr};
+use mylib::inventory::csv::{self, Reconciliation};
+use mylib::inventory::journal::{Journal, JournaledInventory};
+use mylib::inventory::BasicInventory;
+use std::path::Path;
+
+const USAGE: &str = "usage: mc-02-stocktake <system.csv> <counted.csv> [--apply <journal>]";
+
+fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (system, counted, journal) = match args.as_slice() {
+        [system, counted] => (system, counted, None),
+        [system, counted, flag, journal] if flag == "--apply" => (system, counted, Some(journal)),
+        _ => return Err(eyre!(USAGE)),
+    };
+
+    let mut inventory = BasicInventory::default();
+    let system_csv =
+        std::fs::read_to_string(system).wrap_err_with(|| format!("failed to read {system}"))?;
+    csv::import(&mut inventory, &system_csv)
+        .wrap_err_with(|| format!("failed to load {system}"))?;
+    let counts = csv::parse_file(counted).wrap_err_with(|| format!("failed to load {counted}"))?;
+
+    let reconciliation = Reconciliation::new(&inventory, &counts);
+    print!("{reconciliation}");
+
+    if let Some(journal) = journal {
+        let history = if Path::new(journal).exists() {
+            Journal::load(journal).wrap_err_with(|| format!("failed to load {journal}"))?
+        } else {
+            Journal::default()
+        };
+        let mut inventory = JournaledInventory::resume(inventory, history);
+        let actor = std::env::var("USER").unwrap_or_else(|_| "stocktake".to_owned());
+        let applied = reconciliation.apply(&mut inventory, &actor);
+        // the journal is saved first, so the system file never changes without a record of why
+        inventory
+            .journal()
+            .save(journal)
+            .wrap_err_with(|| format!("failed to write {journal}"))?;
+        csv::export_file(inventory.inner(), system)
+            .wrap_err_with(|| format!("failed to write {system}"))?;
+        println!("applied {applied} adjustments to {system}");
+    }
+    Ok(())
+}
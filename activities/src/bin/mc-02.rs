@@ -17,34 +17,8 @@
 //     low quantity of apple: 50
 //     low quantity of cilantro: 55
 
-use std::collections::HashMap;
-
-/// Manages the quantity of an inventory.
-pub trait InventoryManager {
-    /// Change the quantity of an item. If the item does not exist, it will be added.
-    fn update_quantity<I: Into<String>>(&mut self, item: I, amount: i32);
-
-    /// Returns the total quantity of an item, if the item was found.
-    fn get_quantity<I: AsRef<str>>(&self, item: I) -> Option<i32>;
-}
-
-/// An in-memory inventory manager backed by a hashmap.
-#[derive(Debug, Default)]
-struct BasicInventory {
-    inventory: HashMap<String, i32>,
-}
-
-impl InventoryManager for BasicInventory {
-    fn update_quantity<I: Into<String>>(&mut self, item: I, amount: i32) {
-        let item = item.into();
-        let entry = self.inventory.entry(item).or_default();
-        *entry += amount;
-    }
-
-    fn get_quantity<I: AsRef<str>>(&self, item: I) -> Option<i32> {
-        self.inventory.get(item.as_ref()).copied()
-    }
-}
+use mylib::inventory::alert::{InventoryAlerter, StdoutSink};
+use mylib::inventory::{BasicInventory, InventoryManager};
 
 /***********************************************************************************************
 * Do not edit this function. It should work with the structure that you create without having to
@@ -62,7 +36,7 @@ fn main() {
     /******************************************************
      * Change the below line to create your proxy structure
      ******************************************************/
-    let mut inventory = BasicInventory::default();
+    let mut inventory = InventoryAlerter::new(BasicInventory::default()).with_sink(StdoutSink);
 
     /***********************************************************************************************
      * Do not change anything else in this function. When implemented correctly, you should get 2
@@ -83,3 +57,40 @@ fn main() {
     // should have an alert
     change_quantity(&mut inventory, "cilantro", -6);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mylib::inventory::sharded::ShardedInventory;
+    use mylib::inventory::warehouse::MultiWarehouseInventory;
+    use std::thread;
+
+    #[test]
+    fn change_quantity_updates_the_default_location() {
+        let mut inventory = MultiWarehouseInventory::new("shop");
+        change_quantity(&mut inventory, "apple", 10);
+        change_quantity(&mut inventory, String::from("apple"), -3);
+
+        assert_eq!(inventory.quantity_at("shop", "apple"), Some(7));
+        assert_eq!(inventory.get_quantity("apple"), Some(7));
+    }
+
+    #[test]
+    fn change_quantity_updates_a_shared_inventory_from_many_threads() {
+        let inventory = ShardedInventory::new(4);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let mut shared = &inventory;
+                    for i in 0..100 {
+                        change_quantity(&mut shared, format!("item-{}", i % 5), 1);
+                    }
+                });
+            }
+        });
+
+        for i in 0..5 {
+            assert_eq!(inventory.quantity(&format!("item-{i}")), Some(80));
+        }
+    }
+}
@@ -18,6 +18,10 @@
 //   Adjusted balance by -$30.00. New balance: $120.00
 //   Adjusted balance by $20.00. New balance: $140.00
 
+use mylib::bank::money::{Currency, Money};
+use mylib::bank::policy::WithdrawalPolicy;
+use mylib::bank::{self, AccountError};
+
 /**********************************************
 * Do not change
 **********************************************/
@@ -53,6 +57,76 @@ impl Account for BankAccount {
     }
 }
 
+trait AccountExt {
+    fn withdraw(&mut self, amount: f64);
+    fn deposit(&mut self, amount: f64);
+}
+
+impl<A: Account> AccountExt for A {
+    fn withdraw(&mut self, amount: f64) {
+        self.adjust(-amount);
+    }
+
+    fn deposit(&mut self, amount: f64) {
+        self.adjust(amount);
+    }
+}
+
+/// An account kept in [`Money`] on a ledger, with withdrawals checked against a
+/// [`WithdrawalPolicy`].
+///
+/// Amounts are parsed from decimal strings such as `"30.00"`, so they are exact instead of
+/// rounded from an `f64`.
+struct LedgerAccount(bank::BankAccount);
+
+impl LedgerAccount {
+    fn new(initial_balance: &str, policy: WithdrawalPolicy) -> Result<Self, AccountError> {
+        let account = bank::BankAccount::new(dollars(initial_balance)?);
+        Ok(LedgerAccount(account.with_policy(policy)))
+    }
+
+    fn balance(&self) -> Money {
+        self.0.balance()
+    }
+
+    fn withdraw(&mut self, amount: &str) -> Result<(), AccountError> {
+        let amount = dollars(amount)?;
+        bank::AccountExt::withdraw(&mut self.0, amount)?;
+        self.report(amount.checked_neg()?);
+        Ok(())
+    }
+
+    fn deposit(&mut self, amount: &str) -> Result<(), AccountError> {
+        let amount = dollars(amount)?;
+        bank::AccountExt::deposit(&mut self.0, amount)?;
+        self.report(amount);
+        Ok(())
+    }
+
+    fn report(&self, change: Money) {
+        println!(
+            "Adjusted balance by {change}. New balance: {}",
+            self.balance()
+        );
+    }
+}
+
+fn dollars(amount: &str) -> Result<Money, AccountError> {
+    Ok(Money::parse(amount, Currency::USD)?)
+}
+
+/// The exercise's operations on a [`LedgerAccount`], plus a withdrawal its policy refuses.
+fn ledger_account() -> Result<(), AccountError> {
+    let mut account = LedgerAccount::new("100.00", WithdrawalPolicy::default())?;
+    account.deposit("50.00")?;
+    account.withdraw("30.00")?;
+    account.deposit("20.00")?;
+    if let Err(e) = account.withdraw("1000.00") {
+        println!("Couldn't withdraw $1000.00: {e}");
+    }
+    Ok(())
+}
+
 /**********************************************
 * Do not change
 **********************************************/
@@ -67,4 +141,31 @@ fn main() {
 
     // Using the extended deposit method to deposit money
     account.deposit(20.0);
+
+    // The same again, in exact amounts of money
+    if let Err(e) = ledger_account() {
+        println!("Ledger account failed: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ledger_accounts_check_withdrawals() {
+        let mut account = LedgerAccount::new("100.00", WithdrawalPolicy::default()).unwrap();
+        account.deposit("0.10").unwrap();
+        account.deposit("0.20").unwrap();
+        account.withdraw("30.00").unwrap();
+        assert_eq!(account.balance(), dollars("70.30").unwrap());
+
+        // no overdraft is allowed, so this is refused and the balance is unchanged
+        assert!(matches!(
+            account.withdraw("100.00"),
+            Err(AccountError::InsufficientFunds { .. })
+        ));
+        assert_eq!(account.balance(), dollars("70.30").unwrap());
+        assert!(account.deposit("ten").is_err());
+    }
 }
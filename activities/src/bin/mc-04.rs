@@ -21,14 +21,70 @@
 // - The `.max()` method on iterators won't work for f64. Consider writing a `for` loop and
 //   manually track the highest temperature, or use `.fold`
 
-fn main() {}
+use mylib::sensor::TemperatureSensor;
+
+fn main() {
+    let mut sensor = TemperatureSensor::new();
+    for temperature in [21.5, 22.0, 22.8, 23.1, 22.4, 21.9, 24.3, 23.6] {
+        sensor.record_temperature(temperature);
+    }
+
+    if let Some(average) = sensor.get_average_temperature() {
+        println!("average: {average:.2}");
+    }
+    if let Some(max) = sensor.get_max_temperature() {
+        println!("max: {max:.2}");
+    }
+    if let Some(p95) = sensor.percentile(0.95) {
+        println!("95th percentile: {p95:.2}");
+    }
+    if let Some(ewma) = sensor.ewma() {
+        println!("moving average: {ewma:.2}");
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn sensor_with(temperatures: &[f64]) -> TemperatureSensor {
+        let mut sensor = TemperatureSensor::new();
+        for &temperature in temperatures {
+            sensor.record_temperature(temperature);
+        }
+        sensor
+    }
+
+    #[test]
+    fn no_readings() {
+        let sensor = TemperatureSensor::new();
+        assert_eq!(sensor.count(), 0);
+        assert_eq!(sensor.get_average_temperature(), None);
+        assert_eq!(sensor.get_max_temperature(), None);
+    }
+
+    #[test]
+    fn records_temperatures() {
+        let sensor = sensor_with(&[20.0, 25.0]);
+        assert_eq!(sensor.count(), 2);
+    }
+
+    #[test]
+    fn average_temperature() {
+        let sensor = sensor_with(&[20.0, 25.0, 30.0]);
+        assert_eq!(sensor.get_average_temperature(), Some(25.0));
+    }
+
+    #[test]
+    fn max_temperature() {
+        let sensor = sensor_with(&[20.0, 31.5, -4.0, 25.0]);
+        assert_eq!(sensor.get_max_temperature(), Some(31.5));
+    }
+
     #[test]
-    fn feature() {
-        todo!()
+    fn ignores_readings_which_arent_numbers() {
+        let sensor = sensor_with(&[20.0, f64::NAN, f64::INFINITY]);
+        assert_eq!(sensor.count(), 1);
+        assert_eq!(sensor.get_max_temperature(), Some(20.0));
     }
 }
@@ -0,0 +1,54 @@
+//! Inventory management: tracking the quantity of each item along with the wrappers that add
+//! behavior on top, such as low stock alerts.
+
+pub mod alert;
+pub mod csv;
+pub mod forecast;
+pub mod journal;
+pub mod reservation;
+pub mod sharded;
+pub mod warehouse;
+
+use std::collections::HashMap;
+
+/// Manages the quantity of an inventory.
+pub trait InventoryManager {
+    /// Change the quantity of an item. If the item does not exist, it will be added.
+    fn update_quantity<I: Into<String>>(&mut self, item: I, amount: i32);
+
+    /// Returns the total quantity of an item, if the item was found.
+    fn get_quantity<I: AsRef<str>>(&self, item: I) -> Option<i32>;
+}
+
+/// An inventory which can list everything it holds, such as for exporting it.
+pub trait ListItems: InventoryManager {
+    /// Returns every item along with its total quantity, in no particular order.
+    fn items(&self) -> Vec<(String, i32)>;
+}
+
+/// An in-memory inventory manager backed by a hashmap.
+#[derive(Debug, Default)]
+pub struct BasicInventory {
+    inventory: HashMap<String, i32>,
+}
+
+impl InventoryManager for BasicInventory {
+    fn update_quantity<I: Into<String>>(&mut self, item: I, amount: i32) {
+        let item = item.into();
+        let entry = self.inventory.entry(item).or_default();
+        *entry += amount;
+    }
+
+    fn get_quantity<I: AsRef<str>>(&self, item: I) -> Option<i32> {
+        self.inventory.get(item.as_ref()).copied()
+    }
+}
+
+impl ListItems for BasicInventory {
+    fn items(&self) -> Vec<(String, i32)> {
+        self.inventory
+            .iter()
+            .map(|(item, quantity)| (item.clone(), *quantity))
+            .collect()
+    }
+}
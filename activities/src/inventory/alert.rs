@@ -0,0 +1,510 @@
+//! Low stock alerts.
+//!
+//! [`InventoryAlerter`] wraps another [`InventoryManager`] and watches every change. When an
+//! item's quantity reaches or falls below its threshold a [`LowStock`] event is sent to each
+//! registered [`AlertSink`]. The alert won't fire again for that item until its quantity climbs
+//! back above the recovery level, so a run of small sales doesn't produce a flood of alerts.
+
+use super::{InventoryManager, ListItems};
+use chrono::{DateTime, Utc};
+use crossbeam_channel::{Receiver, Sender};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The threshold used for items that haven't been given one.
+pub const DEFAULT_THRESHOLD: i32 = 50;
+
+/// How many undelivered alert errors are kept until [`InventoryAlerter::take_errors`] is called.
+pub const MAX_PENDING_ERRORS: usize = 100;
+
+/// An item's quantity has reached or fallen below its alert threshold.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LowStock {
+    pub item: String,
+    pub quantity: i32,
+    pub threshold: i32,
+    pub at: DateTime<Utc>,
+}
+
+impl fmt::Display for LowStock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Low quantity of {}: {}", self.item, self.quantity)
+    }
+}
+
+/// Errors that may occur while delivering an alert.
+#[derive(Debug, thiserror::Error)]
+pub enum AlertError {
+    #[error("IO error")]
+    IO(#[from] io::Error),
+
+    #[error("failed to encode alert")]
+    Format(#[from] serde_json::Error),
+
+    #[error("HTTP request failed")]
+    Http(#[from] reqwest::Error),
+
+    #[error("alert receiver was dropped")]
+    Disconnected,
+}
+
+/// Somewhere to deliver low stock alerts.
+pub trait AlertSink {
+    fn send(&mut self, alert: &LowStock) -> Result<(), AlertError>;
+}
+
+/// Prints alerts to stdout.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdoutSink;
+
+impl AlertSink for StdoutSink {
+    fn send(&mut self, alert: &LowStock) -> Result<(), AlertError> {
+        println!("{alert}");
+        Ok(())
+    }
+}
+
+/// Sends alerts over a channel, for handling on another thread.
+#[derive(Debug, Clone)]
+pub struct ChannelSink {
+    sender: Sender<LowStock>,
+}
+
+impl ChannelSink {
+    /// Create a sink which sends alerts to `sender`.
+    pub fn new(sender: Sender<LowStock>) -> Self {
+        Self { sender }
+    }
+
+    /// Create a sink along with the receiving end of its channel.
+    pub fn unbounded() -> (Self, Receiver<LowStock>) {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        (Self::new(sender), receiver)
+    }
+}
+
+impl AlertSink for ChannelSink {
+    fn send(&mut self, alert: &LowStock) -> Result<(), AlertError> {
+        self.sender
+            .send(alert.clone())
+            .map_err(|_| AlertError::Disconnected)
+    }
+}
+
+/// Appends alerts to a file as JSON, one per line.
+#[derive(Debug)]
+pub struct FileSink {
+    file: File,
+}
+
+impl FileSink {
+    /// Open the file at `path` for appending, creating it if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+}
+
+impl AlertSink for FileSink {
+    fn send(&mut self, alert: &LowStock) -> Result<(), AlertError> {
+        let mut line = serde_json::to_vec(alert)?;
+        line.push(b'\n');
+        // a single write keeps lines whole when several processes append to the same file
+        self.file.write_all(&line)?;
+        Ok(())
+    }
+}
+
+/// Collects alerts in memory. Clones share the same list, so keep one to inspect in tests.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    alerts: Arc<Mutex<Vec<LowStock>>>,
+}
+
+impl MemorySink {
+    /// Alerts received so far.
+    pub fn alerts(&self) -> Vec<LowStock> {
+        self.alerts.lock().clone()
+    }
+}
+
+impl AlertSink for MemorySink {
+    fn send(&mut self, alert: &LowStock) -> Result<(), AlertError> {
+        self.alerts.lock().push(alert.clone());
+        Ok(())
+    }
+}
+
+/// Posts alerts as JSON to an HTTP endpoint, such as a local stand-in for a paging service.
+#[derive(Debug, Clone)]
+pub struct HttpSink {
+    client: reqwest::blocking::Client,
+    url: String,
+}
+
+impl HttpSink {
+    /// Create a sink which posts to `url`.
+    pub fn new<U: Into<String>>(url: U) -> Self {
+        Self {
+            client: reqwest::blocking::Client::new(),
+            url: url.into(),
+        }
+    }
+}
+
+impl AlertSink for HttpSink {
+    fn send(&mut self, alert: &LowStock) -> Result<(), AlertError> {
+        self.client
+            .post(&self.url)
+            .json(alert)
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// When an item alerts, and when it may alert again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlertLevel {
+    threshold: i32,
+    recovery: i32,
+}
+
+impl AlertLevel {
+    /// Alert at `threshold` and re-arm as soon as the quantity is back above it.
+    pub fn new(threshold: i32) -> Self {
+        Self {
+            threshold,
+            recovery: threshold,
+        }
+    }
+
+    /// Alert at `threshold` and re-arm once the quantity rises above `recovery`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `recovery` is below `threshold`, since the alert would re-arm while the stock
+    /// is still low and fire on every change.
+    pub fn with_recovery(threshold: i32, recovery: i32) -> Self {
+        assert!(
+            recovery >= threshold,
+            "recovery level {recovery} is below the alert threshold {threshold}"
+        );
+        Self {
+            threshold,
+            recovery,
+        }
+    }
+
+    /// Alert when the quantity reaches or falls below this.
+    pub fn threshold(&self) -> i32 {
+        self.threshold
+    }
+
+    /// Re-arm the alert once the quantity rises above this.
+    pub fn recovery(&self) -> i32 {
+        self.recovery
+    }
+}
+
+/// Sends a [`LowStock`] alert when the quantity of an item gets low.
+pub struct InventoryAlerter<M> {
+    inventory: M,
+    levels: HashMap<String, AlertLevel>,
+    default_level: AlertLevel,
+    /// Items which have alerted and haven't recovered yet.
+    alerted: HashSet<String>,
+    sinks: Vec<Box<dyn AlertSink>>,
+    /// The most recent delivery errors, oldest first.
+    errors: VecDeque<AlertError>,
+    dropped_errors: usize,
+}
+
+impl<M> InventoryAlerter<M> {
+    /// Wrap `inventory`. Alerts go nowhere until a sink is added.
+    pub fn new(inventory: M) -> Self {
+        Self {
+            inventory,
+            levels: HashMap::new(),
+            default_level: AlertLevel::new(DEFAULT_THRESHOLD),
+            alerted: HashSet::new(),
+            sinks: Vec::new(),
+            errors: VecDeque::new(),
+            dropped_errors: 0,
+        }
+    }
+
+    /// Deliver alerts to `sink` as well as any sinks already added.
+    pub fn with_sink<S: AlertSink + 'static>(mut self, sink: S) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Use `level` for items that haven't been given their own.
+    pub fn with_default_level(mut self, level: AlertLevel) -> Self {
+        self.default_level = level;
+        self
+    }
+
+    /// Set the threshold for an item, re-arming as soon as the quantity is back above it.
+    pub fn set_alert_threshold<I: Into<String>>(&mut self, item: I, threshold: i32) {
+        self.set_alert_level(item, AlertLevel::new(threshold));
+    }
+
+    /// Set the threshold and recovery level for an item.
+    pub fn set_alert_level<I: Into<String>>(&mut self, item: I, level: AlertLevel) {
+        self.levels.insert(item.into(), level);
+    }
+
+    /// The alert level used for an item.
+    pub fn alert_level<I: AsRef<str>>(&self, item: I) -> AlertLevel {
+        self.levels
+            .get(item.as_ref())
+            .copied()
+            .unwrap_or(self.default_level)
+    }
+
+    /// Take the errors from sinks that failed to deliver an alert, oldest first.
+    ///
+    /// A failing sink doesn't stop the inventory from changing, or other sinks from being told.
+    /// Only the latest [`MAX_PENDING_ERRORS`] are kept between calls.
+    pub fn take_errors(&mut self) -> Vec<AlertError> {
+        self.dropped_errors = 0;
+        std::mem::take(&mut self.errors).into()
+    }
+
+    /// The number of errors discarded since [`take_errors`](Self::take_errors) was last called,
+    /// because more than [`MAX_PENDING_ERRORS`] piled up.
+    pub fn dropped_errors(&self) -> usize {
+        self.dropped_errors
+    }
+
+    /// The wrapped inventory.
+    pub fn inner(&self) -> &M {
+        &self.inventory
+    }
+
+    /// Unwrap the inventory.
+    pub fn into_inner(self) -> M {
+        self.inventory
+    }
+}
+
+impl<M: InventoryManager> InventoryAlerter<M> {
+    fn check(&mut self, item: String) {
+        let Some(quantity) = self.inventory.get_quantity(&item) else {
+            return;
+        };
+        let level = self.alert_level(&item);
+        if quantity > level.recovery {
+            self.alerted.remove(&item);
+        } else if quantity <= level.threshold && !self.alerted.contains(&item) {
+            let alert = LowStock {
+                item: item.clone(),
+                quantity,
+                threshold: level.threshold,
+                at: Utc::now(),
+            };
+            for sink in &mut self.sinks {
+                if let Err(e) = sink.send(&alert) {
+                    if self.errors.len() == MAX_PENDING_ERRORS {
+                        self.errors.pop_front();
+                        self.dropped_errors += 1;
+                    }
+                    self.errors.push_back(e);
+                }
+            }
+            self.alerted.insert(item);
+        }
+    }
+}
+
+impl<M: InventoryManager> InventoryManager for InventoryAlerter<M> {
+    fn update_quantity<I: Into<String>>(&mut self, item: I, amount: i32) {
+        let item = item.into();
+        self.inventory.update_quantity(item.as_str(), amount);
+        self.check(item);
+    }
+
+    fn get_quantity<I: AsRef<str>>(&self, item: I) -> Option<i32> {
+        self.inventory.get_quantity(item)
+    }
+}
+
+impl<M: ListItems> ListItems for InventoryAlerter<M> {
+    fn items(&self) -> Vec<(String, i32)> {
+        self.inventory.items()
+    }
+}
+
+impl<M: fmt::Debug> fmt::Debug for InventoryAlerter<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InventoryAlerter")
+            .field("inventory", &self.inventory)
+            .field("levels", &self.levels)
+            .field("default_level", &self.default_level)
+            .field("alerted", &self.alerted)
+            .field("sinks", &self.sinks.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::BasicInventory;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn quantities(sink: &MemorySink) -> Vec<(String, i32)> {
+        sink.alerts()
+            .into_iter()
+            .map(|alert| (alert.item, alert.quantity))
+            .collect()
+    }
+
+    #[test]
+    fn alerts_at_threshold() {
+        let sink = MemorySink::default();
+        let mut inventory =
+            InventoryAlerter::new(BasicInventory::default()).with_sink(sink.clone());
+
+        inventory.update_quantity("apple", 50);
+        inventory.update_quantity("tomato", 120);
+        inventory.update_quantity("cilantro", 60);
+        inventory.set_alert_threshold("cilantro", 55);
+        inventory.update_quantity("cilantro", -6);
+
+        assert_eq!(
+            quantities(&sink),
+            vec![("apple".into(), 50), ("cilantro".into(), 54)]
+        );
+        assert_eq!(sink.alerts()[0].to_string(), "Low quantity of apple: 50");
+    }
+
+    #[test]
+    fn hysteresis_stops_repeat_alerts() {
+        let sink = MemorySink::default();
+        let mut inventory =
+            InventoryAlerter::new(BasicInventory::default()).with_sink(sink.clone());
+        inventory.set_alert_level("pear", AlertLevel::with_recovery(10, 20));
+
+        inventory.update_quantity("pear", 10); // alert
+        inventory.update_quantity("pear", -1); // still low, already alerted
+        inventory.update_quantity("pear", 8); // 17: above threshold but not recovered
+        inventory.update_quantity("pear", -8); // 9: no alert
+        inventory.update_quantity("pear", 12); // 21: recovered
+        inventory.update_quantity("pear", -15); // 6: alert
+
+        assert_eq!(
+            quantities(&sink),
+            vec![("pear".into(), 10), ("pear".into(), 6)]
+        );
+    }
+
+    #[test]
+    fn failing_sinks_are_reported() {
+        let (channel, receiver) = ChannelSink::unbounded();
+        drop(receiver);
+        let sink = MemorySink::default();
+        let mut inventory = InventoryAlerter::new(BasicInventory::default())
+            .with_sink(channel)
+            .with_sink(sink.clone());
+
+        inventory.update_quantity("apple", 1);
+
+        assert_eq!(inventory.get_quantity("apple"), Some(1));
+        assert_eq!(sink.alerts().len(), 1);
+        let errors = inventory.take_errors();
+        assert!(matches!(errors.as_slice(), [AlertError::Disconnected]));
+    }
+
+    #[test]
+    fn keeps_only_the_latest_errors() {
+        let (channel, receiver) = ChannelSink::unbounded();
+        drop(receiver);
+        let mut inventory = InventoryAlerter::new(BasicInventory::default()).with_sink(channel);
+
+        for i in 0..MAX_PENDING_ERRORS + 5 {
+            inventory.update_quantity(format!("item-{i}"), 1);
+        }
+        assert_eq!(inventory.dropped_errors(), 5);
+        assert_eq!(inventory.take_errors().len(), MAX_PENDING_ERRORS);
+        assert_eq!(inventory.dropped_errors(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "below the alert threshold")]
+    fn recovery_below_threshold_is_rejected() {
+        AlertLevel::with_recovery(20, 10);
+    }
+
+    #[test]
+    fn delivers_to_channel_and_file() {
+        let path = std::env::temp_dir().join(format!("inventory-alerts-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let (channel, receiver) = ChannelSink::unbounded();
+        let mut inventory = InventoryAlerter::new(BasicInventory::default())
+            .with_sink(channel)
+            .with_sink(FileSink::open(&path).unwrap());
+        inventory.update_quantity("apple", 5);
+        inventory.update_quantity("plum", 7);
+
+        let sent: Vec<_> = receiver.try_iter().collect();
+        let written: Vec<LowStock> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(sent.len(), 2);
+        assert_eq!(written, sent);
+    }
+
+    #[test]
+    fn posts_to_http_endpoint() {
+        // a stand-in for the alerting service which answers a single request
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end().to_ascii_lowercase();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(length) = line.strip_prefix("content-length:") {
+                    content_length = length.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            serde_json::from_slice::<LowStock>(&body).unwrap()
+        });
+
+        let mut inventory =
+            InventoryAlerter::new(BasicInventory::default()).with_sink(HttpSink::new(url));
+        inventory.update_quantity("apple", 3);
+
+        assert!(inventory.take_errors().is_empty());
+        let received = server.join().unwrap();
+        assert_eq!((received.item.as_str(), received.quantity), ("apple", 3));
+    }
+}
@@ -0,0 +1,317 @@
+//! CSV import and export, and reconciling the system against a stock take.
+//!
+//! Files have an `item,quantity` header followed by one record per item, so they can be edited in
+//! a spreadsheet. Fields containing commas or quotes are quoted.
+
+use super::journal::JournaledInventory;
+use super::{InventoryManager, ListItems};
+use crate::util::csv::{csv_field, parse_csv, SyntaxError};
+use crate::util::fs::write_atomic;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+const HEADER: [&str; 2] = ["item", "quantity"];
+
+/// Errors that may occur while reading or writing inventory CSV files.
+#[derive(Debug, thiserror::Error)]
+pub enum CsvError {
+    #[error("IO error")]
+    IO(#[from] io::Error),
+
+    #[error("expected an `item,quantity` header")]
+    BadHeader,
+
+    #[error("record {record}: {reason}")]
+    Malformed { record: usize, reason: String },
+}
+
+impl From<SyntaxError> for CsvError {
+    fn from(value: SyntaxError) -> Self {
+        let (SyntaxError::StrayQuote { record } | SyntaxError::Unterminated { record }) = value;
+        malformed(record, value.to_string())
+    }
+}
+
+fn malformed<R: Into<String>>(record: usize, reason: R) -> CsvError {
+    CsvError::Malformed {
+        record,
+        reason: reason.into(),
+    }
+}
+
+/// Encode the quantity of every item, sorted by name.
+pub fn export<M: ListItems>(inventory: &M) -> String {
+    let mut items = inventory.items();
+    items.sort();
+    let mut csv = format!("{}\n", HEADER.join(","));
+    for (item, quantity) in items {
+        csv.push_str(&format!("{},{quantity}\n", csv_field(&item)));
+    }
+    csv
+}
+
+/// Write the quantity of every item to the file at `path`.
+///
+/// The file is replaced atomically, so a crash part way through leaves the previous file intact.
+pub fn export_file<M: ListItems, P: AsRef<Path>>(inventory: &M, path: P) -> Result<(), CsvError> {
+    write_atomic(path.as_ref(), export(inventory).as_bytes())?;
+    Ok(())
+}
+
+/// Decode item quantities.
+pub fn parse(csv: &str) -> Result<BTreeMap<String, i32>, CsvError> {
+    let records = parse_numbered(csv)?;
+    Ok(records
+        .into_iter()
+        .map(|(item, (_, quantity))| (item, quantity))
+        .collect())
+}
+
+/// Decode item quantities along with the number of the record each one came from.
+fn parse_numbered(csv: &str) -> Result<BTreeMap<String, (usize, i32)>, CsvError> {
+    let mut records = parse_csv(csv)?.into_iter();
+    match records.next() {
+        Some(header) if header == HEADER => (),
+        _ => return Err(CsvError::BadHeader),
+    }
+
+    let mut quantities = BTreeMap::new();
+    for (i, record) in records.enumerate() {
+        let record_no = i + 1;
+        let [item, quantity] = record.as_slice() else {
+            return Err(malformed(
+                record_no,
+                format!("expected 2 fields, found {}", record.len()),
+            ));
+        };
+        if item.is_empty() {
+            return Err(malformed(record_no, "missing item"));
+        }
+        let quantity = quantity
+            .trim()
+            .parse()
+            .map_err(|_| malformed(record_no, format!("invalid quantity {quantity:?}")))?;
+        if quantities
+            .insert(item.clone(), (record_no, quantity))
+            .is_some()
+        {
+            return Err(malformed(
+                record_no,
+                format!("{item} is listed more than once"),
+            ));
+        }
+    }
+    Ok(quantities)
+}
+
+/// Read item quantities from the file at `path`.
+pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<BTreeMap<String, i32>, CsvError> {
+    parse(&std::fs::read_to_string(path)?)
+}
+
+/// Set the quantity of every item listed in `csv`, returning how many items were listed.
+///
+/// The whole file is checked before anything changes, so a bad record leaves the inventory
+/// untouched. Items which aren't listed keep their quantity.
+pub fn import<M: InventoryManager>(inventory: &mut M, csv: &str) -> Result<usize, CsvError> {
+    let records = parse_numbered(csv)?;
+    let mut changes = Vec::new();
+    for (item, &(record, quantity)) in &records {
+        let current = inventory.get_quantity(item).unwrap_or(0);
+        let change = quantity.checked_sub(current).ok_or_else(|| {
+            malformed(
+                record,
+                format!("changing {item} from {current} to {quantity} overflows"),
+            )
+        })?;
+        if change != 0 {
+            changes.push((item, change));
+        }
+    }
+    for (item, change) in changes {
+        inventory.update_quantity(item.as_str(), change);
+    }
+    Ok(records.len())
+}
+
+/// An item whose counted quantity doesn't match the system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Discrepancy {
+    pub item: String,
+    pub system: i32,
+    pub counted: i32,
+}
+
+impl Discrepancy {
+    /// The change needed to bring the system in line with the count.
+    pub fn adjustment(&self) -> i32 {
+        self.counted - self.system
+    }
+}
+
+/// The result of comparing a stock take with the system.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reconciliation {
+    /// Items whose counts differ, sorted by name.
+    pub discrepancies: Vec<Discrepancy>,
+    /// Number of items which were counted.
+    pub counted: usize,
+}
+
+impl Reconciliation {
+    /// Compare counted quantities with the system. Items which weren't counted are ignored.
+    pub fn new<M: InventoryManager>(inventory: &M, counts: &BTreeMap<String, i32>) -> Self {
+        let discrepancies = counts
+            .iter()
+            .filter_map(|(item, &counted)| {
+                let system = inventory.get_quantity(item).unwrap_or(0);
+                (system != counted).then(|| Discrepancy {
+                    item: item.clone(),
+                    system,
+                    counted,
+                })
+            })
+            .collect();
+        Self {
+            discrepancies,
+            counted: counts.len(),
+        }
+    }
+
+    /// Returns `true` if every counted item matches the system.
+    pub fn is_balanced(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+
+    /// Correct every discrepancy with a journaled adjustment made by `actor`, returning how
+    /// many adjustments were made.
+    pub fn apply<M: InventoryManager>(
+        &self,
+        inventory: &mut JournaledInventory<M>,
+        actor: &str,
+    ) -> usize {
+        for discrepancy in &self.discrepancies {
+            let reason = format!(
+                "stock take: counted {}, system had {}",
+                discrepancy.counted, discrepancy.system
+            );
+            inventory.record(
+                actor,
+                discrepancy.item.as_str(),
+                discrepancy.adjustment(),
+                reason,
+            );
+        }
+        self.discrepancies.len()
+    }
+}
+
+impl fmt::Display for Reconciliation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_balanced() {
+            return writeln!(f, "all {} counted items match the system", self.counted);
+        }
+        writeln!(
+            f,
+            "{} of {} counted items differ:",
+            self.discrepancies.len(),
+            self.counted
+        )?;
+        writeln!(
+            f,
+            "{:<16} {:>8} {:>8} {:>8}",
+            "item", "system", "counted", "change"
+        )?;
+        for d in &self.discrepancies {
+            writeln!(
+                f,
+                "{:<16} {:>8} {:>8} {:>+8}",
+                d.item,
+                d.system,
+                d.counted,
+                d.adjustment()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::BasicInventory;
+
+    #[test]
+    fn export_then_import_round_trips() {
+        let mut inventory = BasicInventory::default();
+        inventory.update_quantity("pear", 3);
+        inventory.update_quantity("apple, red", 10);
+
+        let csv = export(&inventory);
+        assert_eq!(csv, "item,quantity\n\"apple, red\",10\npear,3\n");
+
+        let mut imported = BasicInventory::default();
+        imported.update_quantity("pear", 7);
+        assert_eq!(import(&mut imported, &csv).unwrap(), 2);
+        assert_eq!(imported.get_quantity("apple, red"), Some(10));
+        assert_eq!(imported.get_quantity("pear"), Some(3));
+    }
+
+    #[test]
+    fn bad_files_change_nothing() {
+        let mut inventory = BasicInventory::default();
+        let csv = "item,quantity\napple,5\npear,lots\n";
+        assert!(matches!(
+            import(&mut inventory, csv),
+            Err(CsvError::Malformed { record: 2, .. })
+        ));
+        assert_eq!(inventory.get_quantity("apple"), None);
+
+        assert!(matches!(parse("name,count\n"), Err(CsvError::BadHeader)));
+        assert!(matches!(
+            parse("item,quantity\n\"apple,1\n"),
+            Err(CsvError::Malformed { record: 1, .. })
+        ));
+        assert!(matches!(
+            parse("item,quantity\napple,1\napple,2\n"),
+            Err(CsvError::Malformed { record: 2, .. })
+        ));
+        // the quantity fits, but the change from the current quantity doesn't
+        inventory.update_quantity("pear", -10);
+        let csv = format!("item,quantity\napple,5\npear,{}\n", i32::MAX);
+        assert!(matches!(
+            import(&mut inventory, &csv),
+            Err(CsvError::Malformed { record: 2, .. })
+        ));
+        assert_eq!(inventory.get_quantity("apple"), None);
+        assert_eq!(inventory.get_quantity("pear"), Some(-10));
+    }
+
+    #[test]
+    fn reconciliation_reports_and_journals_corrections() {
+        let mut inventory = JournaledInventory::new(BasicInventory::default());
+        inventory.update_quantity("apple", 10);
+        inventory.update_quantity("pear", 4);
+        inventory.update_quantity("plum", 2);
+
+        let counts = parse("item,quantity\napple,8\npear,4\nkiwi,1\n").unwrap();
+        let reconciliation = Reconciliation::new(&inventory, &counts);
+        let changes: Vec<_> = reconciliation
+            .discrepancies
+            .iter()
+            .map(|d| (d.item.as_str(), d.adjustment()))
+            .collect();
+        assert_eq!(changes, vec![("apple", -2), ("kiwi", 1)]);
+        assert!(reconciliation
+            .to_string()
+            .contains("2 of 3 counted items differ"));
+
+        assert_eq!(reconciliation.apply(&mut inventory, "auditor"), 2);
+        assert!(Reconciliation::new(&inventory, &counts).is_balanced());
+        let last = inventory.journal().entries().last().unwrap();
+        assert_eq!(last.actor, "auditor");
+        assert_eq!(last.reason, "stock take: counted 1, system had 0");
+    }
+}
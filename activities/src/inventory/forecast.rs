@@ -0,0 +1,486 @@
+//! Reorder points forecast from consumption history.
+//!
+//! [`ConsumptionTracker`] wraps another [`InventoryManager`] and adds up the decreases in
+//! quantity on each day. From that history it estimates daily demand for each item, then works
+//! out how low stock can get before an order must be placed to arrive in time:
+//!
+//! ```text
+//! reorder point  = daily demand * lead time + safety stock
+//! safety stock   = safety factor * std dev of daily demand * sqrt(lead time)
+//! order quantity = reorder point + daily demand * cover days - on hand
+//! ```
+
+use super::{InventoryManager, ListItems};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// How many days of consumption are remembered by default.
+pub const DEFAULT_HISTORY_DAYS: u32 = 90;
+
+/// How daily demand is estimated from past consumption.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DemandModel {
+    smoothing: Option<f64>,
+    days: u32,
+}
+
+impl DemandModel {
+    /// The mean of the last `days` days.
+    pub const fn moving_average(days: u32) -> Self {
+        Self {
+            smoothing: None,
+            days,
+        }
+    }
+
+    /// An exponentially weighted average of the last `days` days, where `alpha` sets how much
+    /// more recent days count.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `alpha` isn't in `(0, 1]`.
+    pub fn exponential_smoothing(alpha: f64, days: u32) -> Self {
+        assert!(
+            alpha > 0.0 && alpha <= 1.0,
+            "smoothing alpha must be in (0, 1], got {alpha}"
+        );
+        Self {
+            smoothing: Some(alpha),
+            days,
+        }
+    }
+
+    /// How many days of consumption the model looks at.
+    pub fn days(&self) -> u32 {
+        self.days
+    }
+
+    /// Estimate demand from daily totals, oldest first.
+    fn estimate(&self, daily: &[f64]) -> f64 {
+        if daily.is_empty() {
+            return 0.0;
+        }
+        match self.smoothing {
+            None => mean(daily),
+            Some(alpha) => daily[1..].iter().fold(daily[0], |smoothed, &x| {
+                alpha * x + (1.0 - alpha) * smoothed
+            }),
+        }
+    }
+}
+
+/// Supplier and stocking settings used to derive reorder points.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReorderPolicy {
+    lead_time_days: f64,
+    safety_factor: f64,
+    cover_days: f64,
+}
+
+impl Default for ReorderPolicy {
+    fn default() -> Self {
+        Self {
+            lead_time_days: 7.0,
+            safety_factor: 1.65,
+            cover_days: 14.0,
+        }
+    }
+}
+
+impl ReorderPolicy {
+    /// Expect `days` between placing an order and receiving it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `days` is negative or not a finite number.
+    pub fn lead_time_days(mut self, days: f64) -> Self {
+        self.lead_time_days = non_negative("lead time", days);
+        self
+    }
+
+    /// Hold `factor` standard deviations of demand as safety stock.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `factor` is negative or not a finite number.
+    pub fn safety_factor(mut self, factor: f64) -> Self {
+        self.safety_factor = non_negative("safety factor", factor);
+        self
+    }
+
+    /// Order enough to cover `days` of demand once the order arrives.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `days` is negative or not a finite number.
+    pub fn cover_days(mut self, days: f64) -> Self {
+        self.cover_days = non_negative("cover", days);
+        self
+    }
+}
+
+fn non_negative(name: &str, value: f64) -> f64 {
+    assert!(
+        value.is_finite() && value >= 0.0,
+        "{name} must be a non-negative number, got {value}"
+    );
+    value
+}
+
+/// The forecast for one item.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Forecast {
+    pub item: String,
+    pub on_hand: i32,
+    pub daily_demand: f64,
+    pub safety_stock: i32,
+    pub reorder_point: i32,
+    /// How many to order now, or 0 if stock is above the reorder point.
+    pub order_quantity: i32,
+}
+
+impl Forecast {
+    /// Returns `true` if stock has reached the reorder point.
+    pub fn needs_reorder(&self) -> bool {
+        self.on_hand <= self.reorder_point
+    }
+}
+
+/// Forecasts for every tracked item, displayed as a table of the items due for reordering.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReorderReport {
+    pub forecasts: Vec<Forecast>,
+}
+
+impl ReorderReport {
+    /// Forecasts for items which need reordering.
+    pub fn due(&self) -> impl Iterator<Item = &Forecast> {
+        self.forecasts.iter().filter(|f| f.needs_reorder())
+    }
+}
+
+impl fmt::Display for ReorderReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.due().next().is_none() {
+            return writeln!(f, "no items are due for reordering");
+        }
+        writeln!(
+            f,
+            "{:<16} {:>8} {:>8} {:>8} {:>8}",
+            "item", "on hand", "demand", "reorder", "order"
+        )?;
+        for forecast in self.due() {
+            writeln!(
+                f,
+                "{:<16} {:>8} {:>8.1} {:>8} {:>8}",
+                forecast.item,
+                forecast.on_hand,
+                forecast.daily_demand,
+                forecast.reorder_point,
+                forecast.order_quantity
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// An inventory which remembers how much of each item was consumed on each day, in UTC.
+///
+/// Only the last [`DEFAULT_HISTORY_DAYS`] days of consumption are remembered, so the history
+/// doesn't grow forever. Forecasts using a [`DemandModel`] over more days than that see no
+/// demand on the older days, so raise the limit with
+/// [`with_history_days`](Self::with_history_days) to match the longest model in use.
+#[derive(Debug)]
+pub struct ConsumptionTracker<M> {
+    inventory: M,
+    consumed: HashMap<String, BTreeMap<NaiveDate, i64>>,
+    history_days: u32,
+}
+
+impl<M: Default> Default for ConsumptionTracker<M> {
+    fn default() -> Self {
+        Self::new(M::default())
+    }
+}
+
+impl<M> ConsumptionTracker<M> {
+    /// Wrap `inventory`.
+    pub fn new(inventory: M) -> Self {
+        Self {
+            inventory,
+            consumed: HashMap::new(),
+            history_days: DEFAULT_HISTORY_DAYS,
+        }
+    }
+
+    /// Remember consumption from the last `days` days, instead of [`DEFAULT_HISTORY_DAYS`].
+    pub fn with_history_days(mut self, days: u32) -> Self {
+        self.history_days = days;
+        self
+    }
+
+    /// Units consumed per day over the `days` days up to `now`, oldest first.
+    pub fn daily_consumption<I: AsRef<str>>(
+        &self,
+        item: I,
+        days: u32,
+        now: DateTime<Utc>,
+    ) -> Vec<f64> {
+        let mut daily = vec![0.0; days as usize];
+        let today = now.date_naive();
+        for (&day, &amount) in self.consumed.get(item.as_ref()).into_iter().flatten() {
+            let age = (today - day).num_days();
+            if (0..days as i64).contains(&age) {
+                daily[days as usize - 1 - age as usize] += amount as f64;
+            }
+        }
+        daily
+    }
+
+    /// The wrapped inventory.
+    pub fn inner(&self) -> &M {
+        &self.inventory
+    }
+
+    /// Unwrap the inventory, dropping the history.
+    pub fn into_inner(self) -> M {
+        self.inventory
+    }
+}
+
+impl<M: InventoryManager> ConsumptionTracker<M> {
+    /// Change the quantity of an item as of `at`, recording it if stock went down.
+    pub fn update_quantity_at<I: Into<String>>(&mut self, item: I, amount: i32, at: DateTime<Utc>) {
+        let item = item.into();
+        self.inventory.update_quantity(item.as_str(), amount);
+        if amount < 0 {
+            let history = self.consumed.entry(item).or_default();
+            *history.entry(at.date_naive()).or_default() -= i64::from(amount);
+            let (&latest, _) = history.last_key_value().expect("a day was just added");
+            let oldest = latest - Duration::days(i64::from(self.history_days) - 1);
+            *history = history.split_off(&oldest);
+        }
+    }
+
+    /// Forecast demand and the reorder point for an item as of `now`.
+    pub fn forecast<I: AsRef<str>>(
+        &self,
+        item: I,
+        model: DemandModel,
+        policy: ReorderPolicy,
+        now: DateTime<Utc>,
+    ) -> Forecast {
+        let item = item.as_ref();
+        let daily = self.daily_consumption(item, model.days(), now);
+        let daily_demand = model.estimate(&daily);
+        let safety_stock = policy.safety_factor * std_dev(&daily) * policy.lead_time_days.sqrt();
+        let reorder_point = daily_demand * policy.lead_time_days + safety_stock;
+
+        let on_hand = self.inventory.get_quantity(item).unwrap_or(0);
+        let reorder_point = reorder_point.ceil() as i32;
+        let order_quantity = if on_hand <= reorder_point {
+            let target = reorder_point as f64 + daily_demand * policy.cover_days;
+            (target.ceil() as i32 - on_hand).max(0)
+        } else {
+            0
+        };
+
+        Forecast {
+            item: item.to_owned(),
+            on_hand,
+            daily_demand,
+            safety_stock: safety_stock.ceil() as i32,
+            reorder_point,
+            order_quantity,
+        }
+    }
+
+    /// Forecast every item that has been consumed, sorted by name.
+    pub fn report(
+        &self,
+        model: DemandModel,
+        policy: ReorderPolicy,
+        now: DateTime<Utc>,
+    ) -> ReorderReport {
+        let items: BTreeMap<_, _> = self
+            .consumed
+            .keys()
+            .map(|item| (item.as_str(), self.forecast(item, model, policy, now)))
+            .collect();
+        ReorderReport {
+            forecasts: items.into_values().collect(),
+        }
+    }
+
+    /// Reorder points for every item that has been consumed, for use as alert thresholds.
+    pub fn reorder_points(
+        &self,
+        model: DemandModel,
+        policy: ReorderPolicy,
+        now: DateTime<Utc>,
+    ) -> impl Iterator<Item = (String, i32)> {
+        self.report(model, policy, now)
+            .forecasts
+            .into_iter()
+            .map(|f| (f.item, f.reorder_point))
+    }
+}
+
+impl<M: InventoryManager> InventoryManager for ConsumptionTracker<M> {
+    fn update_quantity<I: Into<String>>(&mut self, item: I, amount: i32) {
+        self.update_quantity_at(item, amount, Utc::now());
+    }
+
+    fn get_quantity<I: AsRef<str>>(&self, item: I) -> Option<i32> {
+        self.inventory.get_quantity(item)
+    }
+}
+
+impl<M: ListItems> ListItems for ConsumptionTracker<M> {
+    fn items(&self) -> Vec<(String, i32)> {
+        self.inventory.items()
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn std_dev(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return 0.0;
+    }
+    let mean = mean(values);
+    let variance =
+        values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (values.len() - 1) as f64;
+    variance.sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::BasicInventory;
+    use chrono::Duration;
+
+    fn days_ago(now: DateTime<Utc>, days: i64) -> DateTime<Utc> {
+        now - Duration::days(days)
+    }
+
+    const AVERAGE: DemandModel = DemandModel::moving_average(10);
+
+    fn policy() -> ReorderPolicy {
+        ReorderPolicy::default()
+            .lead_time_days(3.0)
+            .safety_factor(2.0)
+            .cover_days(7.0)
+    }
+
+    #[test]
+    fn steady_demand_has_no_safety_stock() {
+        let now = Utc::now();
+        let mut tracker = ConsumptionTracker::new(BasicInventory::default());
+        tracker.update_quantity_at("apple", 100, days_ago(now, 11));
+        tracker.update_quantity_at("pear", 60, days_ago(now, 11));
+        for day in 0..10 {
+            tracker.update_quantity_at("apple", -5, days_ago(now, day));
+            tracker.update_quantity_at("pear", -5, days_ago(now, day));
+        }
+
+        let apple = tracker.forecast("apple", AVERAGE, policy(), now);
+        assert_eq!(apple.daily_demand, 5.0);
+        assert_eq!(apple.safety_stock, 0);
+        assert_eq!(apple.reorder_point, 15);
+        assert!(!apple.needs_reorder());
+        assert_eq!(apple.order_quantity, 0);
+
+        let pear = tracker.forecast("pear", AVERAGE, policy(), now);
+        assert_eq!(pear.on_hand, 10);
+        assert!(pear.needs_reorder());
+        // up to the reorder point plus a week of demand
+        assert_eq!(pear.order_quantity, 15 + 35 - 10);
+    }
+
+    #[test]
+    fn variable_demand_adds_safety_stock() {
+        let now = Utc::now();
+        let mut tracker = ConsumptionTracker::new(BasicInventory::default());
+        for day in 0..10 {
+            let amount = if day % 2 == 0 { -1 } else { -9 };
+            tracker.update_quantity_at("apple", amount, days_ago(now, day));
+        }
+
+        let forecast = tracker.forecast("apple", AVERAGE, policy(), now);
+        assert_eq!(forecast.daily_demand, 5.0);
+        assert!(forecast.safety_stock > 0);
+        assert_eq!(forecast.reorder_point, 15 + forecast.safety_stock);
+    }
+
+    #[test]
+    fn smoothing_favors_recent_demand() {
+        let now = Utc::now();
+        let mut tracker = ConsumptionTracker::new(BasicInventory::default());
+        for day in 0..10 {
+            let amount = if day < 3 { -12 } else { -2 };
+            tracker.update_quantity_at("apple", amount, days_ago(now, day));
+        }
+
+        let smoothing = DemandModel::exponential_smoothing(0.5, 10);
+        let average = tracker.forecast("apple", AVERAGE, policy(), now);
+        let smoothed = tracker.forecast("apple", smoothing, policy(), now);
+        assert_eq!(average.daily_demand, 5.0);
+        assert!(smoothed.daily_demand > 9.0);
+        assert!(smoothed.reorder_point > average.reorder_point);
+    }
+
+    #[test]
+    fn forgets_consumption_older_than_the_history() {
+        let now = Utc::now();
+        let mut tracker = ConsumptionTracker::new(BasicInventory::default()).with_history_days(10);
+        for day in (0..1000).rev() {
+            // several sales a day still take one day of history
+            for _ in 0..5 {
+                tracker.update_quantity_at("apple", -1, days_ago(now, day));
+            }
+        }
+        assert_eq!(tracker.consumed["apple"].len(), 10);
+
+        let forecast = tracker.forecast("apple", AVERAGE, policy(), now);
+        assert_eq!(forecast.daily_demand, 5.0);
+        let longer = DemandModel::moving_average(20);
+        let forecast = tracker.forecast("apple", longer, policy(), now);
+        assert_eq!(forecast.daily_demand, 2.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "lead time must be a non-negative number")]
+    fn rejects_negative_lead_times() {
+        ReorderPolicy::default().lead_time_days(-1.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "alpha must be in (0, 1]")]
+    fn rejects_smoothing_without_a_valid_alpha() {
+        DemandModel::exponential_smoothing(f64::NAN, 10);
+    }
+
+    #[test]
+    fn report_lists_items_due_for_reorder() {
+        let now = Utc::now();
+        let mut tracker = ConsumptionTracker::new(BasicInventory::default());
+        tracker.update_quantity_at("apple", 20, days_ago(now, 11));
+        tracker.update_quantity_at("pear", 500, days_ago(now, 11));
+        for day in 0..10 {
+            tracker.update_quantity_at("apple", -1, days_ago(now, day));
+            tracker.update_quantity_at("pear", -1, days_ago(now, day));
+        }
+        tracker.update_quantity_at("apple", -8, now);
+
+        let report = tracker.report(AVERAGE, policy(), now);
+        assert_eq!(report.forecasts.len(), 2);
+        let due: Vec<_> = report.due().map(|f| f.item.as_str()).collect();
+        assert_eq!(due, vec!["apple"]);
+        let text = report.to_string();
+        assert!(text.contains("apple") && !text.contains("pear"));
+    }
+}
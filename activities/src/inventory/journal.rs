@@ -0,0 +1,459 @@
+//! An audit trail of inventory changes.
+//!
+//! [`JournaledInventory`] wraps another [`InventoryManager`] and records every change in a
+//! [`Journal`]: who made it, when, by how much and why. The journal is append-only. Undoing a
+//! change adds an entry which reverses it, so the trail always shows what really happened.
+
+use super::{InventoryManager, ListItems};
+use crate::util::fs::write_atomic;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+/// The actor recorded for changes made through [`InventoryManager::update_quantity`].
+pub const DEFAULT_ACTOR: &str = "system";
+
+/// Errors that may occur while working with a journal.
+#[derive(Debug, thiserror::Error)]
+pub enum JournalError {
+    #[error("IO error")]
+    IO(#[from] io::Error),
+
+    #[error("malformed journal entry on line {line}")]
+    Format {
+        line: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("journal entry on line {line} is numbered {found}, expected {expected}")]
+    Sequence {
+        line: usize,
+        expected: u64,
+        found: u64,
+    },
+
+    #[error("journal entry {seq} overflows the quantity of {item}")]
+    Overflow { seq: u64, item: String },
+
+    #[error("can't undo {requested} entries, only {available} can be undone")]
+    NothingToUndo { requested: usize, available: usize },
+}
+
+/// A single change to the inventory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Position in the journal, starting from 1.
+    pub seq: u64,
+    pub actor: String,
+    pub at: DateTime<Utc>,
+    pub item: String,
+    pub delta: i32,
+    pub reason: String,
+    /// The entry reversed by this one, if it's an undo.
+    #[serde(default)]
+    pub undoes: Option<u64>,
+}
+
+/// An append-only list of inventory changes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Journal {
+    entries: Vec<JournalEntry>,
+}
+
+impl Journal {
+    /// Every entry, oldest first.
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    /// Entries which changed `item`, oldest first.
+    pub fn history<'a>(&'a self, item: &'a str) -> impl Iterator<Item = &'a JournalEntry> {
+        self.entries.iter().filter(move |entry| entry.item == item)
+    }
+
+    /// The number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if nothing has been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Rebuild the quantity of every item as it was at `time`, starting from an empty inventory.
+    ///
+    /// Fails with [`JournalError::Overflow`] if an item's quantity doesn't fit in an `i32` along
+    /// the way.
+    pub fn quantities_at(
+        &self,
+        time: DateTime<Utc>,
+    ) -> Result<BTreeMap<String, i32>, JournalError> {
+        let mut quantities = BTreeMap::new();
+        for entry in self.entries.iter().filter(|entry| entry.at <= time) {
+            let quantity: &mut i32 = quantities.entry(entry.item.clone()).or_default();
+            *quantity =
+                quantity
+                    .checked_add(entry.delta)
+                    .ok_or_else(|| JournalError::Overflow {
+                        seq: entry.seq,
+                        item: entry.item.clone(),
+                    })?;
+        }
+        Ok(quantities)
+    }
+
+    /// Write the journal to `path` as JSON, one entry per line.
+    ///
+    /// The file is replaced atomically, so a crash part way through leaves the previous journal
+    /// intact rather than a truncated one.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), JournalError> {
+        let mut bytes = Vec::new();
+        for (i, entry) in self.entries.iter().enumerate() {
+            serde_json::to_writer(&mut bytes, entry).map_err(|source| JournalError::Format {
+                line: i + 1,
+                source,
+            })?;
+            bytes.push(b'\n');
+        }
+        write_atomic(path.as_ref(), &bytes)?;
+        Ok(())
+    }
+
+    /// Read a journal written by [`save`](Self::save).
+    ///
+    /// Entries must be numbered 1, 2, 3 and so on, so a journal with entries missing or out of
+    /// order is rejected.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, JournalError> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut entries = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let entry: JournalEntry =
+                serde_json::from_str(&line).map_err(|source| JournalError::Format {
+                    line: i + 1,
+                    source,
+                })?;
+            let expected = entries.len() as u64 + 1;
+            if entry.seq != expected {
+                return Err(JournalError::Sequence {
+                    line: i + 1,
+                    expected,
+                    found: entry.seq,
+                });
+            }
+            entries.push(entry);
+        }
+        Ok(Self { entries })
+    }
+
+    fn push(
+        &mut self,
+        actor: &str,
+        at: DateTime<Utc>,
+        item: String,
+        delta: i32,
+        reason: String,
+        undoes: Option<u64>,
+    ) -> &JournalEntry {
+        self.entries.push(JournalEntry {
+            seq: self.entries.len() as u64 + 1,
+            actor: actor.to_owned(),
+            at,
+            item,
+            delta,
+            reason,
+            undoes,
+        });
+        self.entries.last().expect("an entry was just pushed")
+    }
+
+    /// Entries which can still be undone, newest first.
+    fn undoable(&self) -> impl Iterator<Item = &JournalEntry> {
+        let undone: HashSet<u64> = self.entries.iter().filter_map(|e| e.undoes).collect();
+        self.entries
+            .iter()
+            .rev()
+            .filter(move |entry| entry.undoes.is_none() && !undone.contains(&entry.seq))
+    }
+}
+
+/// An inventory which records every change in a [`Journal`].
+#[derive(Debug, Default)]
+pub struct JournaledInventory<M> {
+    inventory: M,
+    journal: Journal,
+    actor: String,
+}
+
+impl<M> JournaledInventory<M> {
+    /// Wrap `inventory` with an empty journal.
+    pub fn new(inventory: M) -> Self {
+        Self {
+            inventory,
+            journal: Journal::default(),
+            actor: DEFAULT_ACTOR.to_owned(),
+        }
+    }
+
+    /// Wrap `inventory`, which already reflects every entry of `journal`, so new changes are
+    /// recorded after them. Use [`replay`](Self::replay) if the entries still need applying.
+    pub fn resume(inventory: M, journal: Journal) -> Self {
+        Self {
+            journal,
+            ..Self::new(inventory)
+        }
+    }
+
+    /// Record changes made through [`InventoryManager::update_quantity`] as made by `actor`.
+    pub fn with_actor<A: Into<String>>(mut self, actor: A) -> Self {
+        self.actor = actor.into();
+        self
+    }
+
+    /// The journal of every change.
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    /// The wrapped inventory.
+    pub fn inner(&self) -> &M {
+        &self.inventory
+    }
+
+    /// Unwrap the inventory and its journal.
+    pub fn into_parts(self) -> (M, Journal) {
+        (self.inventory, self.journal)
+    }
+}
+
+impl<M: InventoryManager> JournaledInventory<M> {
+    /// Wrap `inventory`, replaying every entry of `journal` into it.
+    pub fn replay(mut inventory: M, journal: Journal) -> Self {
+        for entry in &journal.entries {
+            inventory.update_quantity(entry.item.as_str(), entry.delta);
+        }
+        Self::resume(inventory, journal)
+    }
+
+    /// Change the quantity of an item, recording who made the change and why.
+    pub fn record<A, I, R>(&mut self, actor: A, item: I, delta: i32, reason: R) -> &JournalEntry
+    where
+        A: AsRef<str>,
+        I: Into<String>,
+        R: Into<String>,
+    {
+        self.record_at(actor, item, delta, reason, Utc::now())
+    }
+
+    /// Change the quantity of an item as of `at`.
+    pub fn record_at<A, I, R>(
+        &mut self,
+        actor: A,
+        item: I,
+        delta: i32,
+        reason: R,
+        at: DateTime<Utc>,
+    ) -> &JournalEntry
+    where
+        A: AsRef<str>,
+        I: Into<String>,
+        R: Into<String>,
+    {
+        let item = item.into();
+        self.inventory.update_quantity(item.as_str(), delta);
+        self.journal
+            .push(actor.as_ref(), at, item, delta, reason.into(), None)
+    }
+
+    /// Reverse the last `count` changes which haven't been undone yet, newest first.
+    ///
+    /// Each reversal is recorded as a new entry. Either every change is undone or, if there
+    /// aren't enough to undo, none are.
+    pub fn undo<A: AsRef<str>>(
+        &mut self,
+        actor: A,
+        count: usize,
+    ) -> Result<Vec<JournalEntry>, JournalError> {
+        let targets: Vec<_> = self.journal.undoable().take(count).cloned().collect();
+        if targets.len() < count {
+            return Err(JournalError::NothingToUndo {
+                requested: count,
+                available: targets.len(),
+            });
+        }
+
+        let now = Utc::now();
+        let mut undos = Vec::with_capacity(count);
+        for target in targets {
+            self.inventory
+                .update_quantity(target.item.as_str(), -target.delta);
+            let reason = format!("undo #{}", target.seq);
+            let entry = self.journal.push(
+                actor.as_ref(),
+                now,
+                target.item,
+                -target.delta,
+                reason,
+                Some(target.seq),
+            );
+            undos.push(entry.clone());
+        }
+        Ok(undos)
+    }
+}
+
+impl<M: InventoryManager> InventoryManager for JournaledInventory<M> {
+    fn update_quantity<I: Into<String>>(&mut self, item: I, amount: i32) {
+        let actor = self.actor.clone();
+        self.record(actor, item, amount, "");
+    }
+
+    fn get_quantity<I: AsRef<str>>(&self, item: I) -> Option<i32> {
+        self.inventory.get_quantity(item)
+    }
+}
+
+impl<M: ListItems> ListItems for JournaledInventory<M> {
+    fn items(&self) -> Vec<(String, i32)> {
+        self.inventory.items()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::BasicInventory;
+    use chrono::Duration;
+
+    #[test]
+    fn records_who_changed_what() {
+        let mut inventory = JournaledInventory::new(BasicInventory::default()).with_actor("till-1");
+        inventory.update_quantity("apple", 10);
+        inventory.record("alice", "apple", -3, "damaged");
+
+        let history: Vec<_> = inventory
+            .journal()
+            .history("apple")
+            .map(|e| (e.actor.as_str(), e.delta, e.reason.as_str()))
+            .collect();
+        assert_eq!(history, vec![("till-1", 10, ""), ("alice", -3, "damaged")]);
+        assert_eq!(inventory.get_quantity("apple"), Some(7));
+    }
+
+    #[test]
+    fn rebuilds_past_quantities() {
+        let start = Utc::now();
+        let mut inventory = JournaledInventory::new(BasicInventory::default());
+        inventory.record_at("alice", "apple", 10, "delivery", start);
+        inventory.record_at("bob", "apple", -4, "sale", start + Duration::hours(1));
+        inventory.record_at("bob", "pear", 2, "delivery", start + Duration::hours(2));
+
+        let journal = inventory.journal();
+        assert!(journal
+            .quantities_at(start - Duration::hours(1))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            journal
+                .quantities_at(start + Duration::minutes(90))
+                .unwrap(),
+            BTreeMap::from([("apple".to_owned(), 6)])
+        );
+
+        let mut journal = journal.clone();
+        let at = start + Duration::hours(3);
+        journal.push(
+            "carol",
+            at,
+            "apple".into(),
+            i32::MAX,
+            "miscount".into(),
+            None,
+        );
+        assert!(matches!(
+            journal.quantities_at(at),
+            Err(JournalError::Overflow { seq: 4, item }) if item == "apple"
+        ));
+    }
+
+    #[test]
+    fn undo_reverses_latest_changes() {
+        let mut inventory = JournaledInventory::new(BasicInventory::default());
+        inventory.record("alice", "apple", 10, "delivery");
+        inventory.record("bob", "apple", -4, "sale");
+        inventory.record("bob", "pear", 2, "delivery");
+
+        let undos = inventory.undo("carol", 2).unwrap();
+        assert_eq!(undos[0].undoes, Some(3));
+        assert_eq!(undos[1].undoes, Some(2));
+        assert_eq!(inventory.get_quantity("apple"), Some(10));
+        assert_eq!(inventory.get_quantity("pear"), Some(0));
+
+        // undo entries and entries already undone are skipped
+        inventory.undo("carol", 1).unwrap();
+        assert_eq!(inventory.get_quantity("apple"), Some(0));
+        assert!(matches!(
+            inventory.undo("carol", 1),
+            Err(JournalError::NothingToUndo {
+                requested: 1,
+                available: 0
+            })
+        ));
+        assert_eq!(inventory.journal().len(), 6);
+    }
+
+    #[test]
+    fn saved_journal_replays() {
+        let path = std::env::temp_dir().join(format!("inventory-journal-{}", std::process::id()));
+        let mut inventory = JournaledInventory::new(BasicInventory::default());
+        inventory.record("alice", "apple", 10, "delivery");
+        inventory.record("bob", "apple", -4, "sale, \"express\"");
+        inventory.undo("carol", 1).unwrap();
+        inventory.journal().save(&path).unwrap();
+
+        let journal = Journal::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&journal, inventory.journal());
+
+        let replayed = JournaledInventory::replay(BasicInventory::default(), journal.clone());
+        assert_eq!(replayed.get_quantity("apple"), Some(10));
+
+        let (stock, _) = replayed.into_parts();
+        let mut resumed = JournaledInventory::resume(stock, journal);
+        assert_eq!(resumed.record("dave", "apple", 1, "found").seq, 4);
+        assert_eq!(resumed.get_quantity("apple"), Some(11));
+    }
+
+    #[test]
+    fn load_rejects_missing_entries() {
+        let path = std::env::temp_dir().join(format!("inventory-gaps-{}", std::process::id()));
+        let mut inventory = JournaledInventory::new(BasicInventory::default());
+        for delta in 1..=3 {
+            inventory.record("alice", "apple", delta, "delivery");
+        }
+        inventory.journal().save(&path).unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        // drop the second entry
+        let lines: Vec<_> = saved.lines().collect();
+        std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+
+        let result = Journal::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            result,
+            Err(JournalError::Sequence {
+                line: 2,
+                expected: 2,
+                found: 3
+            })
+        ));
+    }
+}
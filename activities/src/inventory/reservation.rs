@@ -0,0 +1,363 @@
+//! Holding stock for pending orders.
+//!
+//! [`ReservingInventory`] wraps another [`InventoryManager`] and lets orders put a hold on stock
+//! before it's shipped. Held stock can't be reserved again, so the same units are never promised
+//! to two orders. A hold either gets committed, which takes the stock out of the inventory, or
+//! released, which makes it available again. Holds that aren't dealt with in time expire and are
+//! released automatically.
+
+use super::{InventoryManager, ListItems};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeMap, HashMap};
+
+/// Errors that may occur while reserving stock.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ReservationError {
+    #[error("reserved quantity must be positive, got {0}")]
+    InvalidQuantity(i32),
+
+    #[error("only {available} of {item} are available, but {requested} were requested")]
+    InsufficientStock {
+        item: String,
+        available: i32,
+        requested: i32,
+    },
+
+    #[error("no active reservation for order {0}")]
+    UnknownOrder(String),
+}
+
+/// The stock held for one order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reservation {
+    /// Quantity held per item.
+    pub items: BTreeMap<String, i32>,
+    /// The hold is released if it hasn't been committed by this time.
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Reservation {
+    fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at > now
+    }
+}
+
+/// An inventory which can hold stock for pending orders.
+///
+/// As an [`InventoryManager`], quantities are the stock on hand including held units. Use
+/// [`available`](Self::available) for the quantity that can still be sold. Updates through the
+/// trait are applied in full, like in any other inventory, even if they take held stock. Use
+/// [`try_update_quantity`](Self::try_update_quantity) to refuse updates which would.
+#[derive(Debug, Default)]
+pub struct ReservingInventory<M> {
+    inventory: M,
+    reservations: HashMap<String, Reservation>,
+}
+
+impl<M> ReservingInventory<M> {
+    /// Wrap `inventory`.
+    pub fn new(inventory: M) -> Self {
+        Self {
+            inventory,
+            reservations: HashMap::new(),
+        }
+    }
+
+    /// The active reservation for an order, if there is one.
+    pub fn reservation<O: AsRef<str>>(&self, order: O) -> Option<&Reservation> {
+        self.reservation_at(order, Utc::now())
+    }
+
+    /// The reservation for an order that is active at `now`, if there is one.
+    pub fn reservation_at<O: AsRef<str>>(
+        &self,
+        order: O,
+        now: DateTime<Utc>,
+    ) -> Option<&Reservation> {
+        self.reservations
+            .get(order.as_ref())
+            .filter(|reservation| reservation.is_active(now))
+    }
+
+    /// The quantity of an item held by active reservations at `now`.
+    pub fn held_at<I: AsRef<str>>(&self, item: I, now: DateTime<Utc>) -> i32 {
+        self.reservations
+            .values()
+            .filter(|reservation| reservation.is_active(now))
+            .filter_map(|reservation| reservation.items.get(item.as_ref()))
+            .sum()
+    }
+
+    /// Release every reservation that expired by `now`, returning how many were released.
+    pub fn release_expired_at(&mut self, now: DateTime<Utc>) -> usize {
+        let before = self.reservations.len();
+        self.reservations
+            .retain(|_, reservation| reservation.is_active(now));
+        before - self.reservations.len()
+    }
+
+    /// Release an order's reservation, making its stock available again.
+    pub fn release<O: AsRef<str>>(&mut self, order: O) -> Result<Reservation, ReservationError> {
+        self.release_at(order, Utc::now())
+    }
+
+    /// Release an order's reservation as of `now`.
+    pub fn release_at<O: AsRef<str>>(
+        &mut self,
+        order: O,
+        now: DateTime<Utc>,
+    ) -> Result<Reservation, ReservationError> {
+        self.release_expired_at(now);
+        self.reservations
+            .remove(order.as_ref())
+            .ok_or_else(|| ReservationError::UnknownOrder(order.as_ref().to_owned()))
+    }
+
+    /// The wrapped inventory.
+    pub fn inner(&self) -> &M {
+        &self.inventory
+    }
+
+    /// Unwrap the inventory, dropping every reservation.
+    pub fn into_inner(self) -> M {
+        self.inventory
+    }
+}
+
+impl<M: InventoryManager> ReservingInventory<M> {
+    /// The quantity of an item that isn't held for any order.
+    pub fn available<I: AsRef<str>>(&self, item: I) -> i32 {
+        self.available_at(item, Utc::now())
+    }
+
+    /// The quantity of an item that isn't held for any order at `now`.
+    pub fn available_at<I: AsRef<str>>(&self, item: I, now: DateTime<Utc>) -> i32 {
+        let on_hand = self.inventory.get_quantity(item.as_ref()).unwrap_or(0);
+        on_hand - self.held_at(item, now)
+    }
+
+    /// Change the quantity of an item, failing if that would take away held stock.
+    pub fn try_update_quantity<I: Into<String>>(
+        &mut self,
+        item: I,
+        amount: i32,
+    ) -> Result<(), ReservationError> {
+        self.try_update_quantity_at(item, amount, Utc::now())
+    }
+
+    /// Change the quantity of an item as of `now`, failing if that would take away held stock.
+    pub fn try_update_quantity_at<I: Into<String>>(
+        &mut self,
+        item: I,
+        amount: i32,
+        now: DateTime<Utc>,
+    ) -> Result<(), ReservationError> {
+        let item = item.into();
+        if amount < 0 {
+            let available = self.available_at(&item, now);
+            if available.saturating_add(amount) < 0 {
+                return Err(ReservationError::InsufficientStock {
+                    item,
+                    available,
+                    requested: amount.saturating_neg(),
+                });
+            }
+        }
+        self.inventory.update_quantity(item, amount);
+        Ok(())
+    }
+
+    /// Hold `quantity` of an item for an order until `ttl` has passed.
+    ///
+    /// Reserving more for an order that already has a reservation adds to it and pushes back its
+    /// expiry.
+    pub fn reserve<O, I>(
+        &mut self,
+        order: O,
+        item: I,
+        quantity: i32,
+        ttl: Duration,
+    ) -> Result<(), ReservationError>
+    where
+        O: Into<String>,
+        I: Into<String>,
+    {
+        self.reserve_at(order, item, quantity, ttl, Utc::now())
+    }
+
+    /// Hold `quantity` of an item for an order, as of `now`.
+    pub fn reserve_at<O, I>(
+        &mut self,
+        order: O,
+        item: I,
+        quantity: i32,
+        ttl: Duration,
+        now: DateTime<Utc>,
+    ) -> Result<(), ReservationError>
+    where
+        O: Into<String>,
+        I: Into<String>,
+    {
+        let item = item.into();
+        if quantity <= 0 {
+            return Err(ReservationError::InvalidQuantity(quantity));
+        }
+        self.release_expired_at(now);
+
+        let available = self.available_at(&item, now);
+        if quantity > available {
+            return Err(ReservationError::InsufficientStock {
+                item,
+                available,
+                requested: quantity,
+            });
+        }
+
+        let expires_at = now + ttl;
+        let reservation = self
+            .reservations
+            .entry(order.into())
+            .or_insert_with(|| Reservation {
+                items: BTreeMap::new(),
+                expires_at,
+            });
+        reservation.expires_at = expires_at;
+        *reservation.items.entry(item).or_default() += quantity;
+        Ok(())
+    }
+
+    /// Take an order's reserved stock out of the inventory.
+    pub fn commit<O: AsRef<str>>(&mut self, order: O) -> Result<Reservation, ReservationError> {
+        self.commit_at(order, Utc::now())
+    }
+
+    /// Take an order's reserved stock out of the inventory, as of `now`.
+    pub fn commit_at<O: AsRef<str>>(
+        &mut self,
+        order: O,
+        now: DateTime<Utc>,
+    ) -> Result<Reservation, ReservationError> {
+        let reservation = self.release_at(order, now)?;
+        for (item, quantity) in &reservation.items {
+            self.inventory.update_quantity(item.as_str(), -quantity);
+        }
+        Ok(reservation)
+    }
+}
+
+impl<M: InventoryManager> InventoryManager for ReservingInventory<M> {
+    fn update_quantity<I: Into<String>>(&mut self, item: I, amount: i32) {
+        self.inventory.update_quantity(item, amount);
+    }
+
+    fn get_quantity<I: AsRef<str>>(&self, item: I) -> Option<i32> {
+        self.inventory.get_quantity(item)
+    }
+}
+
+impl<M: ListItems> ListItems for ReservingInventory<M> {
+    fn items(&self) -> Vec<(String, i32)> {
+        self.inventory.items()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inventory::BasicInventory;
+
+    fn inventory(apples: i32) -> ReservingInventory<BasicInventory> {
+        let mut inventory = ReservingInventory::new(BasicInventory::default());
+        inventory.update_quantity("apple", apples);
+        inventory
+    }
+
+    #[test]
+    fn holds_reduce_available_stock() {
+        let now = Utc::now();
+        let ttl = Duration::minutes(10);
+        let mut inventory = inventory(10);
+
+        inventory
+            .reserve_at("order-1", "apple", 6, ttl, now)
+            .unwrap();
+        assert_eq!(inventory.available_at("apple", now), 4);
+        assert_eq!(
+            inventory.reserve_at("order-2", "apple", 5, ttl, now),
+            Err(ReservationError::InsufficientStock {
+                item: "apple".into(),
+                available: 4,
+                requested: 5,
+            })
+        );
+
+        inventory.release_at("order-1", now).unwrap();
+        inventory
+            .reserve_at("order-2", "apple", 5, ttl, now)
+            .unwrap();
+        inventory.commit_at("order-2", now).unwrap();
+
+        assert_eq!(inventory.get_quantity("apple"), Some(5));
+        assert_eq!(inventory.available_at("apple", now), 5);
+        assert_eq!(
+            inventory.commit_at("order-2", now),
+            Err(ReservationError::UnknownOrder("order-2".into()))
+        );
+    }
+
+    #[test]
+    fn expired_holds_are_released() {
+        let now = Utc::now();
+        let later = now + Duration::minutes(11);
+        let mut inventory = inventory(10);
+
+        inventory
+            .reserve_at("order-1", "apple", 8, Duration::minutes(10), now)
+            .unwrap();
+        assert_eq!(inventory.available_at("apple", later), 10);
+        assert!(inventory.reservation_at("order-1", later).is_none());
+
+        inventory
+            .reserve_at("order-2", "apple", 9, Duration::minutes(10), later)
+            .unwrap();
+        assert!(matches!(
+            inventory.commit_at("order-1", later),
+            Err(ReservationError::UnknownOrder(_))
+        ));
+        assert_eq!(inventory.get_quantity("apple"), Some(10));
+    }
+
+    #[test]
+    fn checked_updates_cannot_take_held_stock() {
+        let now = Utc::now();
+        let mut inventory = inventory(10);
+        inventory
+            .reserve_at("order-1", "apple", 6, Duration::minutes(10), now)
+            .unwrap();
+
+        assert_eq!(
+            inventory.try_update_quantity_at("apple", -5, now),
+            Err(ReservationError::InsufficientStock {
+                item: "apple".into(),
+                available: 4,
+                requested: 5,
+            })
+        );
+        inventory.try_update_quantity_at("apple", -1, now).unwrap();
+        assert_eq!(inventory.get_quantity("apple"), Some(9));
+
+        // through the trait, the whole update is applied
+        inventory.update_quantity("apple", -5);
+        assert_eq!(inventory.get_quantity("apple"), Some(4));
+        assert_eq!(inventory.available_at("apple", now), -2);
+    }
+
+    #[test]
+    fn rejects_non_positive_quantities() {
+        let mut inventory = inventory(10);
+        assert_eq!(
+            inventory.reserve("order-1", "apple", 0, Duration::minutes(1)),
+            Err(ReservationError::InvalidQuantity(0))
+        );
+    }
+}
@@ -0,0 +1,298 @@
+//! A thread-safe inventory.
+//!
+//! [`ShardedInventory`] spreads items over a number of shards, each behind its own lock, so
+//! threads working on different items rarely wait for each other. Updates which touch several
+//! items lock every shard involved in index order, which makes them atomic without any risk of
+//! two updates deadlocking.
+
+use super::{InventoryManager, ListItems};
+use parking_lot::{Mutex, MutexGuard};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+
+/// The number of shards used by [`ShardedInventory::default`].
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// An error returned when an update would leave an item with negative stock.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("only {available} of {item} are in stock, but {requested} were requested")]
+pub struct InsufficientStock {
+    pub item: String,
+    pub available: i32,
+    pub requested: i32,
+}
+
+/// An inventory which can be shared between threads.
+///
+/// Every method takes `&self`. [`InventoryManager`] is implemented for `&ShardedInventory` as
+/// well, so code written against the trait can update a shared inventory from several threads.
+/// Quantities stop at the limits of `i32` instead of overflowing.
+#[derive(Debug)]
+pub struct ShardedInventory {
+    shards: Vec<Mutex<HashMap<String, i32>>>,
+    hasher: RandomState,
+}
+
+impl Default for ShardedInventory {
+    fn default() -> Self {
+        Self::new(DEFAULT_SHARDS)
+    }
+}
+
+impl ShardedInventory {
+    /// Create an empty inventory with `shards` shards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is 0.
+    pub fn new(shards: usize) -> Self {
+        assert!(shards > 0, "a sharded inventory needs at least one shard");
+        Self {
+            shards: (0..shards).map(|_| Mutex::default()).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn shard_of(&self, item: &str) -> usize {
+        self.hasher.hash_one(item) as usize % self.shards.len()
+    }
+
+    /// Change the quantity of an item. If the item does not exist, it will be added.
+    pub fn update(&self, item: &str, amount: i32) {
+        add(&mut self.shards[self.shard_of(item)].lock(), item, amount);
+    }
+
+    /// Returns the quantity of an item, if the item was found.
+    pub fn quantity(&self, item: &str) -> Option<i32> {
+        self.shards[self.shard_of(item)].lock().get(item).copied()
+    }
+
+    /// Lock every shard holding one of `items`, in index order.
+    fn lock_shards<'a, I>(&self, items: I) -> LockedShards<'_>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut indexes: Vec<_> = items.into_iter().map(|item| self.shard_of(item)).collect();
+        indexes.sort_unstable();
+        indexes.dedup();
+        let guards = indexes
+            .into_iter()
+            .map(|index| (index, self.shards[index].lock()))
+            .collect();
+        LockedShards {
+            inventory: self,
+            guards,
+        }
+    }
+
+    /// Apply several changes at once. No other thread sees some changes without the rest.
+    pub fn update_many(&self, changes: &[(&str, i32)]) {
+        let mut shards = self.lock_shards(changes.iter().map(|(item, _)| *item));
+        for &(item, amount) in changes {
+            shards.add(item, amount);
+        }
+    }
+
+    /// Apply several changes at once, unless one would leave an item with negative stock, in
+    /// which case nothing changes.
+    ///
+    /// An item may appear more than once; its changes are combined.
+    pub fn try_update_many(&self, changes: &[(&str, i32)]) -> Result<(), InsufficientStock> {
+        let mut shards = self.lock_shards(changes.iter().map(|(item, _)| *item));
+
+        let mut combined: Vec<(&str, i32)> = Vec::with_capacity(changes.len());
+        for &(item, amount) in changes {
+            match combined.iter_mut().find(|(seen, _)| *seen == item) {
+                Some((_, total)) => *total = total.saturating_add(amount),
+                None => combined.push((item, amount)),
+            }
+        }
+        for &(item, amount) in &combined {
+            let available = shards.get(item);
+            if available.saturating_add(amount) < 0 {
+                return Err(InsufficientStock {
+                    item: item.to_owned(),
+                    available,
+                    requested: amount.saturating_neg(),
+                });
+            }
+        }
+
+        for (item, amount) in combined {
+            shards.add(item, amount);
+        }
+        Ok(())
+    }
+
+    /// Every item along with its quantity.
+    ///
+    /// Shards are read one at a time, so concurrent multi-item updates may be seen half applied.
+    pub fn snapshot(&self) -> Vec<(String, i32)> {
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                let shard = shard.lock();
+                shard
+                    .iter()
+                    .map(|(item, quantity)| (item.clone(), *quantity))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
+
+/// The shards locked for a multi-item update, in index order.
+struct LockedShards<'a> {
+    inventory: &'a ShardedInventory,
+    guards: Vec<(usize, MutexGuard<'a, HashMap<String, i32>>)>,
+}
+
+impl LockedShards<'_> {
+    fn shard(&mut self, item: &str) -> &mut HashMap<String, i32> {
+        let index = self.inventory.shard_of(item);
+        let pos = self
+            .guards
+            .binary_search_by_key(&index, |(index, _)| *index)
+            .expect("shard is locked");
+        &mut self.guards[pos].1
+    }
+
+    fn get(&mut self, item: &str) -> i32 {
+        self.shard(item).get(item).copied().unwrap_or(0)
+    }
+
+    fn add(&mut self, item: &str, amount: i32) {
+        add(self.shard(item), item, amount);
+    }
+}
+
+/// Add to an item's quantity, only allocating its name the first time it's seen.
+fn add(shard: &mut HashMap<String, i32>, item: &str, amount: i32) {
+    match shard.get_mut(item) {
+        Some(quantity) => *quantity = quantity.saturating_add(amount),
+        None => {
+            shard.insert(item.to_owned(), amount);
+        }
+    }
+}
+
+impl InventoryManager for ShardedInventory {
+    fn update_quantity<I: Into<String>>(&mut self, item: I, amount: i32) {
+        self.update(&item.into(), amount);
+    }
+
+    fn get_quantity<I: AsRef<str>>(&self, item: I) -> Option<i32> {
+        self.quantity(item.as_ref())
+    }
+}
+
+impl ListItems for ShardedInventory {
+    fn items(&self) -> Vec<(String, i32)> {
+        self.snapshot()
+    }
+}
+
+impl InventoryManager for &ShardedInventory {
+    fn update_quantity<I: Into<String>>(&mut self, item: I, amount: i32) {
+        self.update(&item.into(), amount);
+    }
+
+    fn get_quantity<I: AsRef<str>>(&self, item: I) -> Option<i32> {
+        self.quantity(item.as_ref())
+    }
+}
+
+impl ListItems for &ShardedInventory {
+    fn items(&self) -> Vec<(String, i32)> {
+        self.snapshot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn updates_from_many_threads() {
+        let inventory = ShardedInventory::new(4);
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    let mut inventory = &inventory;
+                    for i in 0..1000 {
+                        inventory.update_quantity(format!("item-{}", i % 10), 1);
+                    }
+                });
+            }
+        });
+
+        for i in 0..10 {
+            assert_eq!(inventory.quantity(&format!("item-{i}")), Some(800));
+        }
+        assert_eq!(inventory.items().len(), 10);
+    }
+
+    #[test]
+    fn multi_item_updates_are_atomic() {
+        let items = ["a", "b", "c", "d", "e"];
+        let inventory = ShardedInventory::new(3);
+        for item in items {
+            inventory.update(item, 1000);
+        }
+
+        // move stock around in opposite orders; a deadlock would hang the test
+        thread::scope(|s| {
+            for t in 0..8 {
+                let inventory = &inventory;
+                s.spawn(move || {
+                    for i in 0..2000 {
+                        let from = items[(i + t) % items.len()];
+                        let to = items[(i * 3 + t + 1) % items.len()];
+                        let _ = inventory.try_update_many(&[(from, -1), (to, 1)]);
+                    }
+                });
+            }
+        });
+
+        let total: i32 = inventory.snapshot().iter().map(|(_, q)| q).sum();
+        assert_eq!(total, 5000);
+    }
+
+    #[test]
+    fn failed_multi_item_update_changes_nothing() {
+        let inventory = ShardedInventory::default();
+        inventory.update("apple", 5);
+        inventory.update("pear", 1);
+
+        assert_eq!(
+            inventory.try_update_many(&[("apple", -2), ("pear", -1), ("pear", -1)]),
+            Err(InsufficientStock {
+                item: "pear".into(),
+                available: 1,
+                requested: 2,
+            })
+        );
+        assert_eq!(inventory.quantity("apple"), Some(5));
+        assert_eq!(inventory.quantity("pear"), Some(1));
+    }
+
+    #[test]
+    fn quantities_never_overflow() {
+        let inventory = ShardedInventory::default();
+        inventory.update("apple", i32::MAX);
+        inventory.update("apple", 1);
+        assert_eq!(inventory.quantity("apple"), Some(i32::MAX));
+
+        inventory.update("pear", 1);
+        assert!(inventory
+            .try_update_many(&[("pear", i32::MIN), ("pear", -1)])
+            .is_err());
+        inventory
+            .try_update_many(&[("apple", i32::MAX), ("pear", -1)])
+            .unwrap();
+        assert_eq!(inventory.quantity("apple"), Some(i32::MAX));
+        assert_eq!(inventory.quantity("pear"), Some(0));
+    }
+}
@@ -0,0 +1,267 @@
+//! Inventory spread across several locations.
+
+use super::{InventoryManager, ListItems};
+use std::collections::HashMap;
+
+/// The location used by [`MultiWarehouseInventory::default`].
+pub const DEFAULT_LOCATION: &str = "main";
+
+/// Errors that may occur while changing stock or moving it between locations.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum TransferError {
+    #[error("transfer amount must be positive, got {0}")]
+    InvalidAmount(i32),
+
+    #[error("can't transfer {item} from {location} to itself")]
+    SameLocation { location: String, item: String },
+
+    #[error("{location} has {available} of {item}, but {requested} were requested")]
+    InsufficientStock {
+        location: String,
+        item: String,
+        available: i32,
+        requested: i32,
+    },
+
+    #[error("changing {item} at {location} by {amount} would overflow its quantity")]
+    Overflow {
+        location: String,
+        item: String,
+        amount: i32,
+    },
+}
+
+/// Tracks the quantity of each item per location.
+///
+/// As an [`InventoryManager`], updates apply to the default location and quantities are the
+/// total across every location. The trait can't report errors, so through it quantities stop at
+/// the limits of `i32` instead of overflowing.
+#[derive(Debug)]
+pub struct MultiWarehouseInventory {
+    locations: HashMap<String, HashMap<String, i32>>,
+    default_location: String,
+}
+
+impl Default for MultiWarehouseInventory {
+    fn default() -> Self {
+        Self::new(DEFAULT_LOCATION)
+    }
+}
+
+impl MultiWarehouseInventory {
+    /// Create an empty inventory where untargeted updates go to `default_location`.
+    pub fn new<L: Into<String>>(default_location: L) -> Self {
+        Self {
+            locations: HashMap::new(),
+            default_location: default_location.into(),
+        }
+    }
+
+    /// The location updated by [`update_quantity`](InventoryManager::update_quantity).
+    pub fn default_location(&self) -> &str {
+        &self.default_location
+    }
+
+    /// Change the quantity of an item at one location.
+    ///
+    /// Nothing changes if the new quantity wouldn't fit in an `i32`.
+    pub fn update_at<L, I>(
+        &mut self,
+        location: L,
+        item: I,
+        amount: i32,
+    ) -> Result<(), TransferError>
+    where
+        L: Into<String>,
+        I: Into<String>,
+    {
+        let (location, item) = (location.into(), item.into());
+        let current = self.quantity_at(&location, &item).unwrap_or(0);
+        let Some(quantity) = current.checked_add(amount) else {
+            return Err(TransferError::Overflow {
+                location,
+                item,
+                amount,
+            });
+        };
+        *self.entry(location, item) = quantity;
+        Ok(())
+    }
+
+    fn entry(&mut self, location: String, item: String) -> &mut i32 {
+        self.locations
+            .entry(location)
+            .or_default()
+            .entry(item)
+            .or_default()
+    }
+
+    /// Returns the quantity of an item at one location, if the item was found there.
+    pub fn quantity_at<L, I>(&self, location: L, item: I) -> Option<i32>
+    where
+        L: AsRef<str>,
+        I: AsRef<str>,
+    {
+        self.locations
+            .get(location.as_ref())?
+            .get(item.as_ref())
+            .copied()
+    }
+
+    /// Every item stocked at a location along with its quantity.
+    pub fn items_at<L: AsRef<str>>(&self, location: L) -> impl Iterator<Item = (&str, i32)> {
+        self.locations
+            .get(location.as_ref())
+            .into_iter()
+            .flatten()
+            .map(|(item, quantity)| (item.as_str(), *quantity))
+    }
+
+    /// Every location which has stocked an item.
+    pub fn locations(&self) -> impl Iterator<Item = &str> {
+        self.locations.keys().map(String::as_str)
+    }
+
+    /// Move `amount` of an item from one location to another.
+    ///
+    /// Either both locations are updated or, if the transfer fails, neither is.
+    pub fn transfer<I: AsRef<str>>(
+        &mut self,
+        from: &str,
+        to: &str,
+        item: I,
+        amount: i32,
+    ) -> Result<(), TransferError> {
+        let item = item.as_ref();
+        if amount <= 0 {
+            return Err(TransferError::InvalidAmount(amount));
+        }
+        if from == to {
+            return Err(TransferError::SameLocation {
+                location: from.to_owned(),
+                item: item.to_owned(),
+            });
+        }
+        let available = self.quantity_at(from, item).unwrap_or(0);
+        if available < amount {
+            return Err(TransferError::InsufficientStock {
+                location: from.to_owned(),
+                item: item.to_owned(),
+                available,
+                requested: amount,
+            });
+        }
+        // check the destination first, so a failure leaves both locations as they were
+        self.update_at(to, item, amount)?;
+        self.update_at(from, item, -amount)
+            .expect("the source holds at least the amount moved");
+        Ok(())
+    }
+}
+
+impl InventoryManager for MultiWarehouseInventory {
+    fn update_quantity<I: Into<String>>(&mut self, item: I, amount: i32) {
+        let location = self.default_location.clone();
+        let quantity = self.entry(location, item.into());
+        *quantity = quantity.saturating_add(amount);
+    }
+
+    fn get_quantity<I: AsRef<str>>(&self, item: I) -> Option<i32> {
+        let item = item.as_ref();
+        self.locations
+            .values()
+            .filter_map(|items| items.get(item))
+            .copied()
+            .reduce(i32::saturating_add)
+    }
+}
+
+impl ListItems for MultiWarehouseInventory {
+    fn items(&self) -> Vec<(String, i32)> {
+        let mut totals: HashMap<&str, i32> = HashMap::new();
+        for (item, quantity) in self.locations.values().flatten() {
+            let total = totals.entry(item).or_default();
+            *total = total.saturating_add(*quantity);
+        }
+        totals
+            .into_iter()
+            .map(|(item, quantity)| (item.to_owned(), quantity))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totals_quantities_across_locations() {
+        let mut inventory = MultiWarehouseInventory::default();
+        inventory.update_quantity("apple", 10);
+        inventory.update_at("north", "apple", 5).unwrap();
+        inventory.update_at("north", "pear", 3).unwrap();
+
+        assert_eq!(inventory.get_quantity("apple"), Some(15));
+        assert_eq!(inventory.quantity_at(DEFAULT_LOCATION, "apple"), Some(10));
+        assert_eq!(inventory.quantity_at("north", "pear"), Some(3));
+        assert_eq!(inventory.get_quantity("plum"), None);
+
+        let mut north: Vec<_> = inventory.items_at("north").collect();
+        north.sort();
+        assert_eq!(north, vec![("apple", 5), ("pear", 3)]);
+    }
+
+    #[test]
+    fn transfers_move_stock_or_fail_without_changes() {
+        let mut inventory = MultiWarehouseInventory::default();
+        inventory.update_at("north", "apple", 5).unwrap();
+
+        inventory.transfer("north", "south", "apple", 3).unwrap();
+        assert_eq!(inventory.quantity_at("north", "apple"), Some(2));
+        assert_eq!(inventory.quantity_at("south", "apple"), Some(3));
+
+        assert_eq!(
+            inventory.transfer("north", "south", "apple", 4),
+            Err(TransferError::InsufficientStock {
+                location: "north".into(),
+                item: "apple".into(),
+                available: 2,
+                requested: 4,
+            })
+        );
+        assert_eq!(
+            inventory.transfer("north", "south", "apple", 0),
+            Err(TransferError::InvalidAmount(0))
+        );
+        assert!(inventory.transfer("north", "north", "apple", 1).is_err());
+        assert_eq!(inventory.quantity_at("north", "apple"), Some(2));
+        assert_eq!(inventory.get_quantity("apple"), Some(5));
+    }
+
+    #[test]
+    fn quantities_never_overflow() {
+        let mut inventory = MultiWarehouseInventory::default();
+        inventory.update_at("north", "apple", i32::MAX).unwrap();
+        inventory.update_at("south", "apple", 1).unwrap();
+
+        assert_eq!(
+            inventory.update_at("north", "apple", 1),
+            Err(TransferError::Overflow {
+                location: "north".into(),
+                item: "apple".into(),
+                amount: 1,
+            })
+        );
+        assert!(inventory.transfer("south", "north", "apple", 1).is_err());
+        assert_eq!(inventory.quantity_at("south", "apple"), Some(1));
+
+        // the totals and the trait can't report errors, so they stop at the limit
+        assert_eq!(inventory.get_quantity("apple"), Some(i32::MAX));
+        inventory.update_quantity("apple", i32::MAX);
+        inventory.update_quantity("apple", i32::MAX);
+        assert_eq!(
+            inventory.quantity_at(DEFAULT_LOCATION, "apple"),
+            Some(i32::MAX)
+        );
+    }
+}
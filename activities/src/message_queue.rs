@@ -0,0 +1,631 @@
+//! A message queue with priority lanes, delayed delivery and expiry, along with the storage
+//! backends used to persist it.
+
+pub mod admin;
+pub mod broker;
+pub mod client;
+pub mod formats;
+pub mod payload;
+pub mod protocol;
+pub mod reliable;
+pub mod server;
+pub mod shared;
+pub mod wal;
+
+use crate::util::fs::write_atomic;
+use chrono::{DateTime, Duration, Utc};
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// How urgently a message should be delivered.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Urgent,
+}
+
+impl Priority {
+    /// The lowercase name of the priority.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An error returned when parsing an unknown priority name.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+#[error("unknown priority: {0}")]
+pub struct ParsePriorityError(String);
+
+impl std::str::FromStr for Priority {
+    type Err = ParsePriorityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "low" => Ok(Priority::Low),
+            "normal" => Ok(Priority::Normal),
+            "high" => Ok(Priority::High),
+            "urgent" => Ok(Priority::Urgent),
+            _ => Err(ParsePriorityError(s.to_owned())),
+        }
+    }
+}
+
+/// A message in the queue.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Message {
+    pub id: u32,
+    pub content: String,
+    #[serde(default)]
+    pub priority: Priority,
+    /// The message is not delivered before this time.
+    #[serde(default)]
+    pub not_before: Option<DateTime<Utc>>,
+    /// The message is discarded if it hasn't been delivered by this time.
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Metadata such as the content type or a correlation id. See [`payload::headers`] for the
+    /// well-known names.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Message {
+    /// Create a new message.
+    pub fn new<S: Into<String>>(id: u32, content: S) -> Self {
+        Self {
+            id,
+            content: content.into(),
+            priority: Priority::default(),
+            not_before: None,
+            expires_at: None,
+            headers: BTreeMap::new(),
+        }
+    }
+
+    /// Look up a header by name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+
+    /// Returns `true` if the message may be delivered at `now`.
+    pub fn is_ready(&self, now: DateTime<Utc>) -> bool {
+        self.not_before.is_none_or(|not_before| not_before <= now)
+    }
+
+    /// Returns `true` if the message's time-to-live has run out at `now`.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Optional settings for a message being enqueued.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnqueueOptions {
+    pub priority: Priority,
+    /// The message is not delivered before this time.
+    pub not_before: Option<DateTime<Utc>>,
+    /// How long the message may wait in the queue. Overrides the queue's default TTL.
+    pub ttl: Option<Duration>,
+    /// Headers attached to the message.
+    pub headers: BTreeMap<String, String>,
+}
+
+impl EnqueueOptions {
+    /// Set the priority of the message.
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Hold the message back until `not_before`.
+    pub fn not_before(mut self, not_before: DateTime<Utc>) -> Self {
+        self.not_before = Some(not_before);
+        self
+    }
+
+    /// Discard the message if it hasn't been delivered within `ttl`.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Attach a header to the message, replacing any earlier value with the same name.
+    pub fn header<K: Into<String>, V: Into<String>>(mut self, name: K, value: V) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+}
+
+/// Queue-wide expiry policy and size quotas.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueLimits {
+    /// Time-to-live for messages enqueued without their own.
+    pub default_ttl: Option<Duration>,
+    /// Maximum number of messages held at once.
+    pub max_messages: Option<usize>,
+    /// Maximum total size of message content, in bytes.
+    pub max_bytes: Option<usize>,
+}
+
+/// Counters describing messages the queue has dropped or turned away.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Messages discarded because their TTL ran out.
+    pub expired: u64,
+    /// Enqueues refused because of a quota.
+    pub rejected: u64,
+}
+
+/// An error returned when a message would push the queue over one of its [`QueueLimits`].
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum EnqueueError {
+    #[error("queue already holds the maximum of {limit} messages")]
+    TooManyMessages { limit: usize },
+
+    #[error("message of {size} bytes would exceed the queue limit of {limit} bytes")]
+    TooManyBytes { size: usize, limit: usize },
+}
+
+/// An error that may occur while saving and loading the queue using a storage backend.
+#[derive(Debug, thiserror::Error)]
+#[error("message queue storage error")]
+pub struct MessageQueueStorageError {
+    // this allows putting any errors as a source
+    source: color_eyre::Report,
+}
+
+/// Errors that may occur while working with the `FileStore`.
+#[derive(Debug, thiserror::Error)]
+pub enum FileStoreError {
+    #[error("IO error")]
+    IO(#[from] std::io::Error),
+
+    #[error("malformed queue file")]
+    Format(#[from] serde_json::Error),
+}
+
+impl From<FileStoreError> for MessageQueueStorageError {
+    fn from(value: FileStoreError) -> Self {
+        Self {
+            source: eyre!(value),
+        }
+    }
+}
+
+/// A message queue.
+///
+/// Limits and stats are runtime settings: they aren't saved by storage backends and aren't
+/// considered when comparing queues.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MessageQueue {
+    messages: VecDeque<Message>,
+    next_id: u32,
+    #[serde(skip)]
+    limits: QueueLimits,
+    #[serde(skip)]
+    stats: QueueStats,
+}
+
+impl PartialEq for MessageQueue {
+    fn eq(&self, other: &Self) -> bool {
+        self.messages == other.messages && self.next_id == other.next_id
+    }
+}
+
+impl Eq for MessageQueue {}
+
+impl PartialOrd for MessageQueue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MessageQueue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.messages, self.next_id).cmp(&(&other.messages, other.next_id))
+    }
+}
+
+impl MessageQueue {
+    /// Create an empty queue that enforces the given limits.
+    pub fn with_limits(limits: QueueLimits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    /// The limits enforced by this queue.
+    pub fn limits(&self) -> QueueLimits {
+        self.limits
+    }
+
+    /// Change the limits enforced by this queue, such as after loading it from storage.
+    ///
+    /// Messages already in the queue are kept even if they exceed the new quotas.
+    pub fn set_limits(&mut self, limits: QueueLimits) {
+        self.limits = limits;
+    }
+
+    /// Counters for expired and rejected messages.
+    pub fn stats(&self) -> QueueStats {
+        self.stats
+    }
+
+    /// Add a new message to the queue, returning its id.
+    pub fn enqueue<M: Into<String>>(&mut self, message: M) -> Result<u32, EnqueueError> {
+        self.enqueue_with(message, EnqueueOptions::default())
+    }
+
+    /// Add a new message using the given options, returning its id.
+    pub fn enqueue_with<M: Into<String>>(
+        &mut self,
+        message: M,
+        options: EnqueueOptions,
+    ) -> Result<u32, EnqueueError> {
+        let message = self.prepare(message, options, Utc::now())?;
+        let id = message.id;
+        self.messages.push_back(message);
+        self.next_id += 1;
+        Ok(id)
+    }
+
+    /// Build the next message and make sure it fits within the queue's limits.
+    ///
+    /// The message is not added to the queue.
+    fn prepare<M: Into<String>>(
+        &mut self,
+        message: M,
+        options: EnqueueOptions,
+        now: DateTime<Utc>,
+    ) -> Result<Message, EnqueueError> {
+        let content = message.into();
+        // expired messages shouldn't count against the quotas
+        if self.limits.max_messages.is_some() || self.limits.max_bytes.is_some() {
+            self.purge_expired_at(now);
+        }
+
+        let result = match (self.limits.max_messages, self.limits.max_bytes) {
+            (Some(limit), _) if self.messages.len() >= limit => {
+                Err(EnqueueError::TooManyMessages { limit })
+            }
+            (_, Some(limit)) if self.bytes() + content.len() > limit => {
+                Err(EnqueueError::TooManyBytes {
+                    size: content.len(),
+                    limit,
+                })
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            self.stats.rejected += 1;
+            return Err(e);
+        }
+
+        let ttl = options.ttl.or(self.limits.default_ttl);
+        Ok(Message {
+            id: self.next_id,
+            content,
+            priority: options.priority,
+            not_before: options.not_before,
+            expires_at: ttl.map(|ttl| now + ttl),
+            headers: options.headers,
+        })
+    }
+
+    /// Remove and return the highest priority message that is ready for delivery.
+    ///
+    /// Messages with the same priority are returned in the order they were enqueued. Expired
+    /// messages are purged along the way.
+    pub fn dequeue(&mut self) -> Option<Message> {
+        self.dequeue_at(Utc::now())
+    }
+
+    /// Remove and return the highest priority message that is ready for delivery at `now`.
+    pub fn dequeue_at(&mut self, now: DateTime<Utc>) -> Option<Message> {
+        self.purge_expired_at(now);
+        let pos = self.next_ready(now)?;
+        self.messages.remove(pos)
+    }
+
+    /// Remove every message whose TTL has run out, returning how many were removed.
+    pub fn purge_expired(&mut self) -> usize {
+        self.purge_expired_at(Utc::now())
+    }
+
+    /// Remove every message whose TTL has run out at `now`, returning how many were removed.
+    pub fn purge_expired_at(&mut self, now: DateTime<Utc>) -> usize {
+        let before = self.messages.len();
+        self.messages.retain(|m| !m.is_expired(now));
+        let purged = before - self.messages.len();
+        self.stats.expired += purged as u64;
+        purged
+    }
+
+    /// Total size of message content in the queue, in bytes.
+    pub fn bytes(&self) -> usize {
+        self.messages.iter().map(|m| m.content.len()).sum()
+    }
+
+    /// Return the message that `dequeue` would remove, without removing it.
+    pub fn peek(&self) -> Option<&Message> {
+        self.peek_at(Utc::now())
+    }
+
+    /// Return the message that `dequeue_at` would remove, without removing it.
+    pub fn peek_at(&self, now: DateTime<Utc>) -> Option<&Message> {
+        self.next_ready(now).map(|pos| &self.messages[pos])
+    }
+
+    /// Position of the message that `dequeue_at` would return.
+    fn next_ready(&self, now: DateTime<Utc>) -> Option<usize> {
+        let mut best: Option<(usize, Priority)> = None;
+        for (pos, message) in self.messages.iter().enumerate() {
+            if !message.is_ready(now) || message.is_expired(now) {
+                continue;
+            }
+            // Messages are kept in id order, so only a strictly higher priority wins. This
+            // keeps delivery stable within a priority.
+            if best.is_none_or(|(_, priority)| message.priority > priority) {
+                best = Some((pos, message.priority));
+            }
+        }
+        best.map(|(pos, _)| pos)
+    }
+
+    /// The earliest time at which a message that isn't ready at `now` becomes ready.
+    fn next_ready_time(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.messages
+            .iter()
+            .filter_map(|m| m.not_before)
+            .filter(|not_before| *not_before > now)
+            .min()
+    }
+
+    /// The earliest time at which a message expires.
+    fn next_expiry_time(&self) -> Option<DateTime<Utc>> {
+        self.messages.iter().filter_map(|m| m.expires_at).min()
+    }
+
+    /// Put a previously dequeued message back in its original position.
+    pub(crate) fn requeue(&mut self, message: Message) {
+        let pos = self.messages.partition_point(|m| m.id < message.id);
+        self.messages.insert(pos, message);
+    }
+
+    /// The number of messages in the queue, including expired ones that haven't been purged yet.
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Returns `true` if the queue holds no messages.
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Iterate over all messages in the queue, skipping expired ones.
+    pub fn iter(&self) -> impl Iterator<Item = &Message> {
+        let now = Utc::now();
+        self.messages.iter().filter(move |m| !m.is_expired(now))
+    }
+}
+
+/// Saves and loads an entire [`MessageQueue`].
+pub trait MessageQueueStorage {
+    fn save(&self, queue: &MessageQueue) -> Result<(), MessageQueueStorageError>;
+    fn load(&self) -> Result<MessageQueue, MessageQueueStorageError>;
+
+    /// The file the queue is stored in, if it's stored in one.
+    fn location(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// Stores the whole queue as JSON in a single file.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl FileStore {
+    /// Create a new store which uses the file at `path`.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The file used by this store.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write(&self, queue: &MessageQueue) -> Result<(), FileStoreError> {
+        write_atomic(&self.path, &serde_json::to_vec(queue)?)?;
+        Ok(())
+    }
+
+    fn read(&self) -> Result<MessageQueue, FileStoreError> {
+        let json = std::fs::read(&self.path)?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
+
+impl MessageQueueStorage for FileStore {
+    fn save(&self, queue: &MessageQueue) -> Result<(), MessageQueueStorageError> {
+        Ok(self.write(queue)?)
+    }
+
+    fn load(&self) -> Result<MessageQueue, MessageQueueStorageError> {
+        Ok(self.read()?)
+    }
+
+    fn location(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drain(queue: &mut MessageQueue, now: DateTime<Utc>) -> Vec<String> {
+        std::iter::from_fn(|| queue.dequeue_at(now))
+            .map(|m| m.content)
+            .collect()
+    }
+
+    fn priority(priority: Priority) -> EnqueueOptions {
+        EnqueueOptions::default().priority(priority)
+    }
+
+    #[test]
+    fn dequeues_by_priority_then_fifo() {
+        let mut queue = MessageQueue::default();
+        queue.enqueue_with("low", priority(Priority::Low)).unwrap();
+        queue.enqueue("normal-1").unwrap();
+        queue
+            .enqueue_with("urgent", priority(Priority::Urgent))
+            .unwrap();
+        queue.enqueue("normal-2").unwrap();
+        queue
+            .enqueue_with("high", priority(Priority::High))
+            .unwrap();
+
+        assert_eq!(
+            drain(&mut queue, Utc::now()),
+            vec!["urgent", "high", "normal-1", "normal-2", "low"]
+        );
+    }
+
+    #[test]
+    fn delayed_messages_stay_hidden_until_ready() {
+        let now = Utc::now();
+        let mut queue = MessageQueue::default();
+        let later = priority(Priority::Urgent).not_before(now + Duration::minutes(5));
+        queue.enqueue_with("later", later).unwrap();
+        queue.enqueue("now").unwrap();
+
+        assert_eq!(drain(&mut queue, now), vec!["now"]);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(drain(&mut queue, now + Duration::minutes(5)), vec!["later"]);
+    }
+
+    #[test]
+    fn file_store_round_trips_message_options() {
+        let path = std::env::temp_dir().join(format!("mq-store-{}", std::process::id()));
+        let mut queue = MessageQueue::default();
+        let options = priority(Priority::High)
+            .not_before(Utc::now())
+            .ttl(Duration::hours(1));
+        queue.enqueue_with("a", options).unwrap();
+        queue.enqueue("b").unwrap();
+
+        let storage = FileStore::new(&path);
+        storage.save(&queue).unwrap();
+        let loaded = storage.load();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(loaded.unwrap(), queue);
+    }
+
+    #[test]
+    fn loads_messages_saved_without_priority() {
+        let json = r#"{"messages":[{"id":0,"content":"a"}],"next_id":1}"#;
+        let queue: MessageQueue = serde_json::from_str(json).unwrap();
+        let message = queue.iter().next().unwrap();
+
+        assert_eq!(message.priority, Priority::Normal);
+        assert_eq!(message.not_before, None);
+        assert_eq!(message.expires_at, None);
+    }
+
+    #[test]
+    fn expired_messages_are_skipped_and_purged() {
+        let mut queue = MessageQueue::with_limits(QueueLimits {
+            default_ttl: Some(Duration::minutes(10)),
+            ..Default::default()
+        });
+        queue.enqueue("default ttl").unwrap();
+        let short = EnqueueOptions::default().ttl(Duration::minutes(1));
+        queue.enqueue_with("short ttl", short).unwrap();
+        let long = EnqueueOptions::default().ttl(Duration::hours(1));
+        queue.enqueue_with("long ttl", long).unwrap();
+
+        let later = Utc::now() + Duration::minutes(5);
+        assert_eq!(queue.peek_at(later).unwrap().content, "default ttl");
+        assert_eq!(queue.purge_expired_at(later), 1);
+
+        let much_later = Utc::now() + Duration::minutes(30);
+        assert_eq!(drain(&mut queue, much_later), vec!["long ttl"]);
+        assert_eq!(queue.stats().expired, 2);
+    }
+
+    #[test]
+    fn iter_hides_expired_messages() {
+        let mut queue = MessageQueue::default();
+        queue
+            .enqueue_with("gone", EnqueueOptions::default().ttl(Duration::zero()))
+            .unwrap();
+        queue.enqueue("kept").unwrap();
+
+        let contents: Vec<_> = queue.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, vec!["kept"]);
+        assert_eq!(queue.purge_expired(), 1);
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn quotas_reject_enqueues() {
+        let mut queue = MessageQueue::with_limits(QueueLimits {
+            max_messages: Some(2),
+            max_bytes: Some(8),
+            ..Default::default()
+        });
+        queue.enqueue("12345").unwrap();
+        assert_eq!(
+            queue.enqueue("6789"),
+            Err(EnqueueError::TooManyBytes { size: 4, limit: 8 })
+        );
+        queue.enqueue("678").unwrap();
+        assert_eq!(
+            queue.enqueue(""),
+            Err(EnqueueError::TooManyMessages { limit: 2 })
+        );
+        assert_eq!(queue.stats().rejected, 2);
+
+        queue.dequeue();
+        assert!(queue.enqueue("").is_ok());
+    }
+
+    #[test]
+    fn expired_messages_free_up_quota() {
+        let mut queue = MessageQueue::with_limits(QueueLimits {
+            max_messages: Some(1),
+            ..Default::default()
+        });
+        queue
+            .enqueue_with("stale", EnqueueOptions::default().ttl(Duration::zero()))
+            .unwrap();
+        assert!(queue.enqueue("fresh").is_ok());
+        assert_eq!(queue.stats().expired, 1);
+    }
+}
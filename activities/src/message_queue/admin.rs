@@ -0,0 +1,189 @@
+//! Maintenance operations on persisted queues, used by the `mc-01-admin` tool.
+//!
+//! Each operation loads the queue from a store, changes it and saves it back.
+
+use super::{Message, MessageQueue, MessageQueueStorage, MessageQueueStorageError};
+use chrono::Utc;
+use std::path::{Path, PathBuf};
+
+/// Errors that may occur while moving messages between queues with [`requeue`].
+#[derive(Debug, thiserror::Error)]
+pub enum RequeueError {
+    #[error("the dead letter and live queues are both stored in {0}")]
+    SameQueue(PathBuf),
+
+    #[error(transparent)]
+    Storage(#[from] MessageQueueStorageError),
+}
+
+/// Which messages [`purge`] removes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purge {
+    /// Every message in the queue.
+    All,
+    /// Only messages whose TTL has run out.
+    Expired,
+}
+
+/// Remove messages from the stored queue, returning how many were removed.
+///
+/// Message ids keep counting up from where they were, so ids are never reused.
+pub fn purge<S>(store: &S, which: Purge) -> Result<usize, MessageQueueStorageError>
+where
+    S: MessageQueueStorage + ?Sized,
+{
+    let mut queue = store.load()?;
+    let removed = match which {
+        Purge::All => {
+            let removed = queue.messages.len();
+            queue.messages.clear();
+            removed
+        }
+        Purge::Expired => queue.purge_expired(),
+    };
+    store.save(&queue)?;
+    Ok(removed)
+}
+
+/// Move every unexpired message from `dead` onto the end of `live`, returning how many were
+/// moved.
+///
+/// Moved messages get new ids in the live queue but keep their priority, delay and expiry. The
+/// live queue is saved before the dead one is emptied, so a failure part way through can
+/// duplicate messages but never lose them.
+///
+/// Fails with [`RequeueError::SameQueue`] if both stores use the same file, since emptying the
+/// dead queue would then delete every message.
+pub fn requeue<D, L>(dead: &D, live: &L) -> Result<usize, RequeueError>
+where
+    D: MessageQueueStorage + ?Sized,
+    L: MessageQueueStorage + ?Sized,
+{
+    if let (Some(dead), Some(live)) = (dead.location(), live.location()) {
+        if same_file(dead, live) {
+            return Err(RequeueError::SameQueue(live.to_owned()));
+        }
+    }
+
+    let mut dead_queue = dead.load()?;
+    let mut live_queue = live.load()?;
+
+    let now = Utc::now();
+    let mut moved = 0;
+    for message in dead_queue.messages.drain(..) {
+        if message.is_expired(now) {
+            continue;
+        }
+        append(&mut live_queue, message);
+        moved += 1;
+    }
+
+    live.save(&live_queue)?;
+    dead.save(&dead_queue)?;
+    Ok(moved)
+}
+
+/// Returns `true` if both paths lead to the same file, following links and relative paths when
+/// the files exist.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Add a message to the end of the queue under the queue's next id.
+fn append(queue: &mut MessageQueue, message: Message) {
+    let message = Message {
+        id: queue.next_id,
+        ..message
+    };
+    queue.messages.push_back(message);
+    queue.next_id += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_queue::{EnqueueOptions, FileStore, Priority};
+    use chrono::Duration;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mq-admin-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn purges_all_or_only_expired_messages() {
+        let dir = temp_dir("purge");
+        let store = FileStore::new(dir.join("queue.json"));
+
+        let mut queue = MessageQueue::default();
+        queue.enqueue("keep").unwrap();
+        let expired = EnqueueOptions::default().ttl(Duration::zero());
+        queue.enqueue_with("stale", expired).unwrap();
+        store.save(&queue).unwrap();
+
+        assert_eq!(purge(&store, Purge::Expired).unwrap(), 1);
+        assert_eq!(store.load().unwrap().len(), 1);
+        assert_eq!(purge(&store, Purge::All).unwrap(), 1);
+
+        let mut queue = store.load().unwrap();
+        assert!(queue.is_empty());
+        assert_eq!(queue.enqueue("next").unwrap(), 2);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn requeues_dead_messages_with_new_ids() {
+        let dir = temp_dir("requeue");
+        let dead = FileStore::new(dir.join("dead.json"));
+        let live = FileStore::new(dir.join("live.json"));
+
+        let mut dead_queue = MessageQueue::default();
+        let urgent = EnqueueOptions::default().priority(Priority::Urgent);
+        dead_queue.enqueue_with("retry me", urgent).unwrap();
+        let expired = EnqueueOptions::default().ttl(Duration::zero());
+        dead_queue.enqueue_with("too late", expired).unwrap();
+        dead.save(&dead_queue).unwrap();
+
+        let mut live_queue = MessageQueue::default();
+        for content in ["a", "b", "c"] {
+            live_queue.enqueue(content).unwrap();
+        }
+        live.save(&live_queue).unwrap();
+
+        assert_eq!(requeue(&dead, &live).unwrap(), 1);
+        assert!(dead.load().unwrap().is_empty());
+
+        let mut live_queue = live.load().unwrap();
+        let message = live_queue.dequeue().unwrap();
+        assert_eq!(message.id, 3);
+        assert_eq!(message.content, "retry me");
+        assert_eq!(message.priority, Priority::Urgent);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_requeue_a_queue_into_itself() {
+        let dir = temp_dir("requeue-same");
+        let queue = FileStore::new(dir.join("queue.json"));
+        let mut saved = MessageQueue::default();
+        saved.enqueue("keep me").unwrap();
+        queue.save(&saved).unwrap();
+
+        let same = FileStore::new(dir.join(".").join("queue.json"));
+        assert!(matches!(
+            requeue(&queue, &same),
+            Err(RequeueError::SameQueue(_))
+        ));
+        assert_eq!(queue.load().unwrap().len(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
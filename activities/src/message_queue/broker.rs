@@ -0,0 +1,320 @@
+//! Named topics with consumer groups.
+//!
+//! Each topic is an append-only [`MessageQueue`] where a message's id is its offset in the topic.
+//! Messages are never removed when they are read. Instead, every consumer group keeps a committed
+//! offset per topic (the offset of the next message it wants), so any number of groups can read
+//! the same stream independently.
+//!
+//! A broker opened with [`Broker::open`] persists topics and committed offsets to a directory, so
+//! consumers resume where they left off after a restart. Each topic is stored as a
+//! [`DurableQueue`], so publishing appends one record to the topic's log instead of rewriting it.
+
+use super::wal::{DurableQueue, WalError};
+use super::{EnqueueError, Message, MessageQueue};
+use crate::util::fs::write_atomic;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+const TOPICS_DIR: &str = "topics";
+const OFFSETS_FILE: &str = "offsets.json";
+
+/// Committed offsets, keyed by topic and then by consumer group.
+type Offsets = BTreeMap<String, BTreeMap<String, u32>>;
+
+/// Errors that may occur while working with a [`Broker`].
+#[derive(Debug, thiserror::Error)]
+pub enum BrokerError {
+    #[error("invalid topic name: {0:?}")]
+    InvalidTopicName(String),
+
+    #[error("unknown topic: {0}")]
+    UnknownTopic(String),
+
+    #[error("offset {offset} is past the end of topic {topic}")]
+    OffsetOutOfRange { topic: String, offset: u32 },
+
+    #[error("IO error")]
+    IO(#[from] std::io::Error),
+
+    #[error("malformed offsets file")]
+    Format(#[from] serde_json::Error),
+
+    #[error("topic storage error")]
+    Log(#[from] WalError),
+
+    #[error("message rejected")]
+    Rejected(#[from] EnqueueError),
+}
+
+/// The messages published to a topic, kept in memory or in a log on disk.
+#[derive(Debug)]
+enum Topic {
+    Memory(MessageQueue),
+    Durable(DurableQueue),
+}
+
+impl Topic {
+    fn queue(&self) -> &MessageQueue {
+        match self {
+            Topic::Memory(queue) => queue,
+            Topic::Durable(queue) => queue.queue(),
+        }
+    }
+
+    fn publish(&mut self, message: String) -> Result<u32, BrokerError> {
+        match self {
+            Topic::Memory(queue) => Ok(queue.enqueue(message)?),
+            Topic::Durable(queue) => {
+                let offset = queue.queue().next_id;
+                queue.enqueue(message)?;
+                Ok(offset)
+            }
+        }
+    }
+}
+
+/// Holds many named topics and the offsets committed by their consumer groups.
+#[derive(Debug, Default)]
+pub struct Broker {
+    topics: BTreeMap<String, Topic>,
+    offsets: Offsets,
+    dir: Option<PathBuf>,
+}
+
+impl Broker {
+    /// Open the broker stored in `dir`, creating it if it doesn't exist yet.
+    pub fn open<P: Into<PathBuf>>(dir: P) -> Result<Self, BrokerError> {
+        let dir = dir.into();
+        let topics_dir = dir.join(TOPICS_DIR);
+        std::fs::create_dir_all(&topics_dir)?;
+
+        let mut topics = BTreeMap::new();
+        for entry in std::fs::read_dir(&topics_dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            if path.is_dir() && validate_topic_name(name).is_ok() {
+                let topic = Topic::Durable(DurableQueue::open(&path)?);
+                topics.insert(name.to_owned(), topic);
+            }
+        }
+
+        let offsets_path = dir.join(OFFSETS_FILE);
+        let offsets = if offsets_path.exists() {
+            serde_json::from_slice(&std::fs::read(&offsets_path)?)?
+        } else {
+            Offsets::default()
+        };
+
+        Ok(Self {
+            topics,
+            offsets,
+            dir: Some(dir),
+        })
+    }
+
+    /// Create a topic if it doesn't already exist.
+    pub fn create_topic(&mut self, topic: &str) -> Result<(), BrokerError> {
+        validate_topic_name(topic)?;
+        if !self.topics.contains_key(topic) {
+            let stored = match &self.dir {
+                Some(dir) => Topic::Durable(DurableQueue::open(dir.join(TOPICS_DIR).join(topic))?),
+                None => Topic::Memory(MessageQueue::default()),
+            };
+            self.topics.insert(topic.to_owned(), stored);
+        }
+        Ok(())
+    }
+
+    /// Append a message to a topic, creating the topic if needed.
+    ///
+    /// Returns the offset of the new message.
+    pub fn publish<M: Into<String>>(
+        &mut self,
+        topic: &str,
+        message: M,
+    ) -> Result<u32, BrokerError> {
+        self.create_topic(topic)?;
+        self.topics
+            .get_mut(topic)
+            .expect("topic was just created")
+            .publish(message.into())
+    }
+
+    /// Read up to `max` messages starting at the group's committed offset.
+    ///
+    /// Polling does not move the committed offset. Call [`Broker::commit`] once the messages have
+    /// been processed.
+    pub fn poll(&self, topic: &str, group: &str, max: usize) -> Result<Vec<Message>, BrokerError> {
+        let offset = self.committed(topic, group).unwrap_or(0);
+        Ok(self
+            .topic(topic)?
+            .iter()
+            .skip_while(|m| m.id < offset)
+            .take(max)
+            .cloned()
+            .collect())
+    }
+
+    /// Commit the offset of the next message the group wants to read from a topic.
+    pub fn commit(&mut self, topic: &str, group: &str, offset: u32) -> Result<(), BrokerError> {
+        if offset > self.end_offset(topic)? {
+            return Err(BrokerError::OffsetOutOfRange {
+                topic: topic.to_owned(),
+                offset,
+            });
+        }
+        self.offsets
+            .entry(topic.to_owned())
+            .or_default()
+            .insert(group.to_owned(), offset);
+        self.save_offsets()
+    }
+
+    /// The offset committed by a group, if it has committed one.
+    pub fn committed(&self, topic: &str, group: &str) -> Option<u32> {
+        self.offsets.get(topic)?.get(group).copied()
+    }
+
+    /// Number of messages a group has yet to commit on a topic.
+    pub fn lag(&self, topic: &str, group: &str) -> Result<u32, BrokerError> {
+        let end = self.end_offset(topic)?;
+        Ok(end - self.committed(topic, group).unwrap_or(0))
+    }
+
+    /// The offset that the next published message will get.
+    pub fn end_offset(&self, topic: &str) -> Result<u32, BrokerError> {
+        Ok(self.topic(topic)?.next_id)
+    }
+
+    /// Iterate over the names of all topics.
+    pub fn topics(&self) -> impl Iterator<Item = &str> {
+        self.topics.keys().map(String::as_str)
+    }
+
+    /// Iterate over the groups that have committed offsets on a topic.
+    pub fn groups<'a>(&'a self, topic: &str) -> impl Iterator<Item = &'a str> {
+        self.offsets
+            .get(topic)
+            .into_iter()
+            .flat_map(|groups| groups.keys().map(String::as_str))
+    }
+
+    fn topic(&self, topic: &str) -> Result<&MessageQueue, BrokerError> {
+        self.topics
+            .get(topic)
+            .map(Topic::queue)
+            .ok_or_else(|| BrokerError::UnknownTopic(topic.to_owned()))
+    }
+
+    fn save_offsets(&self) -> Result<(), BrokerError> {
+        if let Some(dir) = &self.dir {
+            write_atomic(&dir.join(OFFSETS_FILE), &serde_json::to_vec(&self.offsets)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// Topic names end up as file names, so only allow a conservative set of characters.
+fn validate_topic_name(topic: &str) -> Result<(), BrokerError> {
+    let valid = !topic.is_empty()
+        && !topic.starts_with('.')
+        && topic
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(BrokerError::InvalidTopicName(topic.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_queue::wal::SNAPSHOT_FILE;
+
+    fn contents(messages: &[Message]) -> Vec<&str> {
+        messages.iter().map(|m| m.content.as_str()).collect()
+    }
+
+    #[test]
+    fn groups_read_independently() {
+        let mut broker = Broker::default();
+        broker.publish("orders", "a").unwrap();
+        broker.publish("orders", "b").unwrap();
+        broker.publish("orders", "c").unwrap();
+
+        let billing = broker.poll("orders", "billing", 2).unwrap();
+        assert_eq!(contents(&billing), vec!["a", "b"]);
+        broker
+            .commit("orders", "billing", billing[1].id + 1)
+            .unwrap();
+
+        assert_eq!(
+            contents(&broker.poll("orders", "billing", 10).unwrap()),
+            vec!["c"]
+        );
+        assert_eq!(
+            contents(&broker.poll("orders", "shipping", 10).unwrap()),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(broker.lag("orders", "billing").unwrap(), 1);
+        assert_eq!(broker.lag("orders", "shipping").unwrap(), 3);
+    }
+
+    #[test]
+    fn rejects_bad_topics_and_offsets() {
+        let mut broker = Broker::default();
+        assert!(matches!(
+            broker.publish("../etc", "x"),
+            Err(BrokerError::InvalidTopicName(_))
+        ));
+        assert!(matches!(
+            broker.poll("missing", "g", 1),
+            Err(BrokerError::UnknownTopic(_))
+        ));
+
+        broker.publish("t", "x").unwrap();
+        assert!(broker.commit("t", "g", 1).is_ok());
+        assert!(matches!(
+            broker.commit("t", "g", 2),
+            Err(BrokerError::OffsetOutOfRange { offset: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn resumes_from_committed_offset_after_restart() {
+        let dir = std::env::temp_dir().join(format!("mq-broker-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let test = || -> Result<(), BrokerError> {
+            {
+                let mut broker = Broker::open(&dir)?;
+                broker.publish("events", "a")?;
+                broker.publish("events", "b")?;
+                broker.create_topic("empty")?;
+                broker.commit("events", "audit", 1)?;
+            }
+
+            // publishing appends to the log rather than writing a snapshot
+            assert!(!dir
+                .join(TOPICS_DIR)
+                .join("events")
+                .join(SNAPSHOT_FILE)
+                .exists());
+
+            let mut broker = Broker::open(&dir)?;
+            assert_eq!(broker.topics().collect::<Vec<_>>(), vec!["empty", "events"]);
+            assert_eq!(broker.groups("events").collect::<Vec<_>>(), vec!["audit"]);
+            assert_eq!(contents(&broker.poll("events", "audit", 10)?), vec!["b"]);
+            assert_eq!(broker.publish("events", "c")?, 2);
+            Ok(())
+        };
+
+        let result = test();
+        let _ = std::fs::remove_dir_all(&dir);
+        result.unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+//! A client for the queue [`server`](super::server).
+
+use super::protocol::{ProtocolError, Request, Response, ServerStats};
+use super::Message;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+/// Errors that may occur while talking to a queue server.
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("IO error")]
+    IO(#[from] io::Error),
+
+    #[error("malformed response")]
+    Protocol(#[from] ProtocolError),
+
+    #[error("server error: {0}")]
+    Server(String),
+
+    #[error("unexpected response: {0}")]
+    Unexpected(String),
+
+    #[error("server closed the connection")]
+    Disconnected,
+}
+
+/// A connection to a queue server.
+#[derive(Debug)]
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Client {
+    /// Connect to the server at `addr`.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, ClientError> {
+        let writer = TcpStream::connect(addr)?;
+        // Requests are tiny and we wait for each reply, so don't let Nagle's algorithm batch them.
+        writer.set_nodelay(true)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Self { reader, writer })
+    }
+
+    /// Add a message to the queue, returning its id.
+    pub fn enqueue<M: Into<String>>(&mut self, message: M) -> Result<u32, ClientError> {
+        match self.send(Request::Enqueue(message.into()))? {
+            Response::Ok(id) => Ok(id),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Remove and return the next message, if there is one.
+    pub fn dequeue(&mut self) -> Result<Option<Message>, ClientError> {
+        self.send_for_message(Request::Dequeue)
+    }
+
+    /// Return the next message without removing it.
+    pub fn peek(&mut self) -> Result<Option<Message>, ClientError> {
+        self.send_for_message(Request::Peek)
+    }
+
+    /// Number of messages in the queue.
+    pub fn len(&mut self) -> Result<usize, ClientError> {
+        match self.send(Request::Len)? {
+            Response::Len(len) => Ok(len),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Returns `true` if the queue holds no messages.
+    pub fn is_empty(&mut self) -> Result<bool, ClientError> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Counters collected by the server.
+    pub fn stats(&mut self) -> Result<ServerStats, ClientError> {
+        match self.send(Request::Stats)? {
+            Response::Stats(stats) => Ok(stats),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    fn send_for_message(&mut self, request: Request) -> Result<Option<Message>, ClientError> {
+        match self.send(request)? {
+            Response::Message(message) => Ok(Some(message)),
+            Response::Empty => Ok(None),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Send a request and wait for the response. `ERR` responses become [`ClientError::Server`].
+    fn send(&mut self, request: Request) -> Result<Response, ClientError> {
+        self.writer.write_all(format!("{request}\n").as_bytes())?;
+
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(ClientError::Disconnected);
+        }
+        match Response::parse(line.trim_end_matches(['\r', '\n']))? {
+            Response::Err(reason) => Err(ClientError::Server(reason)),
+            response => Ok(response),
+        }
+    }
+}
+
+fn unexpected(response: Response) -> ClientError {
+    ClientError::Unexpected(response.to_string())
+}
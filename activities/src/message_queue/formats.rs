@@ -0,0 +1,687 @@
+//! Versioned file formats for [`MessageQueueStorage`].
+//!
+//! Every file starts with a header naming the format and the version it was written with. Stores
+//! always write the latest version but can read every older one, filling in defaults for fields
+//! that didn't exist yet.
+//!
+//! | Version | Message fields                          |
+//! |---------|-----------------------------------------|
+//! | 1       | `id`, `content`                         |
+//! | 2       | adds `priority` and `not_before`        |
+//! | 3       | adds `expires_at`                       |
+//! | 4       | adds `headers`                          |
+
+use super::{Message, MessageQueue, MessageQueueStorage, MessageQueueStorageError, Priority};
+use crate::util::csv::{csv_field, parse_csv, SyntaxError};
+use crate::util::fs::write_atomic;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+/// The version written by all stores.
+pub const CURRENT_VERSION: u16 = 4;
+
+const CSV_MAGIC: &str = "mq-csv";
+const JSON_MAGIC: &str = "mq-json";
+const BINARY_MAGIC: &[u8; 4] = b"MQB\0";
+
+/// Errors that may occur while reading or writing a versioned queue file.
+#[derive(Debug, thiserror::Error)]
+pub enum FormatError {
+    #[error("IO error")]
+    IO(#[from] std::io::Error),
+
+    #[error("malformed JSON")]
+    Json(#[from] serde_json::Error),
+
+    #[error("not a {0} file")]
+    BadHeader(&'static str),
+
+    #[error("{format} version {version} is newer than this program supports")]
+    UnsupportedVersion { format: &'static str, version: u16 },
+
+    #[error("record {record}: {reason}")]
+    Malformed { record: usize, reason: String },
+
+    #[error("file ends in the middle of a record")]
+    Truncated,
+}
+
+impl From<FormatError> for MessageQueueStorageError {
+    fn from(value: FormatError) -> Self {
+        Self {
+            source: eyre!(value),
+        }
+    }
+}
+
+impl From<SyntaxError> for FormatError {
+    fn from(value: SyntaxError) -> Self {
+        match value {
+            SyntaxError::Unterminated { .. } => FormatError::Truncated,
+            SyntaxError::StrayQuote { record } => malformed(record, value.to_string()),
+        }
+    }
+}
+
+fn check_version(format: &'static str, version: u16) -> Result<u16, FormatError> {
+    if version == 0 || version > CURRENT_VERSION {
+        Err(FormatError::UnsupportedVersion { format, version })
+    } else {
+        Ok(version)
+    }
+}
+
+fn malformed<R: Into<String>>(record: usize, reason: R) -> FormatError {
+    FormatError::Malformed {
+        record,
+        reason: reason.into(),
+    }
+}
+
+/// The file formats understood by [`Format::store`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Json,
+    Binary,
+}
+
+impl Format {
+    /// Guess the format from a file extension: `.csv`, `.json` or `.mqb`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "csv" => Some(Format::Csv),
+            "json" => Some(Format::Json),
+            "mqb" | "bin" => Some(Format::Binary),
+            _ => None,
+        }
+    }
+
+    /// Split a `csv:`, `json:` or `binary:` prefix off a path argument.
+    ///
+    /// Returns `None` if the argument doesn't start with a known format.
+    pub fn split_prefix(arg: &str) -> Option<(Self, &str)> {
+        let (format, path) = arg.split_once(':')?;
+        Some((format.parse().ok()?, path))
+    }
+
+    /// Create a store of this format for the file at `path`.
+    pub fn store<P: Into<PathBuf>>(self, path: P) -> Box<dyn MessageQueueStorage> {
+        match self {
+            Format::Csv => Box::new(CsvStore::new(path)),
+            Format::Json => Box::new(JsonStore::new(path)),
+            Format::Binary => Box::new(BinaryStore::new(path)),
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "binary" | "bin" | "mqb" => Ok(Format::Binary),
+            _ => Err(format!("unknown format: {s}")),
+        }
+    }
+}
+
+/// Load a queue from one store and save it to another.
+///
+/// Returns the number of messages that were moved.
+pub fn convert<F, T>(from: &F, to: &T) -> Result<usize, MessageQueueStorageError>
+where
+    F: MessageQueueStorage + ?Sized,
+    T: MessageQueueStorage + ?Sized,
+{
+    let queue = from.load()?;
+    to.save(&queue)?;
+    Ok(queue.len())
+}
+
+// ---------------------------------------------------------------------------------------------
+// CSV
+// ---------------------------------------------------------------------------------------------
+
+/// Stores the queue as comma-separated values.
+///
+/// The first record is `mq-csv,<version>,<next_id>`. Every following record is one message:
+/// `id,content,priority,not_before,expires_at,headers`, with times in RFC 3339 format and headers
+/// as a JSON object. Fields containing commas, quotes or line breaks are quoted.
+#[derive(Debug, Clone)]
+pub struct CsvStore {
+    path: PathBuf,
+}
+
+impl CsvStore {
+    /// Create a new store which uses the file at `path`.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Encode a queue using the current version.
+    pub fn encode(queue: &MessageQueue) -> String {
+        let mut csv = format!("{CSV_MAGIC},{CURRENT_VERSION},{}\n", queue.next_id);
+        for message in &queue.messages {
+            let timestamp =
+                |t: Option<DateTime<Utc>>| t.map(|t| t.to_rfc3339()).unwrap_or_default();
+            let fields = [
+                message.id.to_string(),
+                message.content.clone(),
+                message.priority.to_string(),
+                timestamp(message.not_before),
+                timestamp(message.expires_at),
+                csv_headers(&message.headers),
+            ];
+            let record: Vec<_> = fields.iter().map(|f| csv_field(f)).collect();
+            csv.push_str(&record.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+
+    /// Decode a queue written by any supported version.
+    pub fn decode(csv: &str) -> Result<MessageQueue, FormatError> {
+        let mut records = parse_csv(csv)?.into_iter();
+        let header = records.next().ok_or(FormatError::BadHeader(CSV_MAGIC))?;
+        let (version, next_id) = match header.as_slice() {
+            [magic, version, next_id] if magic == CSV_MAGIC => (version, next_id),
+            _ => return Err(FormatError::BadHeader(CSV_MAGIC)),
+        };
+        let version = version
+            .parse()
+            .map_err(|_| FormatError::BadHeader(CSV_MAGIC))?;
+        let version = check_version(CSV_MAGIC, version)?;
+        let next_id = next_id
+            .parse()
+            .map_err(|_| malformed(0, "invalid next id"))?;
+
+        let mut messages = VecDeque::new();
+        for (i, record) in records.enumerate() {
+            let record_no = i + 1;
+            let expected = match version {
+                1 => 2,
+                2 => 4,
+                3 => 5,
+                _ => 6,
+            };
+            if record.len() != expected {
+                return Err(malformed(
+                    record_no,
+                    format!("expected {expected} fields, found {}", record.len()),
+                ));
+            }
+
+            let id = record[0]
+                .parse()
+                .map_err(|_| malformed(record_no, "invalid id"))?;
+            let mut message = Message::new(id, record[1].as_str());
+            if version >= 2 {
+                message.priority = record[2]
+                    .parse::<Priority>()
+                    .map_err(|e| malformed(record_no, e.to_string()))?;
+                message.not_before = csv_timestamp(&record[3], record_no, "not_before")?;
+            }
+            if version >= 3 {
+                message.expires_at = csv_timestamp(&record[4], record_no, "expires_at")?;
+            }
+            if version >= 4 && !record[5].is_empty() {
+                message.headers = serde_json::from_str(&record[5])
+                    .map_err(|_| malformed(record_no, "invalid headers"))?;
+            }
+            messages.push_back(message);
+        }
+
+        Ok(MessageQueue {
+            messages,
+            next_id,
+            ..Default::default()
+        })
+    }
+}
+
+impl MessageQueueStorage for CsvStore {
+    fn save(&self, queue: &MessageQueue) -> Result<(), MessageQueueStorageError> {
+        write_atomic(&self.path, Self::encode(queue).as_bytes()).map_err(FormatError::from)?;
+        Ok(())
+    }
+
+    fn load(&self) -> Result<MessageQueue, MessageQueueStorageError> {
+        let csv = std::fs::read_to_string(&self.path).map_err(FormatError::from)?;
+        Ok(Self::decode(&csv)?)
+    }
+
+    fn location(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+/// Encode headers as a JSON object, or an empty field if there are none.
+fn csv_headers(headers: &BTreeMap<String, String>) -> String {
+    if headers.is_empty() {
+        return String::new();
+    }
+    serde_json::to_string(headers).expect("a map of strings always serializes")
+}
+
+/// Parse an optional RFC 3339 timestamp field.
+fn csv_timestamp(
+    field: &str,
+    record: usize,
+    name: &str,
+) -> Result<Option<DateTime<Utc>>, FormatError> {
+    if field.is_empty() {
+        return Ok(None);
+    }
+    DateTime::parse_from_rfc3339(field)
+        .map(|t| Some(t.with_timezone(&Utc)))
+        .map_err(|_| malformed(record, format!("invalid {name}")))
+}
+
+// ---------------------------------------------------------------------------------------------
+// JSON
+// ---------------------------------------------------------------------------------------------
+
+#[derive(Serialize)]
+struct JsonFileRef<'a> {
+    format: &'a str,
+    version: u16,
+    queue: &'a MessageQueue,
+}
+
+#[derive(Deserialize)]
+struct JsonFile {
+    format: String,
+    version: u16,
+    queue: MessageQueue,
+}
+
+/// Stores the queue as JSON wrapped in a `{ "format", "version", "queue" }` envelope.
+///
+/// Unversioned files written by [`FileStore`](super::FileStore) are also accepted.
+#[derive(Debug, Clone)]
+pub struct JsonStore {
+    path: PathBuf,
+}
+
+impl JsonStore {
+    /// Create a new store which uses the file at `path`.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Encode a queue using the current version.
+    pub fn encode(queue: &MessageQueue) -> Result<Vec<u8>, FormatError> {
+        Ok(serde_json::to_vec_pretty(&JsonFileRef {
+            format: JSON_MAGIC,
+            version: CURRENT_VERSION,
+            queue,
+        })?)
+    }
+
+    /// Decode a queue written by any supported version.
+    pub fn decode(json: &[u8]) -> Result<MessageQueue, FormatError> {
+        let value: serde_json::Value = serde_json::from_slice(json)?;
+        if value.get("format").is_none() {
+            // written by `FileStore`, which predates versioning
+            return Ok(serde_json::from_value(value)?);
+        }
+
+        let file: JsonFile = serde_json::from_value(value)?;
+        if file.format != JSON_MAGIC {
+            return Err(FormatError::BadHeader(JSON_MAGIC));
+        }
+        // Fields added in later versions default when missing, so every version decodes the same.
+        check_version(JSON_MAGIC, file.version)?;
+        Ok(file.queue)
+    }
+}
+
+impl MessageQueueStorage for JsonStore {
+    fn save(&self, queue: &MessageQueue) -> Result<(), MessageQueueStorageError> {
+        let json = Self::encode(queue)?;
+        write_atomic(&self.path, &json).map_err(FormatError::from)?;
+        Ok(())
+    }
+
+    fn load(&self) -> Result<MessageQueue, MessageQueueStorageError> {
+        let json = std::fs::read(&self.path).map_err(FormatError::from)?;
+        Ok(Self::decode(&json)?)
+    }
+
+    fn location(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+// ---------------------------------------------------------------------------------------------
+// Binary
+// ---------------------------------------------------------------------------------------------
+
+/// Stores the queue in a compact length-prefixed binary format.
+///
+/// All integers are little endian:
+///
+/// ```text
+/// header:  "MQB\0"  version: u16  next_id: u32  count: u32
+/// message: id: u32  content: string
+///          (v2+)    priority: u8  not_before: timestamp
+///          (v3+)    expires_at: timestamp
+///          (v4+)    header_count: u32  (header_count times) name: string  value: string
+///
+/// string:    len: u32  bytes: [u8]
+/// timestamp: present: u8  (if present == 1) secs: i64  nanos: u32
+/// ```
+#[derive(Debug, Clone)]
+pub struct BinaryStore {
+    path: PathBuf,
+}
+
+impl BinaryStore {
+    /// Create a new store which uses the file at `path`.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Encode a queue using the current version.
+    pub fn encode(queue: &MessageQueue) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(BINARY_MAGIC);
+        bytes.extend_from_slice(&CURRENT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&queue.next_id.to_le_bytes());
+        bytes.extend_from_slice(&(queue.messages.len() as u32).to_le_bytes());
+
+        for message in &queue.messages {
+            bytes.extend_from_slice(&message.id.to_le_bytes());
+            push_string(&mut bytes, &message.content);
+            bytes.push(message.priority as u8);
+            push_timestamp(&mut bytes, message.not_before);
+            push_timestamp(&mut bytes, message.expires_at);
+            bytes.extend_from_slice(&(message.headers.len() as u32).to_le_bytes());
+            for (name, value) in &message.headers {
+                push_string(&mut bytes, name);
+                push_string(&mut bytes, value);
+            }
+        }
+        bytes
+    }
+
+    /// Decode a queue written by any supported version.
+    pub fn decode(bytes: &[u8]) -> Result<MessageQueue, FormatError> {
+        let mut reader = ByteReader { bytes };
+        if reader.take(BINARY_MAGIC.len())? != BINARY_MAGIC {
+            return Err(FormatError::BadHeader("binary queue"));
+        }
+        let version = check_version("binary queue", reader.u16()?)?;
+        let next_id = reader.u32()?;
+        let count = reader.u32()? as usize;
+
+        let mut messages = VecDeque::with_capacity(count.min(4096));
+        for record in 1..=count {
+            let id = reader.u32()?;
+            let content = reader.string(record, "content")?;
+            let mut message = Message::new(id, content);
+
+            if version >= 2 {
+                message.priority = match reader.u8()? {
+                    0 => Priority::Low,
+                    1 => Priority::Normal,
+                    2 => Priority::High,
+                    3 => Priority::Urgent,
+                    other => return Err(malformed(record, format!("invalid priority {other}"))),
+                };
+                message.not_before = reader.timestamp(record, "not_before")?;
+            }
+            if version >= 3 {
+                message.expires_at = reader.timestamp(record, "expires_at")?;
+            }
+            if version >= 4 {
+                for _ in 0..reader.u32()? {
+                    let name = reader.string(record, "header name")?;
+                    let value = reader.string(record, "header value")?;
+                    message.headers.insert(name.to_owned(), value.to_owned());
+                }
+            }
+            messages.push_back(message);
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(malformed(count, "unexpected data after last message"));
+        }
+        Ok(MessageQueue {
+            messages,
+            next_id,
+            ..Default::default()
+        })
+    }
+}
+
+impl MessageQueueStorage for BinaryStore {
+    fn save(&self, queue: &MessageQueue) -> Result<(), MessageQueueStorageError> {
+        write_atomic(&self.path, &Self::encode(queue)).map_err(FormatError::from)?;
+        Ok(())
+    }
+
+    fn load(&self) -> Result<MessageQueue, MessageQueueStorageError> {
+        let bytes = std::fs::read(&self.path).map_err(FormatError::from)?;
+        Ok(Self::decode(&bytes)?)
+    }
+
+    fn location(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+fn push_string(bytes: &mut Vec<u8>, string: &str) {
+    bytes.extend_from_slice(&(string.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+fn push_timestamp(bytes: &mut Vec<u8>, timestamp: Option<DateTime<Utc>>) {
+    match timestamp {
+        Some(timestamp) => {
+            bytes.push(1);
+            bytes.extend_from_slice(&timestamp.timestamp().to_le_bytes());
+            bytes.extend_from_slice(&timestamp.timestamp_subsec_nanos().to_le_bytes());
+        }
+        None => bytes.push(0),
+    }
+}
+
+/// Reads little endian values from the front of a byte slice.
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FormatError> {
+        if self.bytes.len() < len {
+            return Err(FormatError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], FormatError> {
+        Ok(self.take(N)?.try_into().expect("took exactly N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, FormatError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, FormatError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, FormatError> {
+        Ok(i64::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self, record: usize, name: &str) -> Result<&'a str, FormatError> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.take(len)?)
+            .map_err(|_| malformed(record, format!("{name} is not valid UTF-8")))
+    }
+
+    fn timestamp(
+        &mut self,
+        record: usize,
+        name: &str,
+    ) -> Result<Option<DateTime<Utc>>, FormatError> {
+        if self.u8()? == 0 {
+            return Ok(None);
+        }
+        let secs = self.i64()?;
+        let nanos = self.u32()?;
+        DateTime::from_timestamp(secs, nanos)
+            .map(Some)
+            .ok_or_else(|| malformed(record, format!("invalid {name}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_queue::EnqueueOptions;
+    use chrono::Duration;
+
+    fn sample_queue() -> MessageQueue {
+        let mut queue = MessageQueue::default();
+        queue.enqueue("plain").unwrap();
+        queue
+            .enqueue("commas, \"quotes\"\nand\r\nnewlines")
+            .unwrap();
+        let options = EnqueueOptions::default()
+            .priority(Priority::Urgent)
+            .not_before(Utc::now() + Duration::hours(1))
+            .ttl(Duration::hours(2))
+            .header("correlation-id", "a,\"b\"\nc");
+        queue.enqueue_with("", options).unwrap();
+        queue.dequeue();
+        queue
+    }
+
+    #[test]
+    fn csv_round_trips() {
+        let queue = sample_queue();
+        assert_eq!(CsvStore::decode(&CsvStore::encode(&queue)).unwrap(), queue);
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let queue = sample_queue();
+        let json = JsonStore::encode(&queue).unwrap();
+        assert_eq!(JsonStore::decode(&json).unwrap(), queue);
+    }
+
+    #[test]
+    fn binary_round_trips() {
+        let queue = sample_queue();
+        assert_eq!(
+            BinaryStore::decode(&BinaryStore::encode(&queue)).unwrap(),
+            queue
+        );
+    }
+
+    #[test]
+    fn loads_version_1_files() {
+        let mut expected = MessageQueue::default();
+        expected.enqueue("a, b").unwrap();
+        expected.enqueue("c").unwrap();
+
+        let csv = "mq-csv,1,2\n0,\"a, b\"\n1,c\n";
+        assert_eq!(CsvStore::decode(csv).unwrap(), expected);
+
+        let json = br#"{"format":"mq-json","version":1,"queue":{"messages":[
+            {"id":0,"content":"a, b"},{"id":1,"content":"c"}],"next_id":2}}"#;
+        assert_eq!(JsonStore::decode(json).unwrap(), expected);
+
+        let mut binary = b"MQB\0".to_vec();
+        binary.extend_from_slice(&1u16.to_le_bytes());
+        binary.extend_from_slice(&2u32.to_le_bytes());
+        binary.extend_from_slice(&2u32.to_le_bytes());
+        for (id, content) in [(0u32, "a, b"), (1, "c")] {
+            binary.extend_from_slice(&id.to_le_bytes());
+            binary.extend_from_slice(&(content.len() as u32).to_le_bytes());
+            binary.extend_from_slice(content.as_bytes());
+        }
+        assert_eq!(BinaryStore::decode(&binary).unwrap(), expected);
+    }
+
+    #[test]
+    fn loads_version_2_files() {
+        let csv = "mq-csv,2,1\n0,a,high,2030-01-01T00:00:00+00:00\n";
+        let queue = CsvStore::decode(csv).unwrap();
+        assert!(queue.peek_at(Utc::now()).is_none(), "message is delayed");
+
+        let message = &queue.messages[0];
+        assert_eq!(message.priority, Priority::High);
+        assert!(message.not_before.is_some());
+        assert_eq!(message.expires_at, None);
+    }
+
+    #[test]
+    fn loads_unversioned_file_store_json() {
+        let json = br#"{"messages":[{"id":4,"content":"x"}],"next_id":5}"#;
+        let queue = JsonStore::decode(json).unwrap();
+        assert_eq!(queue.iter().next().unwrap().content, "x");
+        assert_eq!(queue.next_id, 5);
+    }
+
+    #[test]
+    fn rejects_newer_and_damaged_files() {
+        assert!(matches!(
+            CsvStore::decode("mq-csv,99,0\n"),
+            Err(FormatError::UnsupportedVersion { version: 99, .. })
+        ));
+        assert!(matches!(
+            CsvStore::decode("mq-csv,2,1\n0,\"unterminated\n"),
+            Err(FormatError::Truncated)
+        ));
+        assert!(matches!(
+            CsvStore::decode("id,content\n"),
+            Err(FormatError::BadHeader(_))
+        ));
+
+        let bytes = BinaryStore::encode(&sample_queue());
+        assert!(matches!(
+            BinaryStore::decode(&bytes[..bytes.len() - 3]),
+            Err(FormatError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn converts_between_backends() {
+        let dir = std::env::temp_dir().join(format!("mq-formats-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let queue = sample_queue();
+
+        let test = || -> Result<(), MessageQueueStorageError> {
+            let csv = Format::Csv.store(dir.join("queue.csv"));
+            csv.save(&queue)?;
+            let binary = Format::from_path("queue.mqb")
+                .unwrap()
+                .store(dir.join("queue.mqb"));
+            assert_eq!(convert(csv.as_ref(), binary.as_ref())?, 2);
+            let json = JsonStore::new(dir.join("queue.json"));
+            convert(binary.as_ref(), &json)?;
+            assert_eq!(json.load()?, queue);
+            Ok(())
+        };
+
+        let result = test();
+        let _ = std::fs::remove_dir_all(&dir);
+        result.unwrap();
+    }
+}
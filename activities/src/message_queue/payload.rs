@@ -0,0 +1,200 @@
+//! Typed message payloads.
+//!
+//! A typed payload is stored as JSON in the message content and tagged with a
+//! [`CONTENT_TYPE`](headers::CONTENT_TYPE) header, so consumers get back the type that was sent
+//! instead of parsing the content by hand.
+
+use super::{EnqueueError, EnqueueOptions, Message, MessageQueue};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Names of well-known message headers.
+pub mod headers {
+    /// The media type of the message content, such as [`JSON`](super::JSON).
+    pub const CONTENT_TYPE: &str = "content-type";
+    /// Ties related messages together, such as a request and its reply.
+    pub const CORRELATION_ID: &str = "correlation-id";
+    /// When the message was created, in RFC 3339 format.
+    pub const CREATED_AT: &str = "created-at";
+}
+
+/// The content type of JSON payloads.
+pub const JSON: &str = "application/json";
+
+/// Errors that may occur while sending or receiving typed payloads.
+#[derive(Debug, thiserror::Error)]
+pub enum PayloadError {
+    #[error("failed to encode payload")]
+    Encode(#[source] serde_json::Error),
+
+    #[error("message {id} has content type {content_type:?}, not {JSON:?}")]
+    ContentType { id: u32, content_type: String },
+
+    #[error("failed to decode payload of message {id}")]
+    Decode {
+        id: u32,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error(transparent)]
+    Rejected(#[from] EnqueueError),
+}
+
+/// A message which was taken off the queue by [`MessageQueue::dequeue_typed`] but whose payload
+/// couldn't be decoded.
+///
+/// The message is handed back so it can be moved to a dead-letter queue or logged, instead of
+/// blocking the messages behind it.
+#[derive(Debug, thiserror::Error)]
+#[error("message {} couldn't be decoded", message.id)]
+pub struct UndecodableMessage {
+    pub message: Message,
+    #[source]
+    pub error: PayloadError,
+}
+
+/// A message whose content has been decoded into a `T`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypedMessage<T> {
+    pub id: u32,
+    pub headers: BTreeMap<String, String>,
+    pub payload: T,
+}
+
+impl Message {
+    /// Decode the content as a JSON payload.
+    ///
+    /// Messages without a content type header are decoded as JSON too, but any other content type
+    /// is an error.
+    pub fn payload<T: DeserializeOwned>(&self) -> Result<T, PayloadError> {
+        match self.header(headers::CONTENT_TYPE) {
+            None | Some(JSON) => (),
+            Some(content_type) => {
+                return Err(PayloadError::ContentType {
+                    id: self.id,
+                    content_type: content_type.to_owned(),
+                })
+            }
+        }
+        serde_json::from_str(&self.content).map_err(|source| PayloadError::Decode {
+            id: self.id,
+            source,
+        })
+    }
+
+    /// When the message was created, if it has a valid [`CREATED_AT`](headers::CREATED_AT)
+    /// header.
+    pub fn created_at(&self) -> Option<DateTime<Utc>> {
+        let created_at = self.header(headers::CREATED_AT)?;
+        let created_at = DateTime::parse_from_rfc3339(created_at).ok()?;
+        Some(created_at.with_timezone(&Utc))
+    }
+}
+
+impl MessageQueue {
+    /// Add a message carrying `payload` encoded as JSON, returning its id.
+    pub fn enqueue_typed<T: Serialize>(&mut self, payload: &T) -> Result<u32, PayloadError> {
+        self.enqueue_typed_with(payload, EnqueueOptions::default())
+    }
+
+    /// Add a message carrying `payload` encoded as JSON using the given options, returning its
+    /// id.
+    ///
+    /// The content type and creation time headers are filled in unless `options` already sets
+    /// them.
+    pub fn enqueue_typed_with<T: Serialize>(
+        &mut self,
+        payload: &T,
+        mut options: EnqueueOptions,
+    ) -> Result<u32, PayloadError> {
+        let content = serde_json::to_string(payload).map_err(PayloadError::Encode)?;
+        options
+            .headers
+            .entry(headers::CONTENT_TYPE.to_owned())
+            .or_insert_with(|| JSON.to_owned());
+        options
+            .headers
+            .entry(headers::CREATED_AT.to_owned())
+            .or_insert_with(|| Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true));
+        Ok(self.enqueue_with(content, options)?)
+    }
+
+    /// Remove the next message and decode its payload.
+    ///
+    /// If the payload can't be decoded the message is still removed, and comes back in the
+    /// error, so one bad message can't stop the queue from being consumed.
+    pub fn dequeue_typed<T: DeserializeOwned>(
+        &mut self,
+    ) -> Result<Option<TypedMessage<T>>, UndecodableMessage> {
+        let Some(message) = self.dequeue() else {
+            return Ok(None);
+        };
+        let payload = match message.payload() {
+            Ok(payload) => payload,
+            Err(error) => return Err(UndecodableMessage { message, error }),
+        };
+        Ok(Some(TypedMessage {
+            id: message.id,
+            headers: message.headers,
+            payload,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Order {
+        item: String,
+        quantity: u32,
+    }
+
+    #[test]
+    fn typed_payloads_round_trip() {
+        let mut queue = MessageQueue::default();
+        let order = Order {
+            item: "widget".into(),
+            quantity: 3,
+        };
+        let options = EnqueueOptions::default().header(headers::CORRELATION_ID, "abc");
+        queue.enqueue_typed_with(&order, options).unwrap();
+
+        let message = queue.peek().unwrap();
+        assert_eq!(message.header(headers::CONTENT_TYPE), Some(JSON));
+        assert!(message.created_at().is_some());
+
+        let received = queue.dequeue_typed::<Order>().unwrap().unwrap();
+        assert_eq!(received.payload, order);
+        assert_eq!(received.headers[headers::CORRELATION_ID], "abc");
+        assert!(queue.dequeue_typed::<Order>().unwrap().is_none());
+    }
+
+    #[test]
+    fn bad_payloads_are_removed_and_returned() {
+        let mut queue = MessageQueue::default();
+        queue.enqueue("not json").unwrap();
+        let text = EnqueueOptions::default().header(headers::CONTENT_TYPE, "text/plain");
+        queue.enqueue_with("{}", text).unwrap();
+        let order = Order {
+            item: "widget".into(),
+            quantity: 1,
+        };
+        queue.enqueue_typed(&order).unwrap();
+
+        let bad = queue.dequeue_typed::<Order>().unwrap_err();
+        assert_eq!(bad.message.content, "not json");
+        assert!(matches!(bad.error, PayloadError::Decode { id: 0, .. }));
+        let bad = queue.dequeue_typed::<Order>().unwrap_err();
+        assert!(matches!(bad.error, PayloadError::ContentType { id: 1, .. }));
+
+        let received = queue.dequeue_typed::<Order>().unwrap().unwrap();
+        assert_eq!(received.payload, order);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,264 @@
+//! The line-based text protocol spoken by the queue [`server`](super::server) and
+//! [`client`](super::client).
+//!
+//! Every request and response is a single line terminated by `\n`:
+//!
+//! | Request             | Response                                      |
+//! |---------------------|-----------------------------------------------|
+//! | `ENQUEUE <content>` | `OK <id>`                                     |
+//! | `DEQUEUE`           | `MESSAGE <id> <content>` or `EMPTY`           |
+//! | `PEEK`              | `MESSAGE <id> <content>` or `EMPTY`           |
+//! | `LEN`               | `LEN <count>`                                 |
+//! | `STATS`             | `STATS len=<n> enqueued=<n> dequeued=<n> connections=<n> expired=<n> rejected=<n>` |
+//!
+//! Any request may instead be answered with `ERR <reason>`. Backslashes, carriage returns and
+//! newlines inside message content are escaped as `\\`, `\r` and `\n`.
+
+use super::Message;
+use std::fmt;
+
+/// Errors that may occur while parsing a request or response line.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ProtocolError {
+    #[error("unknown command: {0}")]
+    UnknownCommand(String),
+
+    #[error("missing {0}")]
+    Missing(&'static str),
+
+    #[error("invalid number: {0}")]
+    InvalidNumber(String),
+
+    #[error("invalid escape sequence in content")]
+    InvalidEscape,
+}
+
+/// A command sent by the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Enqueue(String),
+    Dequeue,
+    Peek,
+    Len,
+    Stats,
+}
+
+/// Counters reported by the `STATS` command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServerStats {
+    pub len: usize,
+    pub enqueued: u64,
+    pub dequeued: u64,
+    pub connections: u64,
+    /// Messages dropped because their time-to-live ran out.
+    pub expired: u64,
+    /// Enqueues refused because the queue was over quota.
+    pub rejected: u64,
+}
+
+/// A reply sent by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Ok(u32),
+    Message(Message),
+    Empty,
+    Len(usize),
+    Stats(ServerStats),
+    Err(String),
+}
+
+impl Request {
+    /// Parse a request line, without its trailing newline.
+    pub fn parse(line: &str) -> Result<Self, ProtocolError> {
+        let (command, rest) = split_word(line);
+        match command.to_ascii_uppercase().as_str() {
+            "ENQUEUE" => Ok(Request::Enqueue(unescape(rest)?)),
+            "DEQUEUE" => Ok(Request::Dequeue),
+            "PEEK" => Ok(Request::Peek),
+            "LEN" => Ok(Request::Len),
+            "STATS" => Ok(Request::Stats),
+            _ => Err(ProtocolError::UnknownCommand(command.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Request::Enqueue(content) => write!(f, "ENQUEUE {}", escape(content)),
+            Request::Dequeue => write!(f, "DEQUEUE"),
+            Request::Peek => write!(f, "PEEK"),
+            Request::Len => write!(f, "LEN"),
+            Request::Stats => write!(f, "STATS"),
+        }
+    }
+}
+
+impl Response {
+    /// Parse a response line, without its trailing newline.
+    pub fn parse(line: &str) -> Result<Self, ProtocolError> {
+        let (kind, rest) = split_word(line);
+        match kind {
+            "OK" => Ok(Response::Ok(parse_number(rest, "message id")?)),
+            "MESSAGE" => {
+                let (id, content) = split_word(rest);
+                let id = parse_number(id, "message id")?;
+                Ok(Response::Message(Message::new(id, unescape(content)?)))
+            }
+            "EMPTY" => Ok(Response::Empty),
+            "LEN" => Ok(Response::Len(parse_number(rest, "length")?)),
+            "STATS" => {
+                let mut stats = ServerStats::default();
+                for pair in rest.split_whitespace() {
+                    let (key, value) = pair.split_once('=').ok_or(ProtocolError::Missing("="))?;
+                    match key {
+                        "len" => stats.len = parse_number(value, "len")?,
+                        "enqueued" => stats.enqueued = parse_number(value, "enqueued")?,
+                        "dequeued" => stats.dequeued = parse_number(value, "dequeued")?,
+                        "connections" => stats.connections = parse_number(value, "connections")?,
+                        "expired" => stats.expired = parse_number(value, "expired")?,
+                        "rejected" => stats.rejected = parse_number(value, "rejected")?,
+                        // ignore counters from newer servers
+                        _ => (),
+                    }
+                }
+                Ok(Response::Stats(stats))
+            }
+            "ERR" => Ok(Response::Err(unescape(rest)?)),
+            _ => Err(ProtocolError::UnknownCommand(kind.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::Ok(id) => write!(f, "OK {id}"),
+            Response::Message(message) => {
+                write!(f, "MESSAGE {} {}", message.id, escape(&message.content))
+            }
+            Response::Empty => write!(f, "EMPTY"),
+            Response::Len(len) => write!(f, "LEN {len}"),
+            Response::Stats(stats) => write!(
+                f,
+                "STATS len={} enqueued={} dequeued={} connections={} expired={} rejected={}",
+                stats.len,
+                stats.enqueued,
+                stats.dequeued,
+                stats.connections,
+                stats.expired,
+                stats.rejected
+            ),
+            Response::Err(reason) => write!(f, "ERR {}", escape(reason)),
+        }
+    }
+}
+
+/// Split off the first space-separated word.
+fn split_word(line: &str) -> (&str, &str) {
+    line.split_once(' ').unwrap_or((line, ""))
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, what: &'static str) -> Result<T, ProtocolError> {
+    if value.is_empty() {
+        return Err(ProtocolError::Missing(what));
+    }
+    value
+        .parse()
+        .map_err(|_| ProtocolError::InvalidNumber(value.to_owned()))
+}
+
+/// Escape content so it fits on a single line.
+pub fn escape(content: &str) -> String {
+    let mut escaped = String::with_capacity(content.len());
+    for c in content.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reverse [`escape`].
+pub fn unescape(content: &str) -> Result<String, ProtocolError> {
+    let mut unescaped = String::with_capacity(content.len());
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            _ => return Err(ProtocolError::InvalidEscape),
+        }
+    }
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_round_trip() {
+        let requests = [
+            Request::Enqueue("multi\nline \\ content\r".into()),
+            Request::Enqueue(String::new()),
+            Request::Dequeue,
+            Request::Peek,
+            Request::Len,
+            Request::Stats,
+        ];
+        for request in requests {
+            assert_eq!(Request::parse(&request.to_string()), Ok(request));
+        }
+    }
+
+    #[test]
+    fn responses_round_trip() {
+        let responses = [
+            Response::Ok(7),
+            Response::Message(Message::new(3, "hello world\n")),
+            Response::Empty,
+            Response::Len(12),
+            Response::Stats(ServerStats {
+                len: 1,
+                enqueued: 2,
+                dequeued: 3,
+                connections: 4,
+                expired: 5,
+                rejected: 6,
+            }),
+            Response::Err("queue is full".into()),
+        ];
+        for response in responses {
+            assert_eq!(Response::parse(&response.to_string()), Ok(response));
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert_eq!(
+            Request::parse("PUSH x"),
+            Err(ProtocolError::UnknownCommand("PUSH".into()))
+        );
+        assert_eq!(
+            Request::parse("ENQUEUE bad \\x"),
+            Err(ProtocolError::InvalidEscape)
+        );
+        assert_eq!(
+            Response::parse("OK"),
+            Err(ProtocolError::Missing("message id"))
+        );
+        assert_eq!(
+            Response::parse("LEN many"),
+            Err(ProtocolError::InvalidNumber("many".into()))
+        );
+    }
+}
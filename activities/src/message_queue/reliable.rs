@@ -0,0 +1,350 @@
+//! At-least-once delivery on top of a [`MessageQueue`].
+//!
+//! Consumers `reserve` a message instead of dequeuing it. A reserved message is hidden from other
+//! consumers for the visibility timeout and must then be `ack`ed (removed for good) or `nack`ed
+//! (returned to the queue). Messages that are not acknowledged in time become visible again. Once
+//! a message has been delivered `max_attempts` times without an ack it is moved to the dead-letter
+//! queue, where it can be inspected and replayed.
+
+use super::{EnqueueError, Message, MessageQueue, QueueLimits};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Controls how reserved messages are redelivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeliveryConfig {
+    /// How long a reserved message stays hidden before it is redelivered.
+    pub visibility_timeout: Duration,
+    /// Number of deliveries after which an unacknowledged message is dead-lettered.
+    pub max_attempts: u32,
+}
+
+impl Default for DeliveryConfig {
+    fn default() -> Self {
+        Self {
+            visibility_timeout: Duration::from_secs(30),
+            max_attempts: 5,
+        }
+    }
+}
+
+/// Identifies a single delivery of a message.
+///
+/// A new receipt is issued every time a message is reserved, so a consumer whose reservation has
+/// expired can't acknowledge a message that was redelivered to someone else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Receipt(u64);
+
+impl fmt::Display for Receipt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A reserved message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delivery {
+    pub message: Message,
+    pub receipt: Receipt,
+    /// Which delivery this is, starting at 1.
+    pub attempt: u32,
+}
+
+/// A message that exhausted its delivery attempts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetter {
+    pub message: Message,
+    pub attempts: u32,
+}
+
+/// Errors that may occur while acknowledging messages.
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum DeliveryError {
+    #[error("receipt {0} is unknown or its reservation has expired")]
+    UnknownReceipt(Receipt),
+}
+
+#[derive(Debug)]
+struct InFlight {
+    message: Message,
+    attempts: u32,
+    visible_at: Instant,
+}
+
+/// A message queue with acknowledgements, visibility timeouts and a dead-letter queue.
+#[derive(Debug, Default)]
+pub struct ReliableQueue {
+    queue: MessageQueue,
+    config: DeliveryConfig,
+    /// Deliveries so far for messages that are waiting in `queue`.
+    attempts: HashMap<u32, u32>,
+    in_flight: HashMap<Receipt, InFlight>,
+    dead_letters: VecDeque<DeadLetter>,
+    next_receipt: u64,
+}
+
+impl ReliableQueue {
+    /// Create an empty queue using the given delivery settings.
+    pub fn new(config: DeliveryConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Create an empty queue using the given delivery settings and queue limits.
+    pub fn with_limits(config: DeliveryConfig, limits: QueueLimits) -> Self {
+        Self {
+            queue: MessageQueue::with_limits(limits),
+            ..Self::new(config)
+        }
+    }
+
+    /// Add a new message to the queue, returning its id.
+    pub fn enqueue<M: Into<String>>(&mut self, message: M) -> Result<u32, EnqueueError> {
+        self.queue.enqueue(message)
+    }
+
+    /// Reserve the next visible message.
+    pub fn reserve(&mut self) -> Option<Delivery> {
+        self.reserve_at(Instant::now())
+    }
+
+    /// Reserve the next visible message as of `now`.
+    pub fn reserve_at(&mut self, now: Instant) -> Option<Delivery> {
+        self.release_expired_at(now);
+
+        let message = self.queue.dequeue()?;
+        let attempts = self.attempts.remove(&message.id).unwrap_or(0) + 1;
+        let receipt = Receipt(self.next_receipt);
+        self.next_receipt += 1;
+
+        self.in_flight.insert(
+            receipt,
+            InFlight {
+                message: message.clone(),
+                attempts,
+                visible_at: now + self.config.visibility_timeout,
+            },
+        );
+
+        Some(Delivery {
+            message,
+            receipt,
+            attempt: attempts,
+        })
+    }
+
+    /// Mark a reserved message as processed, removing it for good.
+    pub fn ack(&mut self, receipt: Receipt) -> Result<Message, DeliveryError> {
+        self.in_flight
+            .remove(&receipt)
+            .map(|in_flight| in_flight.message)
+            .ok_or(DeliveryError::UnknownReceipt(receipt))
+    }
+
+    /// Mark a reserved message as failed so it can be redelivered right away.
+    pub fn nack(&mut self, receipt: Receipt) -> Result<(), DeliveryError> {
+        let in_flight = self
+            .in_flight
+            .remove(&receipt)
+            .ok_or(DeliveryError::UnknownReceipt(receipt))?;
+        self.return_to_queue(in_flight);
+        Ok(())
+    }
+
+    /// Return all reservations whose visibility timeout has passed as of `now`.
+    ///
+    /// Returns the number of messages that were released.
+    pub fn release_expired_at(&mut self, now: Instant) -> usize {
+        let expired: Vec<Receipt> = self
+            .in_flight
+            .iter()
+            .filter(|(_, in_flight)| in_flight.visible_at <= now)
+            .map(|(receipt, _)| *receipt)
+            .collect();
+
+        for receipt in &expired {
+            if let Some(in_flight) = self.in_flight.remove(receipt) {
+                self.return_to_queue(in_flight);
+            }
+        }
+        expired.len()
+    }
+
+    /// Iterate over the messages that exhausted their delivery attempts.
+    pub fn dead_letters(&self) -> std::collections::vec_deque::Iter<'_, DeadLetter> {
+        self.dead_letters.iter()
+    }
+
+    /// Move every dead-lettered message back into the queue with a fresh attempt count.
+    ///
+    /// Returns the number of messages that were replayed.
+    pub fn replay_dead_letters(&mut self) -> usize {
+        let count = self.dead_letters.len();
+        for dead in self.dead_letters.drain(..) {
+            self.queue.requeue(dead.message);
+        }
+        count
+    }
+
+    /// Move a single dead-lettered message back into the queue.
+    ///
+    /// Returns `false` if no dead letter has the given message id.
+    pub fn replay_dead_letter(&mut self, id: u32) -> bool {
+        match self.dead_letters.iter().position(|d| d.message.id == id) {
+            Some(pos) => {
+                let dead = self
+                    .dead_letters
+                    .remove(pos)
+                    .expect("position is in bounds");
+                self.queue.requeue(dead.message);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Number of messages waiting to be reserved.
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Returns `true` if no messages are waiting to be reserved.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Number of messages that are currently reserved.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    fn return_to_queue(&mut self, in_flight: InFlight) {
+        if in_flight.attempts >= self.config.max_attempts {
+            self.dead_letters.push_back(DeadLetter {
+                message: in_flight.message,
+                attempts: in_flight.attempts,
+            });
+        } else {
+            self.attempts
+                .insert(in_flight.message.id, in_flight.attempts);
+            self.queue.requeue(in_flight.message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn queue(max_attempts: u32) -> ReliableQueue {
+        ReliableQueue::new(DeliveryConfig {
+            visibility_timeout: TIMEOUT,
+            max_attempts,
+        })
+    }
+
+    #[test]
+    fn reserved_message_is_hidden_until_timeout() {
+        let mut queue = queue(5);
+        queue.enqueue("a").unwrap();
+        let now = Instant::now();
+
+        let first = queue.reserve_at(now).unwrap();
+        assert_eq!(first.attempt, 1);
+        assert!(queue.reserve_at(now + TIMEOUT / 2).is_none());
+
+        let again = queue.reserve_at(now + TIMEOUT).unwrap();
+        assert_eq!(again.message, first.message);
+        assert_eq!(again.attempt, 2);
+        assert_ne!(again.receipt, first.receipt);
+    }
+
+    #[test]
+    fn ack_removes_message() {
+        let mut queue = queue(5);
+        queue.enqueue("a").unwrap();
+        let now = Instant::now();
+
+        let delivery = queue.reserve_at(now).unwrap();
+        assert_eq!(queue.ack(delivery.receipt).unwrap().content, "a");
+        assert!(queue.reserve_at(now + TIMEOUT * 2).is_none());
+        assert_eq!(
+            queue.ack(delivery.receipt),
+            Err(DeliveryError::UnknownReceipt(delivery.receipt))
+        );
+    }
+
+    #[test]
+    fn expired_receipt_cannot_be_acked() {
+        let mut queue = queue(5);
+        queue.enqueue("a").unwrap();
+        let now = Instant::now();
+
+        let stale = queue.reserve_at(now).unwrap();
+        let fresh = queue.reserve_at(now + TIMEOUT).unwrap();
+        assert!(queue.ack(stale.receipt).is_err());
+        assert!(queue.ack(fresh.receipt).is_ok());
+    }
+
+    #[test]
+    fn nack_returns_message_in_order() {
+        let mut queue = queue(5);
+        queue.enqueue("a").unwrap();
+        queue.enqueue("b").unwrap();
+        let now = Instant::now();
+
+        let a = queue.reserve_at(now).unwrap();
+        queue.nack(a.receipt).unwrap();
+
+        let next = queue.reserve_at(now).unwrap();
+        assert_eq!(next.message.content, "a");
+        assert_eq!(next.attempt, 2);
+    }
+
+    #[test]
+    fn message_is_dead_lettered_after_max_attempts() {
+        let mut queue = queue(2);
+        queue.enqueue("poison").unwrap();
+        queue.enqueue("ok").unwrap();
+        let now = Instant::now();
+
+        let first = queue.reserve_at(now).unwrap();
+        queue.nack(first.receipt).unwrap();
+        let second = queue.reserve_at(now).unwrap();
+        assert_eq!(second.message.content, "poison");
+        // times out instead of being nacked
+        queue.release_expired_at(now + TIMEOUT);
+
+        let dead: Vec<_> = queue.dead_letters().collect();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].message.content, "poison");
+        assert_eq!(dead[0].attempts, 2);
+
+        let next = queue.reserve_at(now + TIMEOUT).unwrap();
+        assert_eq!(next.message.content, "ok");
+    }
+
+    #[test]
+    fn replayed_dead_letters_get_fresh_attempts() {
+        let mut queue = queue(1);
+        queue.enqueue("a").unwrap();
+        let now = Instant::now();
+
+        let delivery = queue.reserve_at(now).unwrap();
+        queue.nack(delivery.receipt).unwrap();
+        assert!(queue.is_empty());
+
+        assert!(!queue.replay_dead_letter(42));
+        assert!(queue.replay_dead_letter(delivery.message.id));
+        assert_eq!(queue.dead_letters().count(), 0);
+
+        let replayed = queue.reserve_at(now).unwrap();
+        assert_eq!(replayed.message.content, "a");
+        assert_eq!(replayed.attempt, 1);
+    }
+}
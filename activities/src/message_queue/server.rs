@@ -0,0 +1,266 @@
+//! Serves a [`SharedQueue`] over TCP using the line [`protocol`](super::protocol).
+//!
+//! Each connection is handled on its own thread, so a client blocked on a full queue doesn't hold
+//! up anyone else.
+//!
+//! The protocol has no authentication, so the server only listens on loopback addresses, where
+//! just processes on this host can reach it. Request lines are limited in length, so one client
+//! can't exhaust the server's memory.
+
+use super::protocol::{Request, Response, ServerStats};
+use super::shared::SharedQueue;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// The longest request line accepted by default, in bytes, not counting the newline.
+pub const DEFAULT_MAX_LINE_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Default)]
+struct Counters {
+    enqueued: AtomicU64,
+    dequeued: AtomicU64,
+    connections: AtomicU64,
+}
+
+/// A TCP server exposing a message queue.
+#[derive(Debug)]
+pub struct Server {
+    listener: TcpListener,
+    queue: SharedQueue,
+    counters: Arc<Counters>,
+    max_line_len: usize,
+}
+
+impl Server {
+    /// Bind the server to `addr`, which must be a loopback address such as `127.0.0.1:7878` or
+    /// `localhost:7878`. Use port 0 to have the OS pick a free port.
+    pub fn bind<A: ToSocketAddrs>(addr: A, queue: SharedQueue) -> io::Result<Self> {
+        let addrs: Vec<_> = addr.to_socket_addrs()?.collect();
+        if let Some(remote) = addrs.iter().find(|addr| !addr.ip().is_loopback()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("refusing to listen on non-loopback address {remote}"),
+            ));
+        }
+        Ok(Self {
+            listener: TcpListener::bind(&addrs[..])?,
+            queue,
+            counters: Arc::default(),
+            max_line_len: DEFAULT_MAX_LINE_LEN,
+        })
+    }
+
+    /// Close connections which send a request line longer than `len` bytes, instead of
+    /// [`DEFAULT_MAX_LINE_LEN`].
+    pub fn with_max_line_len(mut self, len: usize) -> Self {
+        self.max_line_len = len;
+        self
+    }
+
+    /// The address the server is listening on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accept connections until the listener fails.
+    pub fn run(self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let queue = self.queue.clone();
+            let counters = Arc::clone(&self.counters);
+            let max_line_len = self.max_line_len;
+            counters.connections.fetch_add(1, Ordering::Relaxed);
+            thread::spawn(move || {
+                // A client going away mid-request isn't a server error.
+                let _ = handle_connection(stream, &queue, &counters, max_line_len);
+            });
+        }
+        Ok(())
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    queue: &SharedQueue,
+    counters: &Counters,
+    max_line_len: usize,
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut buf = String::new();
+    loop {
+        buf.clear();
+        let limit = max_line_len as u64 + 1;
+        if (&mut reader).take(limit).read_line(&mut buf)? == 0 {
+            return Ok(());
+        }
+        let line = match buf.strip_suffix('\n') {
+            Some(line) => line,
+            // The rest of the line is still unread, so there's no way to find the next request.
+            None if buf.len() > max_line_len => {
+                let response = Response::Err(format!("line longer than {max_line_len} bytes"));
+                return writer.write_all(format!("{response}\n").as_bytes());
+            }
+            None => &buf,
+        };
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            continue;
+        }
+        let response = match Request::parse(line) {
+            Ok(request) => execute(request, queue, counters),
+            Err(e) => Response::Err(e.to_string()),
+        };
+        writer.write_all(format!("{response}\n").as_bytes())?;
+    }
+}
+
+fn execute(request: Request, queue: &SharedQueue, counters: &Counters) -> Response {
+    match request {
+        Request::Enqueue(content) => match queue.enqueue(content) {
+            Ok(id) => {
+                counters.enqueued.fetch_add(1, Ordering::Relaxed);
+                Response::Ok(id)
+            }
+            Err(e) => Response::Err(e.to_string()),
+        },
+        Request::Dequeue => match queue.try_dequeue() {
+            Some(message) => {
+                counters.dequeued.fetch_add(1, Ordering::Relaxed);
+                Response::Message(message)
+            }
+            None => Response::Empty,
+        },
+        Request::Peek => queue.peek().map_or(Response::Empty, Response::Message),
+        Request::Len => Response::Len(queue.len()),
+        Request::Stats => {
+            let queue_stats = queue.stats();
+            Response::Stats(ServerStats {
+                len: queue.len(),
+                enqueued: counters.enqueued.load(Ordering::Relaxed),
+                dequeued: counters.dequeued.load(Ordering::Relaxed),
+                connections: counters.connections.load(Ordering::Relaxed),
+                expired: queue_stats.expired,
+                rejected: queue_stats.rejected,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_queue::client::{Client, ClientError};
+    use crate::message_queue::shared::Backpressure;
+    use std::io::ErrorKind;
+
+    /// Start a server on an ephemeral port and return its address.
+    fn start(server: Server) -> SocketAddr {
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        addr
+    }
+
+    fn start_queue(queue: SharedQueue) -> SocketAddr {
+        start(Server::bind("127.0.0.1:0", queue).unwrap())
+    }
+
+    /// Send `request` on a raw connection and return the first response line.
+    fn raw_request(addr: SocketAddr, request: &[u8]) -> String {
+        let mut raw = TcpStream::connect(addr).unwrap();
+        raw.write_all(request).unwrap();
+        let mut line = String::new();
+        BufReader::new(raw).read_line(&mut line).unwrap();
+        line
+    }
+
+    #[test]
+    fn serves_queue_commands() {
+        let addr = start_queue(SharedQueue::unbounded());
+        let mut client = Client::connect(addr).unwrap();
+
+        assert_eq!(client.len().unwrap(), 0);
+        assert_eq!(client.dequeue().unwrap(), None);
+        assert_eq!(client.enqueue("first\nline").unwrap(), 0);
+        assert_eq!(client.enqueue("second").unwrap(), 1);
+        assert_eq!(client.len().unwrap(), 2);
+
+        let peeked = client.peek().unwrap().unwrap();
+        assert_eq!(peeked.content, "first\nline");
+        let dequeued = client.dequeue().unwrap().unwrap();
+        assert_eq!(dequeued, peeked);
+
+        let stats = client.stats().unwrap();
+        assert_eq!(stats.len, 1);
+        assert_eq!(stats.enqueued, 2);
+        assert_eq!(stats.dequeued, 1);
+        assert_eq!(stats.connections, 1);
+    }
+
+    #[test]
+    fn clients_share_one_queue() {
+        let addr = start_queue(SharedQueue::unbounded());
+        let producers: Vec<_> = (0..4)
+            .map(|p| {
+                thread::spawn(move || {
+                    let mut client = Client::connect(addr).unwrap();
+                    for i in 0..25 {
+                        client.enqueue(format!("{p}-{i}")).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        let mut consumer = Client::connect(addr).unwrap();
+        let mut received = 0;
+        while consumer.dequeue().unwrap().is_some() {
+            received += 1;
+        }
+        assert_eq!(received, 100);
+        assert_eq!(consumer.stats().unwrap().connections, 5);
+    }
+
+    #[test]
+    fn reports_errors_to_client() {
+        let addr = start_queue(SharedQueue::bounded(1, Backpressure::Reject));
+        let mut client = Client::connect(addr).unwrap();
+        client.enqueue("a").unwrap();
+
+        assert!(matches!(
+            client.enqueue("b"),
+            Err(ClientError::Server(reason)) if reason.contains("full")
+        ));
+        assert_eq!(
+            raw_request(addr, b"PUSH x\n"),
+            "ERR unknown command: PUSH\n"
+        );
+    }
+
+    #[test]
+    fn closes_connections_sending_long_lines() {
+        let server = Server::bind("127.0.0.1:0", SharedQueue::unbounded()).unwrap();
+        let addr = start(server.with_max_line_len(16));
+
+        assert_eq!(raw_request(addr, b"ENQUEUE 12345678\n"), "OK 0\n");
+        let long = format!("ENQUEUE {}", "x".repeat(1000));
+        assert_eq!(
+            raw_request(addr, long.as_bytes()),
+            "ERR line longer than 16 bytes\n"
+        );
+        assert_eq!(Client::connect(addr).unwrap().len().unwrap(), 1);
+    }
+
+    #[test]
+    fn only_listens_on_loopback() {
+        let error = Server::bind("0.0.0.0:0", SharedQueue::unbounded()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(Server::bind("localhost:0", SharedQueue::unbounded()).is_ok());
+    }
+}
@@ -0,0 +1,472 @@
+//! A [`MessageQueue`] that can be shared between threads.
+//!
+//! [`SharedQueue`] is a cheap-to-clone handle. Consumers can block until a message is ready (up to
+//! a timeout) and producers can be held back when the queue is at capacity.
+
+use super::{EnqueueError, EnqueueOptions, Message, MessageQueue, QueueStats};
+use chrono::Utc;
+use parking_lot::{Condvar, Mutex, MutexGuard};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// What `enqueue` does when a bounded queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait until a consumer makes room.
+    Block,
+    /// Fail right away with [`SharedQueueError::Full`].
+    Reject,
+}
+
+/// Errors that may occur while adding messages to a [`SharedQueue`].
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum SharedQueueError {
+    #[error("queue is full (capacity {capacity})")]
+    Full { capacity: usize },
+
+    #[error("timed out waiting for space in the queue")]
+    Timeout,
+
+    #[error(transparent)]
+    Rejected(#[from] EnqueueError),
+}
+
+#[derive(Debug)]
+struct Inner {
+    queue: Mutex<MessageQueue>,
+    capacity: Option<usize>,
+    on_full: Backpressure,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+/// A thread-safe handle to a message queue.
+#[derive(Debug, Clone)]
+pub struct SharedQueue {
+    inner: Arc<Inner>,
+}
+
+impl Default for SharedQueue {
+    fn default() -> Self {
+        Self::unbounded()
+    }
+}
+
+impl SharedQueue {
+    /// Create a queue with no capacity limit.
+    pub fn unbounded() -> Self {
+        Self::from_queue(MessageQueue::default(), None, Backpressure::Block)
+    }
+
+    /// Create a queue that holds at most `capacity` messages.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0, since nothing could ever be enqueued.
+    pub fn bounded(capacity: usize, on_full: Backpressure) -> Self {
+        Self::from_queue(MessageQueue::default(), Some(capacity), on_full)
+    }
+
+    /// Share an existing queue, such as one loaded from storage.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is `Some(0)`.
+    pub fn from_queue(queue: MessageQueue, capacity: Option<usize>, on_full: Backpressure) -> Self {
+        assert_ne!(
+            capacity,
+            Some(0),
+            "a bounded queue needs room for a message"
+        );
+        Self {
+            inner: Arc::new(Inner {
+                queue: Mutex::new(queue),
+                capacity,
+                on_full,
+                not_empty: Condvar::new(),
+                not_full: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Add a new message to the queue, returning its id.
+    ///
+    /// When the queue is full this blocks or fails depending on the queue's [`Backpressure`].
+    pub fn enqueue<M: Into<String>>(&self, message: M) -> Result<u32, SharedQueueError> {
+        self.enqueue_with(message, EnqueueOptions::default())
+    }
+
+    /// Add a new message using the given options, returning its id.
+    ///
+    /// When the queue is full this blocks or fails depending on the queue's [`Backpressure`].
+    pub fn enqueue_with<M: Into<String>>(
+        &self,
+        message: M,
+        options: EnqueueOptions,
+    ) -> Result<u32, SharedQueueError> {
+        let queue = match self.inner.on_full {
+            Backpressure::Block => self.wait_for_space(None)?,
+            Backpressure::Reject => self.try_lock_space()?,
+        };
+        self.push(queue, message, options)
+    }
+
+    /// Add a new message, failing right away if the queue is full.
+    pub fn try_enqueue<M: Into<String>>(&self, message: M) -> Result<u32, SharedQueueError> {
+        self.try_enqueue_with(message, EnqueueOptions::default())
+    }
+
+    /// Add a new message using the given options, failing right away if the queue is full.
+    pub fn try_enqueue_with<M: Into<String>>(
+        &self,
+        message: M,
+        options: EnqueueOptions,
+    ) -> Result<u32, SharedQueueError> {
+        let queue = self.try_lock_space()?;
+        self.push(queue, message, options)
+    }
+
+    /// Add a new message, waiting up to `timeout` for space if the queue is full.
+    pub fn enqueue_timeout<M: Into<String>>(
+        &self,
+        message: M,
+        timeout: Duration,
+    ) -> Result<u32, SharedQueueError> {
+        self.enqueue_timeout_with(message, EnqueueOptions::default(), timeout)
+    }
+
+    /// Add a new message using the given options, waiting up to `timeout` for space if the queue
+    /// is full.
+    pub fn enqueue_timeout_with<M: Into<String>>(
+        &self,
+        message: M,
+        options: EnqueueOptions,
+        timeout: Duration,
+    ) -> Result<u32, SharedQueueError> {
+        let queue = self.wait_for_space(Some(Instant::now() + timeout))?;
+        self.push(queue, message, options)
+    }
+
+    /// Enqueue into the locked queue, then wake a waiting consumer.
+    fn push<M: Into<String>>(
+        &self,
+        mut queue: MutexGuard<'_, MessageQueue>,
+        message: M,
+        options: EnqueueOptions,
+    ) -> Result<u32, SharedQueueError> {
+        let id = queue.enqueue_with(message, options)?;
+        drop(queue);
+        self.inner.not_empty.notify_one();
+        Ok(id)
+    }
+
+    /// Remove and return the next ready message, waiting up to `timeout` for one to arrive.
+    pub fn dequeue(&self, timeout: Duration) -> Option<Message> {
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.inner.queue.lock();
+        loop {
+            let now = Utc::now();
+            let before = queue.len();
+            let message = queue.dequeue_at(now);
+            // Purging expired messages makes room even when nothing is ready.
+            if queue.len() < before {
+                self.inner.not_full.notify_all();
+            }
+            if message.is_some() {
+                return message;
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+
+            // Delayed messages don't notify anyone when they become ready, so wake up in time
+            // for the next one.
+            let wake_at = queue
+                .next_ready_time(now)
+                .and_then(|ready| (ready - now).to_std().ok())
+                .map_or(deadline, |wait| deadline.min(Instant::now() + wait));
+            self.inner.not_empty.wait_until(&mut queue, wake_at);
+        }
+    }
+
+    /// Remove and return the next ready message without waiting.
+    pub fn try_dequeue(&self) -> Option<Message> {
+        let mut queue = self.inner.queue.lock();
+        let before = queue.len();
+        let message = queue.dequeue();
+        if queue.len() < before {
+            self.inner.not_full.notify_all();
+        }
+        message
+    }
+
+    /// Return a copy of the next ready message without removing it.
+    pub fn peek(&self) -> Option<Message> {
+        self.inner.queue.lock().peek().cloned()
+    }
+
+    /// Number of messages in the queue, including delayed ones.
+    pub fn len(&self) -> usize {
+        self.inner.queue.lock().len()
+    }
+
+    /// Returns `true` if the queue holds no messages.
+    pub fn is_empty(&self) -> bool {
+        self.inner.queue.lock().is_empty()
+    }
+
+    /// Counters for expired and rejected messages.
+    pub fn stats(&self) -> QueueStats {
+        self.inner.queue.lock().stats()
+    }
+
+    /// The maximum number of messages the queue can hold, if it is bounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.inner.capacity
+    }
+
+    /// Run `f` with exclusive access to the underlying queue.
+    pub fn with_queue<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&MessageQueue) -> T,
+    {
+        f(&self.inner.queue.lock())
+    }
+
+    /// Expired messages don't take up room, so they are purged before deciding the queue is
+    /// full.
+    fn is_full(&self, queue: &mut MessageQueue) -> bool {
+        let Some(capacity) = self.inner.capacity else {
+            return false;
+        };
+        if queue.len() >= capacity && queue.purge_expired_at(Utc::now()) > 0 {
+            // there may be room for other waiting producers too
+            self.inner.not_full.notify_all();
+        }
+        queue.len() >= capacity
+    }
+
+    fn try_lock_space(&self) -> Result<MutexGuard<'_, MessageQueue>, SharedQueueError> {
+        let mut queue = self.inner.queue.lock();
+        match self.inner.capacity {
+            Some(capacity) if self.is_full(&mut queue) => Err(SharedQueueError::Full { capacity }),
+            _ => Ok(queue),
+        }
+    }
+
+    fn wait_for_space(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Result<MutexGuard<'_, MessageQueue>, SharedQueueError> {
+        let mut queue = self.inner.queue.lock();
+        while self.is_full(&mut queue) {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(SharedQueueError::Timeout);
+            }
+
+            // Messages don't notify anyone when they expire, so wake up in time to purge the
+            // next one.
+            let now = Utc::now();
+            let expiry = queue
+                .next_expiry_time()
+                .and_then(|expires_at| (expires_at - now).to_std().ok())
+                .map(|wait| Instant::now() + wait);
+            match deadline.into_iter().chain(expiry).min() {
+                Some(wake_at) => {
+                    self.inner.not_full.wait_until(&mut queue, wake_at);
+                }
+                None => self.inner.not_full.wait(&mut queue),
+            }
+        }
+        Ok(queue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    const PRODUCERS: usize = 8;
+    const CONSUMERS: usize = 8;
+    const PER_PRODUCER: usize = 500;
+
+    /// Runs producers and consumers against `queue` and returns every message content received.
+    fn stress(queue: SharedQueue) -> Vec<String> {
+        let done = Arc::new(AtomicBool::new(false));
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|p| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    for i in 0..PER_PRODUCER {
+                        queue.enqueue(format!("{p}-{i}")).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let consumers: Vec<_> = (0..CONSUMERS)
+            .map(|_| {
+                let queue = queue.clone();
+                let done = Arc::clone(&done);
+                thread::spawn(move || {
+                    let mut received = Vec::new();
+                    loop {
+                        // Checked before dequeuing so the final messages aren't missed.
+                        let finished = done.load(Ordering::SeqCst);
+                        match queue.dequeue(Duration::from_millis(10)) {
+                            Some(message) => received.push(message.content),
+                            None if finished => break,
+                            None => (),
+                        }
+                    }
+                    received
+                })
+            })
+            .collect();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+        consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .collect()
+    }
+
+    fn assert_received_once(received: Vec<String>) {
+        let total = PRODUCERS * PER_PRODUCER;
+        assert_eq!(received.len(), total);
+        let unique: HashSet<_> = received.into_iter().collect();
+        assert_eq!(unique.len(), total);
+    }
+
+    #[test]
+    fn stress_unbounded() {
+        assert_received_once(stress(SharedQueue::unbounded()));
+    }
+
+    #[test]
+    fn stress_bounded_with_blocking_producers() {
+        assert_received_once(stress(SharedQueue::bounded(4, Backpressure::Block)));
+    }
+
+    #[test]
+    fn bounded_queue_never_exceeds_capacity() {
+        let queue = SharedQueue::bounded(3, Backpressure::Block);
+        let watcher = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                let mut max = 0;
+                for _ in 0..2000 {
+                    max = max.max(queue.len());
+                    thread::yield_now();
+                }
+                max
+            })
+        };
+        assert_received_once(stress(queue));
+        assert!(watcher.join().unwrap() <= 3);
+    }
+
+    #[test]
+    fn reject_policy_fails_when_full() {
+        let queue = SharedQueue::bounded(2, Backpressure::Reject);
+        queue.enqueue("a").unwrap();
+        queue.enqueue("b").unwrap();
+        assert_eq!(
+            queue.enqueue("c"),
+            Err(SharedQueueError::Full { capacity: 2 })
+        );
+
+        queue.try_dequeue().unwrap();
+        assert!(queue.enqueue("c").is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "room for a message")]
+    fn zero_capacity_is_rejected() {
+        SharedQueue::bounded(0, Backpressure::Block);
+    }
+
+    #[test]
+    fn non_blocking_enqueues_honour_options() {
+        let queue = SharedQueue::bounded(2, Backpressure::Block);
+        let later = Utc::now() + chrono::Duration::minutes(5);
+        let delayed = || EnqueueOptions::default().not_before(later);
+        queue.try_enqueue_with("try", delayed()).unwrap();
+        queue
+            .enqueue_timeout_with("timeout", delayed(), Duration::from_millis(20))
+            .unwrap();
+
+        assert_eq!(queue.len(), 2);
+        assert!(queue.try_dequeue().is_none());
+    }
+
+    #[test]
+    fn enqueue_timeout_waits_for_space() {
+        let queue = SharedQueue::bounded(1, Backpressure::Block);
+        queue.enqueue("a").unwrap();
+        assert_eq!(
+            queue.enqueue_timeout("b", Duration::from_millis(20)),
+            Err(SharedQueueError::Timeout)
+        );
+
+        let consumer = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                queue.try_dequeue()
+            })
+        };
+        assert!(queue.enqueue_timeout("b", Duration::from_secs(5)).is_ok());
+        assert_eq!(consumer.join().unwrap().unwrap().content, "a");
+    }
+
+    #[test]
+    fn expired_messages_dont_take_up_room() {
+        let expiring = || EnqueueOptions::default().ttl(chrono::Duration::milliseconds(20));
+
+        let rejecting = SharedQueue::bounded(1, Backpressure::Reject);
+        rejecting.enqueue_with("a", expiring()).unwrap();
+        assert!(rejecting.enqueue("b").is_err());
+        thread::sleep(Duration::from_millis(30));
+        assert!(rejecting.enqueue("b").is_ok());
+
+        // a blocked producer wakes up when the message in its way expires
+        let blocking = SharedQueue::bounded(1, Backpressure::Block);
+        blocking.enqueue_with("a", expiring()).unwrap();
+        let start = Instant::now();
+        assert!(blocking
+            .enqueue_timeout("b", Duration::from_secs(5))
+            .is_ok());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(blocking.try_dequeue().unwrap().content, "b");
+    }
+
+    #[test]
+    fn dequeue_times_out_when_empty() {
+        let queue = SharedQueue::unbounded();
+        let start = Instant::now();
+        assert!(queue.try_dequeue().is_none());
+        assert!(queue.dequeue(Duration::from_millis(20)).is_none());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn blocking_dequeue_wakes_for_delayed_message() {
+        let queue = SharedQueue::unbounded();
+        let ready_at = Utc::now() + chrono::Duration::milliseconds(30);
+        queue
+            .enqueue_with("later", EnqueueOptions::default().not_before(ready_at))
+            .unwrap();
+
+        assert!(queue.try_dequeue().is_none());
+        let message = queue.dequeue(Duration::from_secs(5)).unwrap();
+        assert_eq!(message.content, "later");
+        assert!(Utc::now() >= ready_at);
+    }
+}
@@ -0,0 +1,437 @@
+//! Write-ahead log persistence for a [`MessageQueue`].
+//!
+//! Every `enqueue` and `dequeue` on a [`DurableQueue`] is appended to a log file and flushed to
+//! disk before the call returns. Opening the queue loads the most recent snapshot and replays the
+//! log on top of it. Compaction folds the log into a new snapshot and truncates the log.
+//!
+//! Records are framed as `[length: u32][checksum: u32][payload]` (little endian). If the process
+//! dies while a record is being written, the final frame will be short or fail its checksum. That
+//! torn record is skipped during replay and cut off the end of the log.
+//!
+//! An append which fails part way, for example because the disk is full, cuts its partial frame
+//! off again before returning. So a bad frame with more records after it wasn't torn by a crash,
+//! and opening the queue fails rather than discarding the records which follow it.
+
+use super::{
+    EnqueueError, EnqueueOptions, FileStore, Message, MessageQueue, MessageQueueStorage,
+    MessageQueueStorageError, QueueLimits,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+pub(crate) const SNAPSHOT_FILE: &str = "snapshot.json";
+const LOG_FILE: &str = "queue.wal";
+const FRAME_HEADER_LEN: usize = 8;
+
+/// Errors that may occur while working with a [`DurableQueue`].
+#[derive(Debug, thiserror::Error)]
+pub enum WalError {
+    #[error("IO error")]
+    IO(#[from] io::Error),
+
+    #[error("malformed log record")]
+    Format(#[from] serde_json::Error),
+
+    #[error("corrupt log record at byte {offset}, with more records after it")]
+    Corrupt { offset: usize },
+
+    #[error("snapshot error")]
+    Snapshot(#[from] MessageQueueStorageError),
+
+    #[error("message rejected")]
+    Rejected(#[from] EnqueueError),
+}
+
+/// A single change to the queue.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum Record {
+    Enqueue(Message),
+    Dequeue { id: u32 },
+}
+
+impl Record {
+    /// Apply this record to the queue.
+    ///
+    /// Records that are already reflected in the queue are ignored. This makes replay safe when a
+    /// crash happens after a snapshot was written but before the log was truncated.
+    fn apply(self, queue: &mut MessageQueue) {
+        match self {
+            Record::Enqueue(message) => {
+                if message.id >= queue.next_id {
+                    queue.next_id = message.id + 1;
+                    queue.messages.push_back(message);
+                }
+            }
+            Record::Dequeue { id } => {
+                if let Some(pos) = queue.messages.iter().position(|m| m.id == id) {
+                    queue.messages.remove(pos);
+                }
+            }
+        }
+    }
+}
+
+/// Information about what happened when the log was replayed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Recovery {
+    /// Number of records applied on top of the snapshot.
+    pub replayed: usize,
+    /// Number of bytes dropped from the end of the log because of a torn record.
+    pub torn_bytes: u64,
+}
+
+/// A [`MessageQueue`] where every change is written to a log before it is applied.
+#[derive(Debug)]
+pub struct DurableQueue {
+    queue: MessageQueue,
+    snapshot: FileStore,
+    log: File,
+    records_since_compaction: usize,
+    compact_after: Option<usize>,
+    recovery: Recovery,
+}
+
+impl DurableQueue {
+    /// Open the queue stored in `dir`, creating it if it doesn't exist yet.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, WalError> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+
+        let snapshot = FileStore::new(dir.join(SNAPSHOT_FILE));
+        let mut queue = if snapshot.path().exists() {
+            snapshot.load()?
+        } else {
+            MessageQueue::default()
+        };
+
+        let log_path: PathBuf = dir.join(LOG_FILE);
+        let mut log = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&log_path)?;
+
+        let mut bytes = Vec::new();
+        log.read_to_end(&mut bytes)?;
+        let (records, valid_len) = decode_frames(&bytes)?;
+
+        let recovery = Recovery {
+            replayed: records.len(),
+            torn_bytes: (bytes.len() - valid_len) as u64,
+        };
+        if recovery.torn_bytes > 0 {
+            log.set_len(valid_len as u64)?;
+            log.sync_data()?;
+        }
+
+        let records_since_compaction = records.len();
+        for record in records {
+            record.apply(&mut queue);
+        }
+
+        Ok(Self {
+            queue,
+            snapshot,
+            log,
+            records_since_compaction,
+            compact_after: None,
+            recovery,
+        })
+    }
+
+    /// Automatically compact the log once it holds `records` records.
+    pub fn compact_every(mut self, records: usize) -> Self {
+        self.compact_after = Some(records.max(1));
+        self
+    }
+
+    /// Enforce `limits` on messages enqueued from now on.
+    pub fn with_limits(mut self, limits: QueueLimits) -> Self {
+        self.queue.set_limits(limits);
+        self
+    }
+
+    /// Add a new message to the queue.
+    pub fn enqueue<M: Into<String>>(&mut self, message: M) -> Result<(), WalError> {
+        self.enqueue_with(message, EnqueueOptions::default())
+    }
+
+    /// Add a new message using the given options.
+    pub fn enqueue_with<M: Into<String>>(
+        &mut self,
+        message: M,
+        options: EnqueueOptions,
+    ) -> Result<(), WalError> {
+        let message = self.queue.prepare(message, options, Utc::now())?;
+        self.commit(Record::Enqueue(message))
+    }
+
+    /// Remove and return the highest priority message that is ready for delivery.
+    pub fn dequeue(&mut self) -> Result<Option<Message>, WalError> {
+        let now = Utc::now();
+        // Expired messages aren't logged as removed. They expire again when the log is replayed.
+        self.queue.purge_expired_at(now);
+        let Some(pos) = self.queue.next_ready(now) else {
+            return Ok(None);
+        };
+        let message = self.queue.messages[pos].clone();
+        self.commit(Record::Dequeue { id: message.id })?;
+        Ok(Some(message))
+    }
+
+    /// Iterate over all messages in the queue, skipping expired ones.
+    pub fn iter(&self) -> impl Iterator<Item = &Message> {
+        self.queue.iter()
+    }
+
+    /// The in-memory queue rebuilt from the snapshot and log.
+    pub fn queue(&self) -> &MessageQueue {
+        &self.queue
+    }
+
+    /// What happened while replaying the log when the queue was opened.
+    pub fn recovery(&self) -> Recovery {
+        self.recovery
+    }
+
+    /// Fold the log into a new snapshot and truncate the log.
+    pub fn compact(&mut self) -> Result<(), WalError> {
+        // The snapshot is flushed to disk before this returns, so a power loss can't keep the
+        // truncated log without the snapshot that replaces it.
+        self.snapshot.save(&self.queue)?;
+        self.log.set_len(0)?;
+        self.log.sync_data()?;
+        self.records_since_compaction = 0;
+        Ok(())
+    }
+
+    fn commit(&mut self, record: Record) -> Result<(), WalError> {
+        self.append(&record)?;
+        record.apply(&mut self.queue);
+        self.records_since_compaction += 1;
+        self.maybe_compact()
+    }
+
+    fn maybe_compact(&mut self) -> Result<(), WalError> {
+        match self.compact_after {
+            Some(limit) if self.records_since_compaction >= limit => self.compact(),
+            _ => Ok(()),
+        }
+    }
+
+    fn append(&mut self, record: &Record) -> Result<(), WalError> {
+        let payload = serde_json::to_vec(record)?;
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&checksum(&payload).to_le_bytes());
+        frame.extend_from_slice(&payload);
+
+        let len = self.log.metadata()?.len();
+        if let Err(e) = self
+            .log
+            .write_all(&frame)
+            .and_then(|()| self.log.sync_data())
+        {
+            // Cut off whatever part of the frame was written, so it can't end up in the middle
+            // of the log once later records are appended.
+            self.log.set_len(len)?;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+/// Decode all complete frames in `bytes`.
+///
+/// Returns the records along with the number of bytes that make up valid frames. Decoding stops at
+/// a final frame that is short or fails its checksum. A frame that fails its checksum but has
+/// more bytes after it is an error.
+fn decode_frames(bytes: &[u8]) -> Result<(Vec<Record>, usize), WalError> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while bytes.len() - offset >= FRAME_HEADER_LEN {
+        let header = &bytes[offset..offset + FRAME_HEADER_LEN];
+        let len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+        let sum = u32::from_le_bytes(header[4..8].try_into().unwrap());
+
+        let start = offset + FRAME_HEADER_LEN;
+        let Some(payload) = bytes.get(start..start + len) else {
+            break;
+        };
+        if checksum(payload) != sum {
+            if start + len < bytes.len() {
+                return Err(WalError::Corrupt { offset });
+            }
+            break;
+        }
+        records.push(serde_json::from_slice(payload)?);
+        offset = start + len;
+    }
+    Ok((records, offset))
+}
+
+/// 32-bit FNV-1a hash used to detect torn records.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch directory that is removed when dropped.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("mq-wal-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn contents(queue: &DurableQueue) -> Vec<(u32, String)> {
+        queue.iter().map(|m| (m.id, m.content.clone())).collect()
+    }
+
+    #[test]
+    fn replays_log_after_reopen() {
+        let dir = TestDir::new("replay");
+        {
+            let mut queue = DurableQueue::open(&dir.0).unwrap();
+            queue.enqueue("a").unwrap();
+            queue.enqueue("b").unwrap();
+            assert_eq!(queue.dequeue().unwrap().unwrap().content, "a");
+            queue.enqueue("c").unwrap();
+        }
+
+        let mut queue = DurableQueue::open(&dir.0).unwrap();
+        assert_eq!(queue.recovery().replayed, 4);
+        assert_eq!(contents(&queue), vec![(1, "b".into()), (2, "c".into())]);
+
+        queue.enqueue("d").unwrap();
+        assert_eq!(queue.iter().last().unwrap().id, 3);
+    }
+
+    #[test]
+    fn skips_torn_final_record() {
+        let dir = TestDir::new("torn");
+        {
+            let mut queue = DurableQueue::open(&dir.0).unwrap();
+            queue.enqueue("a").unwrap();
+            queue.enqueue("b").unwrap();
+        }
+
+        // Chop the last record in half, as if the power went out mid-write.
+        let log_path = dir.0.join(LOG_FILE);
+        let len = std::fs::metadata(&log_path).unwrap().len();
+        let log = OpenOptions::new().write(true).open(&log_path).unwrap();
+        log.set_len(len - 5).unwrap();
+        drop(log);
+
+        let mut queue = DurableQueue::open(&dir.0).unwrap();
+        assert_eq!(queue.recovery().replayed, 1);
+        assert!(queue.recovery().torn_bytes > 0);
+        assert_eq!(contents(&queue), vec![(0, "a".into())]);
+
+        // The log is usable again after recovery.
+        queue.enqueue("c").unwrap();
+        drop(queue);
+        let queue = DurableQueue::open(&dir.0).unwrap();
+        assert_eq!(queue.recovery().torn_bytes, 0);
+        assert_eq!(contents(&queue), vec![(0, "a".into()), (1, "c".into())]);
+    }
+
+    #[test]
+    fn skips_record_with_bad_checksum() {
+        let dir = TestDir::new("checksum");
+        {
+            let mut queue = DurableQueue::open(&dir.0).unwrap();
+            queue.enqueue("a").unwrap();
+            queue.enqueue("b").unwrap();
+        }
+
+        let log_path = dir.0.join(LOG_FILE);
+        let mut bytes = std::fs::read(&log_path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(&log_path, bytes).unwrap();
+
+        let queue = DurableQueue::open(&dir.0).unwrap();
+        assert_eq!(contents(&queue), vec![(0, "a".into())]);
+    }
+
+    #[test]
+    fn refuses_to_drop_records_after_a_bad_one() {
+        let dir = TestDir::new("corrupt");
+        {
+            let mut queue = DurableQueue::open(&dir.0).unwrap();
+            queue.enqueue("a").unwrap();
+            queue.enqueue("b").unwrap();
+            queue.enqueue("c").unwrap();
+        }
+
+        // Damage the last byte of the middle record.
+        let log_path = dir.0.join(LOG_FILE);
+        let mut bytes = std::fs::read(&log_path).unwrap();
+        let frame_end = |offset: usize| {
+            let len = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+            offset + FRAME_HEADER_LEN + len as usize
+        };
+        let first = frame_end(0);
+        let second = frame_end(first);
+        bytes[second - 1] ^= 0xff;
+        std::fs::write(&log_path, &bytes).unwrap();
+
+        assert!(matches!(
+            DurableQueue::open(&dir.0),
+            Err(WalError::Corrupt { offset }) if offset == first
+        ));
+        assert_eq!(std::fs::read(&log_path).unwrap(), bytes);
+    }
+
+    #[test]
+    fn compaction_folds_log_into_snapshot() {
+        let dir = TestDir::new("compact");
+        {
+            let mut queue = DurableQueue::open(&dir.0).unwrap().compact_every(3);
+            queue.enqueue("a").unwrap();
+            queue.enqueue("b").unwrap();
+            queue.dequeue().unwrap();
+            assert_eq!(std::fs::metadata(dir.0.join(LOG_FILE)).unwrap().len(), 0);
+            queue.enqueue("c").unwrap();
+        }
+
+        let queue = DurableQueue::open(&dir.0).unwrap();
+        assert_eq!(queue.recovery().replayed, 1);
+        assert_eq!(contents(&queue), vec![(1, "b".into()), (2, "c".into())]);
+    }
+
+    #[test]
+    fn replay_is_idempotent_when_log_was_not_truncated() {
+        let dir = TestDir::new("idempotent");
+        {
+            let mut queue = DurableQueue::open(&dir.0).unwrap();
+            queue.enqueue("a").unwrap();
+            queue.enqueue("b").unwrap();
+            queue.dequeue().unwrap();
+            // Simulate a crash between writing the snapshot and truncating the log.
+            queue.snapshot.save(&queue.queue).unwrap();
+        }
+
+        let queue = DurableQueue::open(&dir.0).unwrap();
+        assert_eq!(contents(&queue), vec![(1, "b".into())]);
+        assert_eq!(queue.queue().next_id, 2);
+    }
+}
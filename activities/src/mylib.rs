@@ -1 +1,6 @@
+pub mod bank;
+pub mod inventory;
+pub mod message_queue;
+pub mod sensor;
 
+mod util;
@@ -0,0 +1,293 @@
+//! Temperature sensors: recording readings and analyzing them as they arrive.
+//!
+//! Sensors run for months, so [`TemperatureSensor`] only keeps recent readings. Its statistics
+//! over every reading are updated as each one arrives using the streaming [`stats`].
+//! Timestamped readings are kept for an hour by default in a [`series::TimeSeries`], which can
+//! be resampled and checked for periods when the sensor was offline.
+
+pub mod series;
+pub mod stats;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use series::{Aggregation, Bucket, Gap, SeriesError, TimeSeries};
+use stats::{Ewma, P2Quantile, RunningStats, SlidingWindow, Summary, WindowSize};
+
+/// The weight of each reading in [`TemperatureSensor::ewma`] by default.
+pub const DEFAULT_EWMA_ALPHA: f64 = 0.1;
+
+/// The percentiles tracked by default.
+pub const DEFAULT_PERCENTILES: [f64; 2] = [0.5, 0.95];
+
+/// How close a quantile passed to [`TemperatureSensor::percentile`] must be to a tracked one.
+const QUANTILE_TOLERANCE: f64 = 1e-9;
+
+/// The sliding window used by default: the latest 100 readings.
+pub const DEFAULT_WINDOW: WindowSize = WindowSize::Readings(100);
+
+/// Records and analyzes temperature readings.
+///
+/// The sensor keeps the readings taken within its retention period, and those in its sliding
+/// window, so its memory grows with how often readings arrive rather than how long it runs.
+///
+/// Readings which aren't finite numbers are ignored, and readings more than the max lead ahead
+/// of the current time are rejected.
+#[derive(Debug, Clone)]
+pub struct TemperatureSensor {
+    stats: RunningStats,
+    ewma: Ewma,
+    percentiles: Vec<P2Quantile>,
+    window: SlidingWindow,
+    readings: TimeSeries,
+}
+
+impl Default for TemperatureSensor {
+    fn default() -> Self {
+        Self {
+            stats: RunningStats::default(),
+            ewma: Ewma::new(DEFAULT_EWMA_ALPHA),
+            percentiles: DEFAULT_PERCENTILES.map(P2Quantile::new).to_vec(),
+            window: SlidingWindow::new(DEFAULT_WINDOW),
+            readings: TimeSeries::default(),
+        }
+    }
+}
+
+impl TemperatureSensor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Weight each reading by `alpha` in the moving average.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `alpha` isn't in `(0, 1]`.
+    pub fn with_ewma_alpha(mut self, alpha: f64) -> Self {
+        self.ewma = Ewma::new(alpha);
+        self
+    }
+
+    /// Track these percentiles, given as quantiles such as 0.95, instead of the defaults.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a quantile isn't between 0 and 1.
+    pub fn with_percentiles(mut self, quantiles: &[f64]) -> Self {
+        self.percentiles = quantiles.iter().copied().map(P2Quantile::new).collect();
+        self
+    }
+
+    /// Keep window statistics over `size`.
+    pub fn with_window(mut self, size: WindowSize) -> Self {
+        self.window = SlidingWindow::new(size);
+        self
+    }
+
+    /// Keep timestamped readings taken within `retention` of the latest one, instead of
+    /// [`series::DEFAULT_RETENTION`].
+    pub fn with_retention(mut self, retention: TimeDelta) -> Self {
+        self.readings =
+            TimeSeries::with_retention(retention).with_max_lead(self.readings.max_lead());
+        self
+    }
+
+    /// Reject readings more than `max_lead` ahead of the current time, instead of
+    /// [`series::DEFAULT_MAX_LEAD`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_lead` is negative.
+    pub fn with_max_lead(mut self, max_lead: TimeDelta) -> Self {
+        self.readings = self.readings.with_max_lead(max_lead);
+        self
+    }
+
+    /// Add a reading taken now.
+    pub fn record_temperature(&mut self, temperature: f64) {
+        self.record_temperature_at(temperature, Utc::now())
+            .expect("a reading taken now is never ahead of the current time");
+    }
+
+    /// Add a reading taken at `at`. Readings must be recorded in time order.
+    ///
+    /// Readings which aren't finite are ignored. A reading too far ahead of the current time is
+    /// rejected with [`SeriesError::TooFarAhead`], and nothing changes.
+    pub fn record_temperature_at(
+        &mut self,
+        temperature: f64,
+        at: DateTime<Utc>,
+    ) -> Result<(), SeriesError> {
+        if !temperature.is_finite() {
+            return Ok(());
+        }
+        self.readings.push(temperature, at)?;
+        self.stats.push(temperature);
+        self.ewma.push(temperature);
+        for percentile in &mut self.percentiles {
+            percentile.push(temperature);
+        }
+        self.window.push(temperature, at);
+        Ok(())
+    }
+
+    /// The number of readings recorded.
+    pub fn count(&self) -> u64 {
+        self.stats.count()
+    }
+
+    /// The average of every reading, or `None` if there haven't been any.
+    pub fn get_average_temperature(&self) -> Option<f64> {
+        self.summary().map(|summary| summary.mean)
+    }
+
+    /// The highest reading, or `None` if there haven't been any.
+    pub fn get_max_temperature(&self) -> Option<f64> {
+        self.summary().map(|summary| summary.max)
+    }
+
+    /// Statistics over every reading.
+    pub fn summary(&self) -> Option<Summary> {
+        self.stats.summary()
+    }
+
+    /// The exponentially weighted moving average.
+    pub fn ewma(&self) -> Option<f64> {
+        self.ewma.value()
+    }
+
+    /// The estimated percentile for quantile `p`, such as 0.95, or `None` if it isn't tracked
+    /// or there haven't been any readings.
+    ///
+    /// `p` only has to be very close to a tracked quantile, so one which was calculated, such as
+    /// `0.1 * 3.0`, still finds the 0.3 quantile.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        self.percentiles
+            .iter()
+            .find(|percentile| (percentile.quantile() - p).abs() < QUANTILE_TOLERANCE)?
+            .estimate()
+    }
+
+    /// Statistics over the sliding window as of now.
+    pub fn window_summary(&self) -> Option<Summary> {
+        self.window_summary_at(Utc::now())
+    }
+
+    /// Statistics over the sliding window as of `now`.
+    pub fn window_summary_at(&self, now: DateTime<Utc>) -> Option<Summary> {
+        self.window.summary_at(now)
+    }
+
+    /// The readings still retained, in time order.
+    pub fn readings(&self) -> &TimeSeries {
+        &self.readings
+    }
+
+    /// The retained readings combined into buckets of `width`.
+    ///
+    /// Fails with [`SeriesError::TooManyBuckets`] if that would take more than
+    /// [`series::MAX_BUCKETS`] buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `width` isn't at least a millisecond.
+    pub fn resample(
+        &self,
+        width: TimeDelta,
+        aggregation: Aggregation,
+    ) -> Result<Vec<Bucket>, SeriesError> {
+        self.readings.resample(width, aggregation)
+    }
+
+    /// The periods when the sensor was offline, meaning no reading arrived within `expected`
+    /// of the one before, up to now.
+    pub fn gaps(&self, expected: TimeDelta) -> Vec<Gap> {
+        self.gaps_at(expected, Utc::now())
+    }
+
+    /// The periods when the sensor was offline, up to `now`.
+    pub fn gaps_at(&self, expected: TimeDelta, now: DateTime<Utc>) -> Vec<Gap> {
+        self.readings.gaps_at(expected, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn tracks_statistics_without_keeping_readings() {
+        let start: DateTime<Utc> = "2024-06-01T00:00:00Z".parse().unwrap();
+        let mut sensor = TemperatureSensor::new()
+            .with_window(WindowSize::Span(TimeDelta::hours(1)))
+            .with_percentiles(&[0.5, 0.3])
+            .with_ewma_alpha(0.5);
+        assert_eq!(sensor.get_average_temperature(), None);
+
+        // a day of readings every minute, warming from 10 to about 34 degrees
+        for minute in 0..24 * 60 {
+            let at = start + TimeDelta::minutes(minute);
+            sensor
+                .record_temperature_at(10.0 + minute as f64 / 60.0, at)
+                .unwrap();
+        }
+        sensor.record_temperature(f64::NAN);
+
+        assert_eq!(sensor.count(), 1440);
+        assert_eq!(sensor.get_max_temperature(), Some(10.0 + 1439.0 / 60.0));
+        let average = sensor.get_average_temperature().unwrap();
+        assert!((average - 21.99).abs() < 0.01, "average was {average}");
+        let median = sensor.percentile(0.5).unwrap();
+        assert!((median - 22.0).abs() < 0.1, "median was {median}");
+        assert_eq!(sensor.percentile(0.95), None);
+        assert_ne!(0.1 * 3.0, 0.3);
+        assert!(sensor.percentile(0.1 * 3.0).is_some());
+        assert!(sensor.ewma().unwrap() > 33.9);
+
+        let end = start + TimeDelta::minutes(24 * 60 - 1);
+        let last_hour = sensor.window_summary_at(end).unwrap();
+        assert_eq!(last_hour.count, 60);
+        assert_eq!(last_hour.min, 33.0);
+    }
+
+    #[test]
+    fn tells_a_steady_signal_from_a_dead_sensor() {
+        let start: DateTime<Utc> = "2024-06-01T00:00:00Z".parse().unwrap();
+        let minutes = |m: i64| start + TimeDelta::minutes(m);
+        let mut steady = TemperatureSensor::new();
+        let mut dead = TemperatureSensor::new();
+        for minute in 0..60 {
+            steady.record_temperature_at(21.0, minutes(minute)).unwrap();
+            if minute < 10 {
+                dead.record_temperature_at(21.0, minutes(minute)).unwrap();
+            }
+        }
+        assert_eq!(
+            steady.get_average_temperature(),
+            dead.get_average_temperature()
+        );
+
+        let now = minutes(60);
+        assert_eq!(steady.gaps_at(TimeDelta::minutes(2), now), []);
+        let gaps = dead.gaps_at(TimeDelta::minutes(2), now);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].start, minutes(9));
+        assert!(gaps[0].is_ongoing());
+
+        let buckets = dead
+            .resample(TimeDelta::minutes(5), Aggregation::Last)
+            .unwrap();
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[1].count, 5);
+
+        // a reading from a clock which jumped a year ahead is rejected and changes nothing
+        let next_year = Utc::now() + TimeDelta::days(365);
+        assert!(matches!(
+            dead.record_temperature_at(90.0, next_year),
+            Err(SeriesError::TooFarAhead { .. })
+        ));
+        assert_eq!(dead.count(), 10);
+        assert_eq!(dead.get_max_temperature(), Some(21.0));
+        assert_eq!(dead.readings().len(), 10);
+    }
+}
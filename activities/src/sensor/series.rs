@@ -0,0 +1,399 @@
+//! Timestamped readings: resampling them into fixed buckets and finding when they stopped.
+//!
+//! A [`TimeSeries`] keeps readings for a limited time, so a sensor which runs for months only
+//! holds the readings which arrived within that time. Gaps which are older than that can no
+//! longer be reported. A reading stamped well ahead of the current time, say by a clock which
+//! jumped, is rejected rather than allowed to push every other reading out.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use std::collections::VecDeque;
+
+/// How long readings are kept by default.
+pub const DEFAULT_RETENTION: TimeDelta = TimeDelta::hours(1);
+
+/// How far ahead of the current time a reading may be by default, allowing for a sensor whose
+/// clock is slightly fast.
+pub const DEFAULT_MAX_LEAD: TimeDelta = TimeDelta::minutes(5);
+
+/// The most buckets [`TimeSeries::resample`] returns.
+pub const MAX_BUCKETS: usize = 10_000;
+
+/// Errors that may occur while working with a [`TimeSeries`].
+#[derive(Debug, thiserror::Error, Clone, Copy, PartialEq, Eq)]
+pub enum SeriesError {
+    #[error("reading at {at} is more than {max_lead} ahead of the current time, {now}")]
+    TooFarAhead {
+        at: DateTime<Utc>,
+        now: DateTime<Utc>,
+        max_lead: TimeDelta,
+    },
+
+    #[error("resampling into buckets of {width} would take {buckets} buckets")]
+    TooManyBuckets { width: TimeDelta, buckets: i64 },
+}
+
+/// A value and when it was read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    pub at: DateTime<Utc>,
+    pub value: f64,
+}
+
+/// How the readings in a bucket are combined into one value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    Mean,
+    Min,
+    Max,
+    /// The latest reading.
+    Last,
+}
+
+/// One fixed-width interval of a resampled series.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucket {
+    pub start: DateTime<Utc>,
+    /// The number of readings in the bucket.
+    pub count: usize,
+    /// The combined value, or `None` if there weren't any readings.
+    pub value: Option<f64>,
+}
+
+/// A period when no readings arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gap {
+    /// The last reading before the gap.
+    pub start: DateTime<Utc>,
+    /// The first reading after the gap, or `None` if the sensor is still offline.
+    pub end: Option<DateTime<Utc>>,
+}
+
+impl Gap {
+    pub fn is_ongoing(&self) -> bool {
+        self.end.is_none()
+    }
+
+    /// How long the sensor was offline, counting an ongoing gap up to `now`.
+    pub fn duration_at(&self, now: DateTime<Utc>) -> TimeDelta {
+        self.end.unwrap_or(now) - self.start
+    }
+}
+
+/// Readings in time order, kept for a limited time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeSeries {
+    retention: TimeDelta,
+    max_lead: TimeDelta,
+    readings: VecDeque<Reading>,
+}
+
+impl Default for TimeSeries {
+    fn default() -> Self {
+        Self::with_retention(DEFAULT_RETENTION)
+    }
+}
+
+impl TimeSeries {
+    /// Create a series which keeps readings taken within `retention` of the latest one.
+    pub fn with_retention(retention: TimeDelta) -> Self {
+        Self {
+            retention,
+            max_lead: DEFAULT_MAX_LEAD,
+            readings: VecDeque::new(),
+        }
+    }
+
+    /// Reject readings more than `max_lead` ahead of the current time, instead of
+    /// [`DEFAULT_MAX_LEAD`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_lead` is negative.
+    pub fn with_max_lead(mut self, max_lead: TimeDelta) -> Self {
+        assert!(
+            max_lead >= TimeDelta::zero(),
+            "max lead must not be negative, got {max_lead}"
+        );
+        self.max_lead = max_lead;
+        self
+    }
+
+    pub fn retention(&self) -> TimeDelta {
+        self.retention
+    }
+
+    pub fn max_lead(&self) -> TimeDelta {
+        self.max_lead
+    }
+
+    /// Add a reading, dropping any which are now older than the retention period. Readings
+    /// which arrive late are put in time order.
+    ///
+    /// A reading more than the max lead ahead of the current time is rejected, and the series
+    /// isn't changed. Readings after any gap up to now are accepted, so a sensor which was
+    /// offline for a long time picks up again when it comes back.
+    pub fn push(&mut self, value: f64, at: DateTime<Utc>) -> Result<(), SeriesError> {
+        self.push_at(value, at, Utc::now())
+    }
+
+    /// Add a reading, treating `now` as the current time.
+    pub fn push_at(
+        &mut self,
+        value: f64,
+        at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<(), SeriesError> {
+        if at - now > self.max_lead {
+            return Err(SeriesError::TooFarAhead {
+                at,
+                now,
+                max_lead: self.max_lead,
+            });
+        }
+        let index = self.readings.partition_point(|reading| reading.at <= at);
+        self.readings.insert(index, Reading { at, value });
+
+        let cutoff = self.readings[self.readings.len() - 1].at - self.retention;
+        while self
+            .readings
+            .front()
+            .is_some_and(|oldest| oldest.at < cutoff)
+        {
+            self.readings.pop_front();
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.readings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.readings.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Reading> {
+        self.readings.iter()
+    }
+
+    pub fn latest(&self) -> Option<&Reading> {
+        self.readings.back()
+    }
+
+    /// Combine the readings into buckets of `width`, from the bucket holding the first reading
+    /// to the one holding the last. Buckets are aligned to the Unix epoch, so hourly buckets
+    /// start on the hour, and buckets without readings are included with no value.
+    ///
+    /// Fails with [`SeriesError::TooManyBuckets`] if that would take more than [`MAX_BUCKETS`]
+    /// buckets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `width` isn't at least a millisecond.
+    pub fn resample(
+        &self,
+        width: TimeDelta,
+        aggregation: Aggregation,
+    ) -> Result<Vec<Bucket>, SeriesError> {
+        let width_ms = width.num_milliseconds();
+        assert!(width_ms > 0, "bucket width must be positive, got {width}");
+        let bucket_of = |at: DateTime<Utc>| at.timestamp_millis().div_euclid(width_ms);
+
+        let (Some(first), Some(last)) = (self.readings.front(), self.readings.back()) else {
+            return Ok(Vec::new());
+        };
+        let (first_bucket, last_bucket) = (bucket_of(first.at), bucket_of(last.at));
+        let count = last_bucket - first_bucket + 1;
+        if count > MAX_BUCKETS as i64 {
+            return Err(SeriesError::TooManyBuckets {
+                width,
+                buckets: count,
+            });
+        }
+        let mut buckets: Vec<_> = (first_bucket..=last_bucket)
+            .map(|bucket| Bucket {
+                start: DateTime::from_timestamp_millis(bucket * width_ms)
+                    .expect("bucket starts are between readings"),
+                count: 0,
+                value: None,
+            })
+            .collect();
+
+        for reading in &self.readings {
+            let bucket = &mut buckets[(bucket_of(reading.at) - first_bucket) as usize];
+            bucket.count += 1;
+            let value = reading.value;
+            bucket.value = Some(match (aggregation, bucket.value) {
+                (_, None) | (Aggregation::Last, Some(_)) => value,
+                // a running sum until every reading is in
+                (Aggregation::Mean, Some(sum)) => sum + value,
+                (Aggregation::Min, Some(min)) => min.min(value),
+                (Aggregation::Max, Some(max)) => max.max(value),
+            });
+        }
+        if aggregation == Aggregation::Mean {
+            for bucket in &mut buckets {
+                bucket.value = bucket.value.map(|sum| sum / bucket.count as f64);
+            }
+        }
+        Ok(buckets)
+    }
+
+    /// The periods, as of `now`, when no reading arrived within `expected` of the one before.
+    /// If the latest reading is more than `expected` ago, the last gap is still ongoing.
+    pub fn gaps_at(&self, expected: TimeDelta, now: DateTime<Utc>) -> Vec<Gap> {
+        let mut gaps: Vec<_> = self
+            .readings
+            .iter()
+            .zip(self.readings.iter().skip(1))
+            .filter(|(before, after)| after.at - before.at > expected)
+            .map(|(before, after)| Gap {
+                start: before.at,
+                end: Some(after.at),
+            })
+            .collect();
+        if let Some(latest) = self.latest().filter(|latest| now - latest.at > expected) {
+            gaps.push(Gap {
+                start: latest.at,
+                end: None,
+            });
+        }
+        gaps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        format!("2024-06-01T{time}Z").parse().unwrap()
+    }
+
+    fn series(readings: &[(&str, f64)]) -> TimeSeries {
+        let mut series = TimeSeries::with_retention(TimeDelta::hours(6));
+        for &(time, value) in readings {
+            series.push(value, at(time)).unwrap();
+        }
+        series
+    }
+
+    #[test]
+    fn resamples_into_aligned_buckets() {
+        let series = series(&[
+            ("10:05:00", 20.0),
+            ("10:40:00", 22.0),
+            ("10:20:00", 24.0),
+            ("12:10:00", 30.0),
+        ]);
+        let values = |aggregation| {
+            let buckets = series.resample(TimeDelta::hours(1), aggregation).unwrap();
+            buckets
+                .iter()
+                .map(|bucket| bucket.value)
+                .collect::<Vec<_>>()
+        };
+
+        let hourly = series
+            .resample(TimeDelta::hours(1), Aggregation::Mean)
+            .unwrap();
+        let starts: Vec<_> = hourly.iter().map(|bucket| bucket.start).collect();
+        assert_eq!(starts, [at("10:00:00"), at("11:00:00"), at("12:00:00")]);
+        assert_eq!(hourly[0].count, 3);
+        assert_eq!(hourly[1].count, 0);
+
+        assert_eq!(values(Aggregation::Mean), [Some(22.0), None, Some(30.0)]);
+        assert_eq!(values(Aggregation::Min), [Some(20.0), None, Some(30.0)]);
+        assert_eq!(values(Aggregation::Max), [Some(24.0), None, Some(30.0)]);
+        // the late reading at 10:20 doesn't count as the last one
+        assert_eq!(values(Aggregation::Last), [Some(22.0), None, Some(30.0)]);
+        assert!(TimeSeries::default()
+            .resample(TimeDelta::minutes(1), Aggregation::Mean)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn finds_when_the_sensor_was_offline() {
+        let series = series(&[
+            ("10:00:00", 20.0),
+            ("10:01:00", 20.0),
+            ("10:02:30", 20.0),
+            ("10:15:00", 20.0),
+            ("10:16:00", 20.0),
+        ]);
+        let minute = TimeDelta::minutes(1);
+
+        let gaps = series.gaps_at(minute, at("10:16:30"));
+        let expected = [
+            Gap {
+                start: at("10:01:00"),
+                end: Some(at("10:02:30")),
+            },
+            Gap {
+                start: at("10:02:30"),
+                end: Some(at("10:15:00")),
+            },
+        ];
+        assert_eq!(gaps, expected);
+        assert_eq!(gaps[1].duration_at(at("10:16:30")), TimeDelta::seconds(750));
+
+        let gaps = series.gaps_at(TimeDelta::minutes(5), at("10:30:00"));
+        assert_eq!(gaps.len(), 2);
+        assert!(gaps[1].is_ongoing());
+        assert_eq!(gaps[1].duration_at(at("10:30:00")), TimeDelta::minutes(14));
+    }
+
+    #[test]
+    fn keeps_readings_for_the_retention_period() {
+        let mut series = TimeSeries::with_retention(TimeDelta::hours(1));
+        for minute in 0..=90 {
+            series
+                .push(minute as f64, at("00:00:00") + TimeDelta::minutes(minute))
+                .unwrap();
+        }
+        assert_eq!(series.len(), 61);
+        assert_eq!(series.iter().next().unwrap().value, 30.0);
+        assert_eq!(series.latest().unwrap().value, 90.0);
+    }
+
+    #[test]
+    fn rejects_readings_from_the_future() {
+        let mut series = series(&[("10:00:00", 20.0), ("10:01:00", 21.0)]);
+        let now = at("10:02:00");
+        let next_year = now + TimeDelta::days(365);
+        assert_eq!(
+            series.push_at(22.0, next_year, now),
+            Err(SeriesError::TooFarAhead {
+                at: next_year,
+                now,
+                max_lead: DEFAULT_MAX_LEAD,
+            })
+        );
+        assert_eq!(series.len(), 2);
+        series.push_at(22.0, at("10:04:00"), now).unwrap();
+
+        // after a long outage, readings are accepted again
+        let tomorrow = now + TimeDelta::days(1);
+        series.push_at(23.0, tomorrow, tomorrow).unwrap();
+        assert_eq!(series.len(), 1);
+    }
+
+    #[test]
+    fn wont_resample_into_too_many_buckets() {
+        let mut series = TimeSeries::with_retention(TimeDelta::days(30));
+        series.push(20.0, at("10:00:00")).unwrap();
+        series.push(21.0, at("10:10:00")).unwrap();
+        let minutes = series.resample(TimeDelta::minutes(1), Aggregation::Mean);
+        assert_eq!(minutes.unwrap().len(), 11);
+
+        let width = TimeDelta::milliseconds(1);
+        assert_eq!(
+            series.resample(width, Aggregation::Mean),
+            Err(SeriesError::TooManyBuckets {
+                width,
+                buckets: 600_001,
+            })
+        );
+    }
+}
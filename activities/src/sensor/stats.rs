@@ -0,0 +1,374 @@
+//! Streaming statistics which don't keep every reading.
+//!
+//! [`RunningStats`], [`Ewma`] and [`P2Quantile`] use a fixed amount of memory however many
+//! readings they see. A [`SlidingWindow`] keeps every reading it covers, so its memory is only
+//! fixed when it covers a number of readings rather than a span of time.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use std::collections::VecDeque;
+
+/// A summary of some readings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// The population standard deviation.
+    pub std_dev: f64,
+}
+
+/// Count, min, max, mean and standard deviation, updated one reading at a time.
+///
+/// The mean and variance use Welford's method, which stays accurate over millions of readings.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RunningStats {
+    count: u64,
+    mean: f64,
+    /// The sum of squared differences from the mean.
+    m2: f64,
+    min: f64,
+    max: f64,
+}
+
+impl RunningStats {
+    pub fn push(&mut self, value: f64) {
+        self.count += 1;
+        if self.count == 1 {
+            (self.min, self.max) = (value, value);
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The summary of every reading so far, or `None` if there haven't been any.
+    pub fn summary(&self) -> Option<Summary> {
+        (self.count > 0).then(|| Summary {
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean: self.mean,
+            std_dev: (self.m2 / self.count as f64).sqrt(),
+        })
+    }
+}
+
+impl FromIterator<f64> for RunningStats {
+    fn from_iter<I: IntoIterator<Item = f64>>(values: I) -> Self {
+        let mut stats = Self::default();
+        values.into_iter().for_each(|value| stats.push(value));
+        stats
+    }
+}
+
+/// An exponentially weighted moving average.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ewma {
+    alpha: f64,
+    value: Option<f64>,
+}
+
+impl Ewma {
+    /// Create an average where each reading has weight `alpha`, between 0 and 1. Higher values
+    /// follow changes more quickly.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `alpha` isn't in `(0, 1]`.
+    pub fn new(alpha: f64) -> Self {
+        assert!(
+            alpha > 0.0 && alpha <= 1.0,
+            "EWMA weight must be in (0, 1], got {alpha}"
+        );
+        Self { alpha, value: None }
+    }
+
+    pub fn push(&mut self, value: f64) {
+        self.value = Some(match self.value {
+            Some(average) => average + self.alpha * (value - average),
+            None => value,
+        });
+    }
+
+    /// The current average, or `None` if there haven't been any readings.
+    pub fn value(&self) -> Option<f64> {
+        self.value
+    }
+}
+
+/// An estimate of a quantile using the P² algorithm, which tracks five markers instead of
+/// keeping the readings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct P2Quantile {
+    p: f64,
+    count: usize,
+    /// Marker heights. Until there are five readings, the readings themselves.
+    heights: [f64; 5],
+    positions: [f64; 5],
+    desired: [f64; 5],
+    increments: [f64; 5],
+}
+
+impl P2Quantile {
+    /// Track the quantile `p`, so 0.95 for the 95th percentile.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `p` isn't between 0 and 1.
+    pub fn new(p: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&p),
+            "quantile must be in [0, 1], got {p}"
+        );
+        Self {
+            p,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    /// The quantile being tracked.
+    pub fn quantile(&self) -> f64 {
+        self.p
+    }
+
+    pub fn push(&mut self, value: f64) {
+        if self.count < 5 {
+            self.heights[self.count] = value;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_by(f64::total_cmp);
+            }
+            return;
+        }
+        self.count += 1;
+
+        let h = &mut self.heights;
+        let cell = if value < h[0] {
+            h[0] = value;
+            0
+        } else if value >= h[4] {
+            h[4] = value;
+            3
+        } else {
+            (0..4).rfind(|&i| h[i] <= value).unwrap_or(0)
+        };
+        for position in &mut self.positions[cell + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(self.increments) {
+            *desired += increment;
+        }
+
+        for i in 1..4 {
+            let drift = self.desired[i] - self.positions[i];
+            let n = &self.positions;
+            if (drift >= 1.0 && n[i + 1] - n[i] > 1.0) || (drift <= -1.0 && n[i - 1] - n[i] < -1.0)
+            {
+                let d = drift.signum();
+                let parabolic = self.parabolic(i, d);
+                self.heights[i] =
+                    if self.heights[i - 1] < parabolic && parabolic < self.heights[i + 1] {
+                        parabolic
+                    } else {
+                        self.linear(i, d)
+                    };
+                self.positions[i] += d;
+            }
+        }
+    }
+
+    fn parabolic(&self, i: usize, d: f64) -> f64 {
+        let (q, n) = (&self.heights, &self.positions);
+        q[i] + d / (n[i + 1] - n[i - 1])
+            * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]))
+    }
+
+    fn linear(&self, i: usize, d: f64) -> f64 {
+        let j = if d > 0.0 { i + 1 } else { i - 1 };
+        let (q, n) = (&self.heights, &self.positions);
+        q[i] + d * (q[j] - q[i]) / (n[j] - n[i])
+    }
+
+    /// The estimated quantile, or `None` if there haven't been any readings. It's exact until
+    /// there have been five readings.
+    pub fn estimate(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            1..=4 => {
+                let mut values = self.heights[..self.count].to_vec();
+                values.sort_by(f64::total_cmp);
+                let rank = (self.p * (values.len() - 1) as f64).round() as usize;
+                Some(values[rank])
+            }
+            _ => Some(self.heights[2]),
+        }
+    }
+}
+
+/// How much a [`SlidingWindow`] covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowSize {
+    /// The latest readings.
+    Readings(usize),
+    /// Readings taken within this long of the latest one.
+    ///
+    /// Every reading in the span is stored, and [`SlidingWindow::summary_at`] scans them all,
+    /// so memory and time grow with how often readings arrive: at one a second, an hour's span
+    /// holds 3600 readings.
+    Span(TimeDelta),
+}
+
+/// The most recent readings, by count or by age.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlidingWindow {
+    size: WindowSize,
+    readings: VecDeque<(DateTime<Utc>, f64)>,
+}
+
+impl SlidingWindow {
+    pub fn new(size: WindowSize) -> Self {
+        Self {
+            size,
+            readings: VecDeque::new(),
+        }
+    }
+
+    pub fn size(&self) -> WindowSize {
+        self.size
+    }
+
+    /// Add a reading taken at `at`, dropping any which fall out of the window. Readings must
+    /// arrive in time order.
+    pub fn push(&mut self, value: f64, at: DateTime<Utc>) {
+        self.readings.push_back((at, value));
+        match self.size {
+            WindowSize::Readings(count) => {
+                while self.readings.len() > count {
+                    self.readings.pop_front();
+                }
+            }
+            WindowSize::Span(span) => self.expire(at - span),
+        }
+    }
+
+    /// Drop readings taken at or before `cutoff`.
+    fn expire(&mut self, cutoff: DateTime<Utc>) {
+        while self.readings.front().is_some_and(|(at, _)| *at <= cutoff) {
+            self.readings.pop_front();
+        }
+    }
+
+    /// The summary of the readings in the window as of `now`. A window by span only covers
+    /// readings taken within the span before `now`.
+    ///
+    /// This scans every reading in the window.
+    pub fn summary_at(&self, now: DateTime<Utc>) -> Option<Summary> {
+        let values = self.readings.iter().filter(|(at, _)| match self.size {
+            WindowSize::Readings(_) => true,
+            WindowSize::Span(span) => *at > now - span,
+        });
+        values
+            .map(|(_, value)| *value)
+            .collect::<RunningStats>()
+            .summary()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A deterministic shuffle of `0..n`.
+    fn shuffled(n: u64) -> Vec<f64> {
+        let mut values: Vec<u64> = (0..n).collect();
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        for i in (1..values.len()).rev() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            values.swap(i, (state % (i as u64 + 1)) as usize);
+        }
+        values.into_iter().map(|v| v as f64).collect()
+    }
+
+    #[test]
+    fn running_stats_match_the_textbook_formulas() {
+        let stats: RunningStats = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]
+            .into_iter()
+            .collect();
+        let summary = stats.summary().unwrap();
+        assert_eq!(summary.count, 8);
+        assert_eq!((summary.min, summary.max), (2.0, 9.0));
+        assert!((summary.mean - 5.0).abs() < 1e-12);
+        assert!((summary.std_dev - 2.0).abs() < 1e-12);
+        assert_eq!(RunningStats::default().summary(), None);
+
+        // a large offset would wreck a naive sum of squares
+        let stats: RunningStats = (0..1000).map(|i| 1e9 + (i % 2) as f64).collect();
+        assert!((stats.summary().unwrap().std_dev - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ewma_follows_recent_readings() {
+        let mut ewma = Ewma::new(0.5);
+        assert_eq!(ewma.value(), None);
+        for value in [10.0, 20.0, 20.0] {
+            ewma.push(value);
+        }
+        assert_eq!(ewma.value(), Some(17.5));
+    }
+
+    #[test]
+    fn p2_estimates_percentiles() {
+        let mut p50 = P2Quantile::new(0.5);
+        let mut p95 = P2Quantile::new(0.95);
+        assert_eq!(p50.estimate(), None);
+        for value in shuffled(10_000) {
+            p50.push(value);
+            p95.push(value);
+        }
+        let p50 = p50.estimate().unwrap();
+        let p95 = p95.estimate().unwrap();
+        assert!((p50 - 5000.0).abs() < 100.0, "p50 was {p50}");
+        assert!((p95 - 9500.0).abs() < 100.0, "p95 was {p95}");
+
+        let mut few = P2Quantile::new(0.5);
+        for value in [3.0, 1.0, 2.0] {
+            few.push(value);
+        }
+        assert_eq!(few.estimate(), Some(2.0));
+    }
+
+    #[test]
+    fn sliding_windows() {
+        let start: DateTime<Utc> = "2024-06-01T00:00:00Z".parse().unwrap();
+        let seconds = |s: i64| start + TimeDelta::seconds(s);
+
+        let mut last_three = SlidingWindow::new(WindowSize::Readings(3));
+        let mut last_minute = SlidingWindow::new(WindowSize::Span(TimeDelta::minutes(1)));
+        for (i, value) in [1.0, 2.0, 3.0, 4.0, 5.0].into_iter().enumerate() {
+            last_three.push(value, seconds(i as i64 * 20));
+            last_minute.push(value, seconds(i as i64 * 20));
+        }
+
+        let summary = last_three.summary_at(seconds(80)).unwrap();
+        assert_eq!((summary.count, summary.mean), (3, 4.0));
+        // readings at 40, 60 and 80 seconds are within a minute of 80
+        let summary = last_minute.summary_at(seconds(80)).unwrap();
+        assert_eq!((summary.min, summary.max), (3.0, 5.0));
+        assert_eq!(last_minute.summary_at(seconds(150)), None);
+    }
+}
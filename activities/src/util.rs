@@ -0,0 +1,4 @@
+//! Helpers shared between the modules of this crate.
+
+pub(crate) mod csv;
+pub(crate) mod fs;
@@ -0,0 +1,83 @@
+//! Reading and writing CSV records, shared by the CSV file formats in this crate.
+//!
+//! Fields containing commas, quotes or line breaks are quoted, with quotes inside them doubled.
+
+use std::borrow::Cow;
+
+/// Why CSV text couldn't be split into records. Records are numbered from 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub(crate) enum SyntaxError {
+    #[error("unexpected character in quoted field")]
+    StrayQuote { record: usize },
+
+    #[error("text ends in the middle of a quoted field")]
+    Unterminated { record: usize },
+}
+
+/// Quote a field if it contains characters that would otherwise break the record apart.
+pub(crate) fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Split CSV text into records of fields, honouring quoted fields.
+pub(crate) fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, SyntaxError> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    // true once the current field has been quoted, so `"a"b` can be rejected
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                c => field.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() && !quoted => {
+                in_quotes = true;
+                quoted = true;
+            }
+            ',' => {
+                record.push(std::mem::take(&mut field));
+                quoted = false;
+            }
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+                quoted = false;
+            }
+            c if quoted || c == '"' => {
+                return Err(SyntaxError::StrayQuote {
+                    record: records.len(),
+                });
+            }
+            c => field.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(SyntaxError::Unterminated {
+            record: records.len(),
+        });
+    }
+    if quoted || !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
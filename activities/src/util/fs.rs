@@ -0,0 +1,37 @@
+//! File system helpers.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Replace the file at `path` with `bytes`.
+///
+/// The bytes are written to a temporary file first so a crash never leaves a half-written file
+/// behind. The file and the rename are both flushed to disk before this returns, so callers can
+/// rely on the new contents surviving a power loss.
+pub(crate) fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp, path)?;
+    sync_parent_dir(path)
+}
+
+/// Flush the directory entry for `path` to disk, making a create or rename durable.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+/// Directories can't be opened for syncing on this platform, so renames are as durable as the
+/// file system makes them.
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}